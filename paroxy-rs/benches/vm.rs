@@ -0,0 +1,57 @@
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use paroxy::{testing, Chunk, VM};
+
+/// Brainfuck nesting five loops of eight iterations, wrapping the
+/// innermost cell around.
+const NESTED_LOOPS: &str = "++++++++[>++++++++[>++++++++[>++++++++[>++++++++[>+<-]<-]<-]<-]<-]";
+
+const HELLO_WORLD: &str = include_str!("../scripts/hello_world.bf");
+
+fn vm(chunk: Chunk) -> VM {
+    let mut vm = VM::with_output(chunk, Box::new(io::sink()));
+    vm.set_wrapping(true);
+    vm
+}
+
+/// Number of instructions a run of the chunk executes.
+fn instructions(chunk: Chunk) -> u64 {
+    let mut vm = vm(chunk);
+    vm.enable_opcode_counts();
+    vm.run().unwrap();
+
+    vm.opcode_counts().map_or(0, |counts| counts.total())
+}
+
+/// Measure runs of the chunk in executed instructions per second. The
+/// chunk is compiled outside of the measurement.
+fn bench_program(c: &mut Criterion, name: &str, compile: impl Fn() -> Chunk) {
+    let mut group = c.benchmark_group("vm");
+    group.throughput(Throughput::Elements(instructions(compile())));
+    group.bench_function(name, |b| {
+        b.iter_batched(
+            || vm(compile()),
+            |mut vm| vm.run().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn dispatch(c: &mut Criterion) {
+    bench_program(c, "bf_nested_loops", || testing::compile_bf(NESTED_LOOPS));
+    bench_program(c, "bf_hello_world", || testing::compile_bf(HELLO_WORLD));
+
+    // The same clear loops as written and as single instructions.
+    let clear = "{1}".to_owned() + &"+255[-]".repeat(64);
+    bench_program(c, "clear_loop", || testing::compile(&clear));
+    bench_program(c, "clear_loop_optimized", || testing::compile_optimized(&clear));
+
+    bench_program(c, "print_loop", || {
+        testing::compile("{6}'hello'>5+250[<5.5>5-]")
+    });
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paroxy::parser::compile;
+
+// Any source has to compile or fail with diagnostics, never panic or hang.
+fuzz_target!(|source: &str| {
+    let _ = compile(source);
+});
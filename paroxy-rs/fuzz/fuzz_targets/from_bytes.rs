@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paroxy::chunk::Chunk;
+
+// Any data has to load or fail with an error, never panic or hang.
+fuzz_target!(|bytes: &[u8]| {
+    let _ = Chunk::from_bytes(bytes);
+});
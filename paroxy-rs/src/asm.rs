@@ -0,0 +1,543 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{Display, Write},
+};
+
+use crate::{
+    chunk::{Chunk, Value},
+    debug::{self, jump_target},
+    opcode::OpCode,
+};
+
+/// Mnemonic of the opcode in assembly, the lowercase disassembly name
+/// without its `OP_` prefix.
+pub fn mnemonic(opcode: OpCode) -> String {
+    let name = debug::opcode_name(opcode);
+    name.strip_prefix("OP_")
+        .unwrap_or(name)
+        .to_ascii_lowercase()
+}
+
+/// Write the chunk as assembly: directives for the cell width and the
+/// constant pool, then one instruction per line with jumps to labels.
+///
+/// Unlike the disassembly the listing carries everything the chunk holds,
+/// including the line table as `.line` directives, so the same listing
+/// always describes the same chunk. Bytes which do not form an instruction
+/// are written as `.byte` directives.
+pub fn to_asm(chunk: &Chunk) -> String {
+    let mut out = format!(".cell_width {}\n", chunk.cell_width);
+    for value in &chunk.constants {
+        let _ = match value {
+            Value::Int(value) => writeln!(out, ".constant {value}"),
+            Value::String(value) => writeln!(out, ".constant {value:?}"),
+        };
+    }
+
+    let mut starts = BTreeSet::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        match instruction_at(chunk, offset) {
+            Some(opcode) => {
+                starts.insert(offset);
+                offset += opcode.length();
+            }
+            None => offset += 1,
+        }
+    }
+
+    let labels: BTreeSet<usize> = starts
+        .iter()
+        .filter_map(|offset| jump_target(chunk, *offset))
+        .filter(|target| starts.contains(target))
+        .collect();
+
+    let mut line = None;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        if chunk.line_for(offset).is_some() && chunk.line_for(offset) != line {
+            line = chunk.line_for(offset);
+            let _ = writeln!(out, ".line {}", line.unwrap_or_default());
+        }
+
+        if labels.contains(&offset) {
+            let _ = writeln!(out, "{}:", label(offset));
+        }
+
+        let opcode = match instruction_at(chunk, offset) {
+            Some(opcode) => opcode,
+            None => {
+                let _ = writeln!(out, "    .byte {}", chunk.code[offset]);
+                offset += 1;
+                continue;
+            }
+        };
+
+        let _ = write!(out, "    {}", mnemonic(opcode));
+        let operands = &chunk.code[offset + 1..offset + opcode.length()];
+        let _ = match opcode {
+            OpCode::Constant | OpCode::Constant16 => {
+                let index = operands
+                    .iter()
+                    .fold(0usize, |index, byte| (index << 8) | *byte as usize);
+
+                match chunk.constants.get(index) {
+                    Some(Value::String(value)) => write!(out, " {index} ; {value:?}"),
+                    Some(value) => write!(out, " {index} ; {value}"),
+                    None => write!(out, " {index}"),
+                }
+            }
+            OpCode::JumpIfZero | OpCode::Loop | OpCode::JumpIfZeroWide | OpCode::LoopWide => {
+                match jump_target(chunk, offset).filter(|target| labels.contains(target)) {
+                    Some(target) => write!(out, " {}", label(target)),
+                    None => {
+                        let jump = operands
+                            .iter()
+                            .fold(0usize, |jump, byte| (jump << 8) | *byte as usize);
+                        write!(out, " {jump}")
+                    }
+                }
+            }
+            _ => match operands {
+                [byte] => write!(out, " {byte}"),
+                _ => Ok(()),
+            },
+        };
+        out.push('\n');
+
+        offset += opcode.length();
+    }
+
+    out
+}
+
+/// The complete instruction starting at the offset, if the byte there is
+/// an opcode whose operands fit into the code.
+fn instruction_at(chunk: &Chunk, offset: usize) -> Option<OpCode> {
+    let opcode = OpCode::try_from(chunk.code[offset]).ok()?;
+
+    (offset + opcode.length() <= chunk.code.len()).then_some(opcode)
+}
+
+fn label(offset: usize) -> String {
+    format!("L{offset:04}")
+}
+
+/// Failure to assemble a listing, at the given line of the listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Jump whose operand is written once every label is known.
+struct Fixup {
+    offset: usize,
+    opcode: OpCode,
+    label: String,
+    line: usize,
+}
+
+/// Build a chunk from assembly as written by [`to_asm`].
+///
+/// Every line holds a directive, a label or an instruction, and text after
+/// `;` is a comment. Constants must be declared with `.constant` before
+/// they are loaded. Jumps take a label, resolved into the offset the
+/// instruction expects, or a raw offset. The line table is only filled if
+/// `.line` precedes the first instruction.
+pub fn assemble(text: &str) -> Result<Chunk, AssembleError> {
+    let mut chunk = Chunk::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut fixups = vec![];
+
+    // Line of the listing every byte of code was assembled from.
+    let mut origins = vec![];
+    let mut line: Option<usize> = None;
+
+    for (index, source) in text.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: String| AssembleError {
+            line: number,
+            message,
+        };
+
+        let source = strip_comment(source).trim();
+        if source.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = source.strip_suffix(':') {
+            if !is_label(name) {
+                return Err(error(format!("invalid label '{name}'")));
+            }
+            if labels.insert(name, chunk.code.len()).is_some() {
+                return Err(error(format!("label '{name}' is defined twice")));
+            }
+            continue;
+        }
+
+        let (word, operand) = match source.split_once(char::is_whitespace) {
+            Some((word, operand)) => (word, Some(operand.trim())),
+            None => (source, None),
+        };
+
+        let bytes = match word {
+            ".cell_width" => {
+                chunk.cell_width = operand.unwrap_or_default().parse().map_err(error)?;
+                continue;
+            }
+            ".constant" => {
+                let value = match operand {
+                    Some(string) if string.starts_with('"') => {
+                        Value::String(unquote(string).map_err(error)?.into())
+                    }
+                    Some(int) => Value::Int(
+                        number_operand(Some(int), u32::MAX as usize).map_err(error)? as u32,
+                    ),
+                    None => return Err(error(String::from("expected a constant value"))),
+                };
+                chunk.add_constant(value);
+                continue;
+            }
+            ".line" => {
+                if line.is_none() && !chunk.code.is_empty() {
+                    return Err(error(String::from(
+                        "'.line' must precede the first instruction",
+                    )));
+                }
+                line = Some(number_operand(operand, usize::MAX).map_err(error)?);
+                continue;
+            }
+            ".byte" => vec![number_operand(operand, u8::MAX as usize).map_err(error)? as u8],
+            mnemonic => {
+                let opcode = lookup(mnemonic)
+                    .ok_or_else(|| error(format!("unknown mnemonic '{mnemonic}'")))?;
+                instruction(&chunk, opcode, operand, &mut |label| {
+                    fixups.push(Fixup {
+                        offset: chunk.code.len(),
+                        opcode,
+                        label: String::from(label),
+                        line: number,
+                    })
+                })
+                .map_err(error)?
+            }
+        };
+
+        for byte in bytes {
+            chunk.write_chunk(byte, line.unwrap_or_default());
+            origins.push(number);
+        }
+    }
+
+    for fixup in fixups {
+        let error = |message: String| AssembleError {
+            line: fixup.line,
+            message,
+        };
+
+        let target = *labels
+            .get(fixup.label.as_str())
+            .ok_or_else(|| error(format!("undefined label '{}'", fixup.label)))?;
+        let next = fixup.offset + fixup.opcode.length();
+        let jump = match fixup.opcode {
+            OpCode::JumpIfZero | OpCode::JumpIfZeroWide => target.checked_sub(next),
+            _ => next.checked_sub(target),
+        }
+        .ok_or_else(|| error(format!("label '{}' is in the wrong direction", fixup.label)))?;
+
+        let operand = jump_operand(fixup.opcode, jump).map_err(error)?;
+        chunk.code[fixup.offset + 1..next].copy_from_slice(&operand);
+    }
+
+    if line.is_none() {
+        chunk.strip();
+    }
+
+    chunk.verify().map_err(|error| AssembleError {
+        line: error
+            .offset()
+            .and_then(|offset| origins.get(offset).copied())
+            .unwrap_or_default(),
+        message: error.to_string(),
+    })?;
+
+    Ok(chunk)
+}
+
+/// Find an opcode by its assembly mnemonic.
+fn lookup(mnemonic: &str) -> Option<OpCode> {
+    OpCode::ALL
+        .iter()
+        .find(|opcode| self::mnemonic(**opcode) == mnemonic)
+        .copied()
+}
+
+/// Encode the instruction with its operand. Jumps to labels are encoded
+/// with a zero offset and handed to `fixup`.
+fn instruction(
+    chunk: &Chunk,
+    opcode: OpCode,
+    operand: Option<&str>,
+    fixup: &mut dyn FnMut(&str),
+) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![opcode as u8];
+
+    match opcode {
+        OpCode::Constant | OpCode::Constant16 => {
+            let max = match opcode {
+                OpCode::Constant => u8::MAX as usize,
+                _ => u16::MAX as usize,
+            };
+            let index = number_operand(operand, max)?;
+            if index >= chunk.constants.len() {
+                return Err(format!("constant {index} is not declared"));
+            }
+
+            bytes.extend(&(index as u16).to_be_bytes()[3 - opcode.length()..]);
+        }
+        OpCode::JumpIfZero | OpCode::Loop | OpCode::JumpIfZeroWide | OpCode::LoopWide => {
+            match operand {
+                Some(label) if is_label(label) => {
+                    fixup(label);
+                    bytes.resize(opcode.length(), 0);
+                }
+                operand => {
+                    let jump = number_operand(operand, usize::MAX)?;
+                    bytes.extend(jump_operand(opcode, jump)?);
+                }
+            }
+        }
+        _ if opcode.length() == 2 => {
+            bytes.push(number_operand(operand, u8::MAX as usize)? as u8);
+        }
+        _ => {
+            if let Some(operand) = operand {
+                return Err(format!("unexpected operand '{operand}'"));
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Big endian operand of the jump, as wide as the opcode takes.
+fn jump_operand(opcode: OpCode, jump: usize) -> Result<Vec<u8>, String> {
+    let width = opcode.length() - 1;
+    match width {
+        2 => u16::try_from(jump)
+            .map(|jump| jump.to_be_bytes().to_vec())
+            .ok(),
+        _ => u32::try_from(jump)
+            .map(|jump| jump.to_be_bytes().to_vec())
+            .ok(),
+    }
+    .ok_or_else(|| format!("jump of {jump} bytes is too far for {}", mnemonic(opcode)))
+}
+
+fn number_operand(operand: Option<&str>, max: usize) -> Result<usize, String> {
+    let operand = operand.ok_or_else(|| String::from("expected a number"))?;
+
+    match operand.parse::<usize>() {
+        Ok(number) if number <= max => Ok(number),
+        _ => Err(format!(
+            "expected a number between 0 and {max}, found '{operand}'"
+        )),
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The line without its comment, ignoring `;` in string literals.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+/// Decode a string literal written with the escapes of [`to_asm`].
+fn unquote(literal: &str) -> Result<String, String> {
+    let invalid = || format!("invalid string literal {literal}");
+    let inner = literal
+        .strip_prefix('"')
+        .and_then(|literal| literal.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        value.push(match chars.next().ok_or_else(invalid)? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            c @ ('\\' | '"' | '\'') => c,
+            'u' => {
+                let code: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                code.strip_prefix('{')
+                    .and_then(|code| u32::from_str_radix(code, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(invalid)?
+            }
+            _ => return Err(invalid()),
+        });
+    }
+
+    Ok(value)
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner, tape::CellWidth};
+
+    #[test]
+    fn should_keep_asm_format_stable() {
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new("{4}+3>\n[+]<\n'h;i'"), &mut chunk).compile());
+
+        assert_eq!(
+            to_asm(&chunk),
+            "\
+.cell_width 8
+.constant 4
+.constant \"h;i\"
+.line 1
+    constant 0 ; 4
+    define_tape
+    increment 3
+    shift_right
+.line 2
+L0006:
+    jump_if_zero L0013
+    increment_single
+    loop L0006
+L0013:
+    shift_left
+.line 3
+    constant 1 ; \"h;i\"
+    write_string
+    return
+"
+        );
+    }
+
+    #[test]
+    fn should_write_stray_bytes_and_stripped_chunks() {
+        let mut chunk = Chunk::new();
+        for byte in [OpCode::ShiftRight as u8, 0xfe, OpCode::Loop as u8, 0xff] {
+            chunk.write_chunk(byte, 1);
+        }
+        chunk.strip();
+
+        assert_eq!(
+            to_asm(&chunk),
+            ".cell_width 8\n    shift_right\n    .byte 254\n    .byte 19\n    .byte 255\n"
+        );
+    }
+
+    #[test]
+    fn should_assemble_its_own_listing_unchanged() {
+        let source = "{40}'Hello, \"world\"\n'$^+72[>+++[-]<-]>>>,*^<:\n[<]$dup$sort(0, 5, 1)";
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new(source), &mut chunk).compile());
+
+        let listing = to_asm(&chunk);
+        let assembled = assemble(&listing).unwrap();
+
+        assert_eq!(assembled.code, chunk.code);
+        assert_eq!(assembled.lines, chunk.lines);
+        assert_eq!(assembled.constants, chunk.constants);
+        assert_eq!(to_asm(&assembled), listing);
+
+        chunk.strip();
+        chunk.cell_width = CellWidth::Sixteen;
+        let stripped = assemble(&to_asm(&chunk)).unwrap();
+        assert!(stripped.lines.is_empty());
+        assert_eq!(stripped.cell_width, CellWidth::Sixteen);
+    }
+
+    #[test]
+    fn should_resolve_labels_into_jump_offsets() {
+        let chunk = assemble(
+            "\
+.constant 3 ; tape size
+.constant \"a;b\"
+    constant 0
+    define_tape
+    increment 2
+start:
+    jump_if_zero_wide end
+    decrement_single
+    loop start
+end:
+    constant 1
+    write_string
+    return
+",
+        )
+        .unwrap();
+
+        assert_eq!(jump_target(&chunk, 5), Some(14));
+        assert_eq!(jump_target(&chunk, 11), Some(5));
+        assert_eq!(chunk.constants[1], Value::String("a;b".into()));
+        assert!(chunk.lines.is_empty());
+    }
+
+    #[test]
+    fn should_report_line_of_assembly_errors() {
+        let error = |text| assemble(text).unwrap_err();
+
+        assert_eq!(
+            error("    return\n    nope"),
+            AssembleError {
+                line: 2,
+                message: String::from("unknown mnemonic 'nope'")
+            }
+        );
+        assert_eq!(error("\n    loop missing\n").line, 2);
+        assert_eq!(error("a:\na:").message, "label 'a' is defined twice");
+        assert_eq!(
+            error("    constant 0").message,
+            "constant 0 is not declared"
+        );
+        assert_eq!(error("    increment 256").line, 1);
+        assert_eq!(error("    return 1").message, "unexpected operand '1'");
+        assert_eq!(error("    return\n.line 1").line, 2);
+        assert_eq!(
+            error("a:\n    return\n    jump_if_zero a").message,
+            "label 'a' is in the wrong direction"
+        );
+        assert_eq!(
+            error("    jump_if_zero 9\n    return").message,
+            "jump at offset 0000 does not land on an instruction"
+        );
+    }
+}
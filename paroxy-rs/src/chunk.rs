@@ -3,25 +3,124 @@ use std::{fmt::Display, rc::Rc};
 use bincode::{DefaultOptions, Options};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::{
+    error::{Error, Result},
+    opcode::OpCode,
+    tape::CellWidth,
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub lines: Vec<usize>,
     pub constants: Vec<Value>,
+
+    /// Width of the cells of the tape the chunk runs on.
+    pub cell_width: CellWidth,
+
+    /// Hash of the source the chunk was compiled from, see [`source_hash`].
+    /// Serialized in the header.
+    #[serde(skip)]
+    pub source_hash: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Value {
     Int(u32),
     String(Rc<str>),
 }
 
+/// Bytes every serialized chunk starts with.
+pub const MAGIC: [u8; 4] = *b"PXB\0";
+
+/// Version of the serialized layout, written little endian after the
+/// magic. Increased whenever the header or chunk layout changes.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Metadata serialized in front of a chunk.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Version of the compiler which produced the chunk.
+    pub compiler: String,
+
+    /// Hash of the source the chunk was compiled from.
+    pub source_hash: Option<u64>,
+}
+
+/// Failure to load a chunk from binary data.
+#[derive(Debug)]
+pub enum ChunkError {
+    /// The data does not start with [`MAGIC`], so it is not a compiled chunk
+    /// or was produced before chunks had a magic.
+    BadMagic,
+
+    /// The data was serialized with another layout version.
+    VersionMismatch { found: u16 },
+
+    /// The data could not be decoded into a chunk.
+    Malformed(bincode::Error),
+
+    /// The decoded chunk is inconsistent.
+    Invalid(VerifyError),
+
+    /// The chunk was produced by another compiler version and could
+    /// not be decoded by this one.
+    Incompatible {
+        compiler: String,
+        error: bincode::Error,
+    },
+}
+
+/// Inconsistency found by [`Chunk::verify`]. Offsets are those of the
+/// offending instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The line table is neither stripped nor as long as the code.
+    LineTable { lines: usize, code: usize },
+
+    /// The byte is not an opcode.
+    UnknownOpcode { offset: usize, byte: u8 },
+
+    /// The code ends in the middle of the operands.
+    TruncatedOperands { offset: usize },
+
+    /// The operand indexes a constant missing from the pool.
+    MissingConstant { offset: usize, index: u16 },
+
+    /// The jump or loop continues outside the code or in the middle of
+    /// another instruction.
+    InvalidJump { offset: usize },
+}
+
+impl Header {
+    /// Header of chunks produced by this build.
+    pub fn current() -> Self {
+        Self {
+            compiler: String::from(env!("CARGO_PKG_VERSION")),
+            source_hash: None,
+        }
+    }
+}
+
+impl Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "compiler: paroxy-rs {}", self.compiler)?;
+        if let Some(hash) = self.source_hash {
+            write!(f, "\nsource hash: {hash:016x}")?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Chunk {
     pub fn new() -> Self {
         Self {
             code: vec![],
             lines: vec![],
             constants: vec![],
+            cell_width: CellWidth::Eight,
+            source_hash: None,
         }
     }
 
@@ -32,17 +131,289 @@ impl Chunk {
 
     pub fn add_constant(&mut self, value: Value) -> usize {
         self.constants.push(value);
-        return self.constants.len() - 1;
+        self.constants.len() - 1
+    }
+
+    /// Source line of the instruction at the given offset, unless the
+    /// line table was stripped.
+    pub fn line_for(&self, offset: usize) -> Option<usize> {
+        self.lines.get(offset).copied()
+    }
+
+    /// Drop the line table to make the serialized chunk smaller. Runtime
+    /// errors then only report instruction offsets.
+    pub fn strip(&mut self) {
+        self.lines = vec![];
+    }
+
+    pub fn constant_count(&self) -> usize {
+        self.constants.len()
+    }
+
+    /// Number of instructions in the code, not counting their operands.
+    pub fn instruction_count(&self) -> usize {
+        let mut count = 0;
+        let mut offset = 0;
+
+        while offset < self.code.len() {
+            offset += OpCode::try_from(self.code[offset]).map_or(1, |code| code.length());
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Hash identifying the program of the chunk: its code, constants and
+    /// cell width. Unlike the source hash it does not change when the line
+    /// table is stripped.
+    pub fn fingerprint(&self) -> u64 {
+        let program = options()
+            .serialize(&(&self.constants, self.cell_width))
+            .unwrap_or_default();
+
+        fnv1a(self.code.iter().copied().chain(program))
+    }
+
+    /// Serialize the chunk behind the header of this build.
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let header = Header {
+            source_hash: self.source_hash,
+            ..Header::current()
+        };
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        options()
+            .serialize_into(&mut bytes, &header)
+            .map_err(Error::Serialize)?;
+        options()
+            .serialize_into(&mut bytes, self)
+            .map_err(Error::Serialize)?;
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, ChunkError> {
+        let mut reader = bytes;
+        let header = Self::read_header(&mut reader)?;
+
+        // Limit reads to the data so garbage lengths cannot allocate more.
+        let limit = reader.len() as u64;
+        let mut chunk: Chunk =
+            options()
+                .with_limit(limit)
+                .deserialize(reader)
+                .map_err(|error| {
+                    if header.compiler == Header::current().compiler {
+                        ChunkError::Malformed(error)
+                    } else {
+                        ChunkError::Incompatible {
+                            compiler: header.compiler,
+                            error,
+                        }
+                    }
+                })?;
+
+        chunk.source_hash = header.source_hash;
+        chunk.verify().map_err(ChunkError::Invalid)?;
+
+        Ok(chunk)
+    }
+
+    /// Check that every instruction is complete, refers to an existing
+    /// constant and jumps to the start of an instruction, and that the
+    /// line table covers the code if present.
+    pub fn verify(&self) -> std::result::Result<(), VerifyError> {
+        if !self.lines.is_empty() && self.lines.len() != self.code.len() {
+            return Err(VerifyError::LineTable {
+                lines: self.lines.len(),
+                code: self.code.len(),
+            });
+        }
+
+        let mut instructions = vec![];
+        let mut starts = vec![false; self.code.len()];
+
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let opcode = match OpCode::try_from(self.code[offset]) {
+                Ok(opcode) => opcode,
+                Err(byte) => return Err(VerifyError::UnknownOpcode { offset, byte }),
+            };
+
+            if offset + opcode.length() > self.code.len() {
+                return Err(VerifyError::TruncatedOperands { offset });
+            }
+
+            instructions.push((offset, opcode));
+            starts[offset] = true;
+            offset += opcode.length();
+        }
+
+        for (offset, opcode) in instructions {
+            let operands = &self.code[offset + 1..offset + opcode.length()];
+            let next = offset + opcode.length();
+
+            match opcode {
+                OpCode::Constant | OpCode::Constant16 => {
+                    let index = match operands {
+                        [index] => *index as u16,
+                        _ => u16::from_be_bytes([operands[0], operands[1]]),
+                    };
+
+                    if index as usize >= self.constants.len() {
+                        return Err(VerifyError::MissingConstant { offset, index });
+                    }
+                }
+                OpCode::JumpIfZero | OpCode::Loop | OpCode::JumpIfZeroWide | OpCode::LoopWide => {
+                    let jump = operands
+                        .iter()
+                        .fold(0usize, |jump, byte| (jump << 8) | *byte as usize);
+                    let target = match opcode {
+                        OpCode::JumpIfZero | OpCode::JumpIfZeroWide => next.checked_add(jump),
+                        _ => next.checked_sub(jump),
+                    };
+
+                    if target.is_none_or(|target| starts.get(target) != Some(&true)) {
+                        return Err(VerifyError::InvalidJump { offset });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read only the header in front of serialized chunk data.
+    pub fn header(bytes: &[u8]) -> std::result::Result<Header, ChunkError> {
+        Self::read_header(&mut &bytes[..])
+    }
+
+    fn read_header(reader: &mut &[u8]) -> std::result::Result<Header, ChunkError> {
+        match reader.strip_prefix(&MAGIC) {
+            Some(rest) => *reader = rest,
+            None => return Err(ChunkError::BadMagic),
+        }
+
+        match reader {
+            [a, b, rest @ ..] => {
+                let found = u16::from_le_bytes([*a, *b]);
+                if found != FORMAT_VERSION {
+                    return Err(ChunkError::VersionMismatch { found });
+                }
+
+                *reader = rest;
+            }
+            _ => {
+                let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                return Err(ChunkError::Malformed(bincode::ErrorKind::Io(eof).into()));
+            }
+        }
+
+        // Limit reads to the data so garbage lengths cannot allocate more.
+        options()
+            .with_limit(reader.len() as u64)
+            .deserialize_from(reader)
+            .map_err(ChunkError::Malformed)
+    }
+}
+
+/// Hash of program source, stable across builds and platforms so stale
+/// chunks can be detected (64 bit FNV-1a).
+pub fn source_hash(source: &str) -> u64 {
+    fnv1a(source.bytes())
+}
+
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub(crate) fn options() -> impl Options {
+    DefaultOptions::new().with_varint_encoding()
+}
+
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkError::BadMagic => write!(f, "not a compiled paroxy chunk"),
+            ChunkError::VersionMismatch { found } => write!(
+                f,
+                "chunk format version {found} is not supported, expected {FORMAT_VERSION}"
+            ),
+            ChunkError::Malformed(error) => write!(f, "{error}"),
+            ChunkError::Invalid(error) => write!(f, "{error}"),
+            ChunkError::Incompatible { compiler, error } => {
+                write!(
+                    f,
+                    "{error} (this chunk was compiled by paroxy-rs {compiler})"
+                )
+            }
+        }
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::LineTable { lines, code } => {
+                write!(f, "line table has {lines} entries for {code} bytes of code")
+            }
+            VerifyError::UnknownOpcode { offset, byte } => {
+                write!(f, "unknown opcode {byte} at offset {offset:04}")
+            }
+            VerifyError::TruncatedOperands { offset } => {
+                write!(f, "truncated operands at offset {offset:04}")
+            }
+            VerifyError::MissingConstant { offset, index } => {
+                write!(f, "missing constant {index} at offset {offset:04}")
+            }
+            VerifyError::InvalidJump { offset } => {
+                write!(
+                    f,
+                    "jump at offset {offset:04} does not land on an instruction"
+                )
+            }
+        }
+    }
+}
+
+impl VerifyError {
+    /// Offset of the offending instruction, if the error has one.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            VerifyError::LineTable { .. } => None,
+            VerifyError::UnknownOpcode { offset, .. }
+            | VerifyError::TruncatedOperands { offset }
+            | VerifyError::MissingConstant { offset, .. }
+            | VerifyError::InvalidJump { offset } => Some(*offset),
+        }
     }
+}
 
-    pub fn as_bytes(&self) -> Result<Vec<u8>, Box<bincode::ErrorKind>> {
-        DefaultOptions::new().with_varint_encoding().serialize(self)
+impl std::error::Error for VerifyError {}
+
+impl std::error::Error for ChunkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChunkError::BadMagic | ChunkError::VersionMismatch { .. } | ChunkError::Invalid(_) => {
+                None
+            }
+            ChunkError::Malformed(error) => Some(error),
+            ChunkError::Incompatible { error, .. } => Some(error),
+        }
     }
+}
 
-    pub fn from_bytes(bytes: &Vec<u8>) -> Result<Self, Box<bincode::ErrorKind>> {
-        DefaultOptions::new()
-            .with_varint_encoding()
-            .deserialize(bytes)
+impl Value {
+    /// Name of the variant with an article, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "an integer",
+            Value::String(_) => "a string",
+        }
     }
 }
 
@@ -54,3 +425,281 @@ impl Display for Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_round_trip_bytes() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Int(30000));
+        chunk.write_chunk(0, 1);
+
+        let bytes = chunk.as_bytes().unwrap();
+        let loaded = Chunk::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.code, chunk.code);
+        assert_eq!(loaded.lines, chunk.lines);
+    }
+
+    #[test]
+    fn should_count_instructions_without_operands() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Int(30000));
+        for byte in [OpCode::Constant as u8, 0, OpCode::DefineTape as u8] {
+            chunk.write_chunk(byte, 1);
+        }
+        for byte in [OpCode::JumpIfZero as u8, 0, 1, OpCode::Return as u8] {
+            chunk.write_chunk(byte, 1);
+        }
+
+        assert_eq!(chunk.instruction_count(), 4);
+        assert_eq!(chunk.constant_count(), 1);
+    }
+
+    #[test]
+    fn should_shrink_when_stripped() {
+        let mut chunk = Chunk::new();
+        for offset in 0..64 {
+            chunk.write_chunk(OpCode::IncrementSingular as u8, 1000 + offset);
+        }
+
+        let full = chunk.as_bytes().unwrap();
+        chunk.strip();
+        let stripped = chunk.as_bytes().unwrap();
+
+        assert!(stripped.len() < full.len());
+        assert_eq!(Chunk::from_bytes(&stripped).unwrap().line_for(0), None);
+    }
+
+    #[test]
+    fn should_keep_fingerprint_when_stripped() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Int(30000));
+        chunk.write_chunk(OpCode::IncrementSingular as u8, 1);
+
+        let fingerprint = chunk.fingerprint();
+        chunk.strip();
+        assert_eq!(chunk.fingerprint(), fingerprint);
+
+        chunk.write_chunk(OpCode::IncrementSingular as u8, 2);
+        assert_ne!(chunk.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn should_record_compiler_version() {
+        let bytes = Chunk::new().as_bytes().unwrap();
+
+        assert_eq!(Chunk::header(&bytes).unwrap(), Header::current());
+    }
+
+    #[test]
+    fn should_record_source_hash() {
+        let mut chunk = Chunk::new();
+        chunk.source_hash = Some(source_hash("+>+"));
+
+        let bytes = chunk.as_bytes().unwrap();
+
+        assert_eq!(
+            Chunk::header(&bytes).unwrap().source_hash,
+            chunk.source_hash
+        );
+        assert_eq!(
+            Chunk::from_bytes(&bytes).unwrap().source_hash,
+            chunk.source_hash
+        );
+        assert_ne!(source_hash("+>+"), source_hash("+>-"));
+        assert_eq!(source_hash(""), 0xcbf29ce484222325);
+    }
+
+    #[test]
+    fn should_name_compiler_of_incompatible_chunk() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        let header = Header {
+            compiler: String::from("0.0.1"),
+            source_hash: None,
+        };
+        options().serialize_into(&mut bytes, &header).unwrap();
+        bytes.extend([0xff, 0xff]);
+
+        let error = Chunk::from_bytes(&bytes).unwrap_err();
+
+        assert!(matches!(error, ChunkError::Incompatible { .. }));
+        assert!(error
+            .to_string()
+            .ends_with("(this chunk was compiled by paroxy-rs 0.0.1)"));
+    }
+
+    #[test]
+    fn should_fail_to_deserialize_garbage() {
+        let result = Chunk::from_bytes(&[b'P', b'X', b'B', 0, 2, 0, 0xff, 0xff, 0xff]);
+
+        assert!(matches!(result, Err(ChunkError::Malformed(_))));
+        assert!(matches!(
+            Chunk::from_bytes(&MAGIC),
+            Err(ChunkError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn should_not_allocate_garbage_header_lengths() {
+        // A compiler name claiming to be 1 TiB long.
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        bytes.push(253);
+        bytes.extend((1u64 << 40).to_le_bytes());
+
+        assert!(matches!(
+            Chunk::from_bytes(&bytes),
+            Err(ChunkError::Malformed(_))
+        ));
+        assert!(matches!(
+            Chunk::header(&bytes),
+            Err(ChunkError::Malformed(_))
+        ));
+    }
+
+    fn chunk_of(code: &[u8], constants: usize) -> Chunk {
+        let mut chunk = Chunk::new();
+        for _ in 0..constants {
+            chunk.add_constant(Value::Int(1));
+        }
+        for byte in code {
+            chunk.write_chunk(*byte, 1);
+        }
+
+        chunk
+    }
+
+    fn verify_error(chunk: &Chunk) -> VerifyError {
+        match Chunk::from_bytes(&chunk.as_bytes().unwrap()) {
+            Err(ChunkError::Invalid(error)) => error,
+            result => panic!("expected an invalid chunk, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn should_verify_decoded_chunks() {
+        let constant = OpCode::Constant as u8;
+        let jump = OpCode::JumpIfZero as u8;
+        let repeat = OpCode::Loop as u8;
+        let ret = OpCode::Return as u8;
+
+        assert!(chunk_of(&[constant, 0, jump, 0, 0, ret], 1)
+            .verify()
+            .is_ok());
+        assert_eq!(
+            verify_error(&chunk_of(&[constant, 1, ret], 1)),
+            VerifyError::MissingConstant {
+                offset: 0,
+                index: 1
+            }
+        );
+        assert_eq!(
+            verify_error(&chunk_of(&[OpCode::Constant16 as u8, 1, 0, ret], 256)),
+            VerifyError::MissingConstant {
+                offset: 0,
+                index: 256
+            }
+        );
+        assert_eq!(
+            verify_error(&chunk_of(&[ret, constant], 1)),
+            VerifyError::TruncatedOperands { offset: 1 }
+        );
+        assert_eq!(
+            verify_error(&chunk_of(&[0xfe], 0)),
+            VerifyError::UnknownOpcode {
+                offset: 0,
+                byte: 0xfe
+            }
+        );
+
+        // Past the end, before the start and into the operand of a constant.
+        for code in [
+            [jump, 0, 9, ret, ret, ret],
+            [repeat, 0, 4, ret, ret, ret],
+            [jump, 0, 1, constant, 0, ret],
+        ] {
+            assert_eq!(
+                verify_error(&chunk_of(&code, 1)),
+                VerifyError::InvalidJump { offset: 0 }
+            );
+        }
+
+        let mut chunk = chunk_of(&[ret], 0);
+        chunk.lines.push(2);
+        assert_eq!(
+            verify_error(&chunk),
+            VerifyError::LineTable { lines: 2, code: 1 }
+        );
+    }
+
+    #[test]
+    fn should_fail_on_random_bytes_without_panicking() {
+        let valid = chunk_of(&[OpCode::Constant as u8, 0, OpCode::Return as u8], 1)
+            .as_bytes()
+            .unwrap();
+
+        // Xorshift, so every run feeds the same bytes.
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..2000 {
+            let mut bytes = valid.clone();
+            bytes.truncate(random() as usize % (valid.len() + 1));
+            for _ in 0..random() % 4 {
+                if !bytes.is_empty() {
+                    let index = random() as usize % bytes.len();
+                    bytes[index] = random() as u8;
+                }
+            }
+            bytes.extend((0..random() % 8).map(|_| random() as u8));
+
+            if let Ok(chunk) = Chunk::from_bytes(&bytes) {
+                assert!(chunk.verify().is_ok());
+            }
+        }
+
+        for length in 0..64 {
+            let bytes: Vec<u8> = (0..length).map(|_| random() as u8).collect();
+            assert!(Chunk::from_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn should_reject_chunk_without_magic() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(OpCode::Return as u8, 1);
+
+        // Chunks used to be serialized as the header followed by the chunk.
+        let mut old = vec![];
+        options()
+            .serialize_into(&mut old, &Header::current())
+            .unwrap();
+        options().serialize_into(&mut old, &chunk).unwrap();
+
+        assert!(matches!(Chunk::from_bytes(&old), Err(ChunkError::BadMagic)));
+        assert!(matches!(Chunk::header(&old), Err(ChunkError::BadMagic)));
+    }
+
+    #[test]
+    fn should_reject_other_format_version() {
+        let mut bytes = Chunk::new().as_bytes().unwrap();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&7u16.to_le_bytes());
+
+        let error = Chunk::from_bytes(&bytes).unwrap_err();
+
+        assert!(matches!(error, ChunkError::VersionMismatch { found: 7 }));
+        assert_eq!(
+            error.to_string(),
+            "chunk format version 7 is not supported, expected 2"
+        );
+    }
+}
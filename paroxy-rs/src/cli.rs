@@ -1,6 +1,15 @@
-use std::path::PathBuf;
+use std::{ascii, ops::Range, path::PathBuf, str::FromStr, time::Duration};
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use paroxy::{
+    format::Indent,
+    include::DEFAULT_MAX_SOURCE_SIZE,
+    intrinsic::{self, Intrinsics},
+    opcode::OpCode,
+    scanner,
+    tape::{Cell, CellWidth, DEFAULT_MAX_TAPE_SIZE},
+    vm::{EofMode, TapeRedefinition},
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -10,26 +19,188 @@ pub struct Cli {
     pub command: Commands,
 }
 
+// Parsed once per process, so the size of the run options does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Run a source string or file
     Run {
-        /// Program string or file.
+        /// Program string or file, '-' with '--file' to read stdin.
         #[clap(value_parser)]
         source: String,
 
+        /// Further programs to run alongside the first with '--interleave'.
+        #[clap(value_parser, requires = "interleave")]
+        others: Vec<String>,
+
+        /// Run the programs round robin on their own tapes, switching
+        /// whenever a program executes '$yield'.
+        #[clap(long, action)]
+        interleave: bool,
+
         /// The source is a file.
         #[clap(short, long, action)]
         file: bool,
 
-        /// The source is compiled binary data.
-        #[clap(short, long, action)]
+        /// The source is compiled binary data. Requires '--file'.
+        #[clap(short, long, action, requires = "file")]
         compiled: bool,
+
+        #[clap(flatten)]
+        source_args: SourceArgs,
+
+        /// Run an unchanged brainfuck program: treat every non brainfuck
+        /// character as a comment, wrap 8 bit cells around instead of
+        /// failing, read and print single bytes and leave the cell
+        /// unchanged once the input ended.
+        #[clap(
+            long,
+            action,
+            conflicts_with_all = &["bf-compat", "compiled", "cell-width", "eof", "growable"]
+        )]
+        bf_strict: bool,
+
+        /// Number of bits of every cell: 8, 16 or 32. Compiled chunks keep
+        /// the width they were compiled with.
+        #[clap(long, value_parser, default_value = "8", conflicts_with = "compiled")]
+        cell_width: CellWidth,
+
+        /// Print the disassembly of the compiled chunk to stdout before
+        /// running it.
+        #[clap(long, action, conflicts_with = "compiled")]
+        dump_bytecode: bool,
+
+        /// Print execution counts and time spent per loop, hottest first.
+        #[clap(long, action, conflicts_with = "interleave")]
+        profile_loops: bool,
+
+        /// Print how many times every opcode executed, most frequent first.
+        #[clap(long, action, conflicts_with = "interleave")]
+        profile: bool,
+
+        /// Print the share of instructions executed and the source lines
+        /// which never executed.
+        #[clap(long, action, conflicts_with = "interleave")]
+        coverage: bool,
+
+        /// Discard the program output and only print its length in bytes.
+        #[clap(long, action, conflicts_with = "interleave")]
+        count_only: bool,
+
+        /// Print a line break after the program output if it does not end
+        /// with one.
+        #[clap(long, action, conflicts_with_all = &["count-only", "interleave"])]
+        ensure_newline: bool,
+
+        /// Pause after executing the given number of instructions.
+        #[clap(long, value_parser, conflicts_with = "interleave")]
+        run_steps: Option<usize>,
+
+        /// Write the state of the vm to the given file when pausing after
+        /// `--run-steps`, so the run can continue with `--resume`.
+        #[clap(long, value_parser, value_name = "FILE", requires = "run-steps")]
+        snapshot_out: Option<PathBuf>,
+
+        /// Continue from a state written by `--snapshot-out` for the same
+        /// program.
+        #[clap(long, value_parser, value_name = "FILE", conflicts_with = "interleave")]
+        resume: Option<PathBuf>,
+
+        /// Fail when the program executes more than the given number of
+        /// instructions.
+        #[clap(long, value_parser, conflicts_with_all = &["run-steps", "trace", "interleave"])]
+        max_steps: Option<u64>,
+
+        /// Fail when the program runs for longer than the given duration,
+        /// such as 500ms, 2s or 1m. Seconds without a unit.
+        #[clap(
+            long,
+            value_parser = parse_duration,
+            conflicts_with_all = &["run-steps", "trace", "interleave"]
+        )]
+        timeout: Option<Duration>,
+
+        /// Forbid access to the environment of the process.
+        #[clap(long, action)]
+        sandbox: bool,
+
+        /// Halt as soon as the program prints the given byte.
+        #[clap(long, value_parser)]
+        stop_on_output: Option<u8>,
+
+        /// Print the tape to stderr once the program stops, as decimal
+        /// and ASCII. Takes an optional range of cells like `0..32` or
+        /// `16..`, defaulting to the cells the program touched.
+        #[clap(
+            long,
+            value_name = "RANGE",
+            value_parser = parse_cell_range,
+            min_values = 0,
+            require_equals = true,
+            conflicts_with = "interleave"
+        )]
+        dump_tape: Option<Option<Range<usize>>>,
+
+        /// Print the cell under the pointer to stderr once the program stops.
+        #[clap(long, value_enum, conflicts_with = "interleave")]
+        print_final_cell: Option<CellFormat>,
+
+        /// What a second tape definition does: zero the tape, keep the
+        /// cells both sizes share, or fail.
+        #[clap(long, value_enum, default_value = "preserve")]
+        redefine_tape: RedefineTape,
+
+        /// What reading input writes to the current cell once the input
+        /// ended: zero, nothing, or the largest cell value.
+        #[clap(long, value_enum, default_value = "zero")]
+        eof: Eof,
+
+        /// Storage of the tape: 'memory' or 'mmap:<path>' (experimental).
+        #[clap(
+            long,
+            value_parser,
+            default_value = "memory",
+            conflicts_with = "interleave"
+        )]
+        tape_backing: TapeBacking,
+
+        /// Grow the tape with zero cells whenever the pointer moves past
+        /// its end instead of failing.
+        #[clap(long, action, conflicts_with = "tape-backing")]
+        growable: bool,
+
+        /// Number of cells the growable tape stops growing at.
+        #[clap(long, value_parser, default_value_t = DEFAULT_MAX_TAPE_SIZE, requires = "growable")]
+        max_tape_size: usize,
+
+        /// Write the pointer, changed cells and output of every executed
+        /// instruction to the given file.
+        #[clap(long, value_parser, conflicts_with = "interleave")]
+        trace: Option<PathBuf>,
+
+        /// Print the offset, instruction, pointer and current cell of every
+        /// executed instruction to stderr.
+        #[clap(long, action, conflicts_with = "interleave")]
+        trace_execution: bool,
+
+        /// Warn if the compiled chunk was not compiled from the current
+        /// contents of the given source file.
+        #[clap(
+            long,
+            value_parser,
+            requires = "compiled",
+            conflicts_with = "interleave"
+        )]
+        check_source: Option<PathBuf>,
+
+        /// Fail instead of warning about a stale compiled chunk.
+        #[clap(long, action, requires = "check-source")]
+        strict: bool,
     },
 
     /// Compile given program into binary bundle
     Compile {
-        /// Program string or file.
+        /// Program string or file, '-' with '--file' to read stdin.
         #[clap(value_parser)]
         source: String,
 
@@ -40,5 +211,427 @@ pub enum Commands {
         /// The output path
         #[clap(value_parser)]
         out: Option<PathBuf>,
+
+        #[clap(flatten)]
+        source_args: SourceArgs,
+
+        /// Number of bits of every cell: 8, 16 or 32.
+        #[clap(long, value_parser, default_value = "8")]
+        cell_width: CellWidth,
+
+        /// Print the disassembly of the chunk to stdout as compiled, before
+        /// stripping.
+        #[clap(long, action)]
+        dump_bytecode: bool,
+
+        /// Kind of file to emit.
+        #[clap(long, alias = "format", value_enum, default_value = "pxb")]
+        emit: Emit,
+
+        /// Print the disassembly of the compiled chunk to stdout.
+        #[clap(long, action)]
+        show_disasm: bool,
+
+        /// Leave out the line table. Runtime errors then report instruction
+        /// offsets instead of lines.
+        #[clap(long, action)]
+        strip: bool,
+
+        /// Print the size of the compiled chunk to stderr.
+        #[clap(short, long, action)]
+        verbose: bool,
     },
+
+    /// Report every compile error of a program without compiling or
+    /// running it. Exits with 1 if there are any
+    Check {
+        /// Program string or file, '-' with '--file' to read stdin.
+        #[clap(value_parser)]
+        source: String,
+
+        /// The source is a file.
+        #[clap(short, long, action)]
+        file: bool,
+
+        #[clap(flatten)]
+        source_args: SourceArgs,
+
+        /// Number of bits of every cell: 8, 16 or 32.
+        #[clap(long, value_parser, default_value = "8")]
+        cell_width: CellWidth,
+    },
+
+    /// Run a program, or every program of a directory, and compare its
+    /// output with the expected output
+    Test {
+        /// Program file, or a directory of `.px` programs with their `.in`
+        /// and `.expected` files.
+        #[clap(value_parser)]
+        program: PathBuf,
+
+        /// File the program reads its input from. The input is empty if not given.
+        #[clap(short, long, value_parser)]
+        input: Option<PathBuf>,
+
+        /// File holding the expected output.
+        #[clap(short, long, value_parser)]
+        expect: Option<PathBuf>,
+
+        /// Fail a program running for longer than the given duration, such
+        /// as 500ms, 2s or 1m. Seconds without a unit.
+        #[clap(long, value_parser = parse_duration, default_value = "10s")]
+        timeout: Duration,
+    },
+
+    /// Assemble a textual assembly listing into a binary bundle
+    Assemble {
+        /// Assembly file, as written by 'compile --emit asm', or '-' to
+        /// read stdin.
+        #[clap(value_parser)]
+        source: PathBuf,
+
+        /// The output path. Defaults to the source path ending in '.pxb'.
+        #[clap(short, long, value_parser)]
+        out: Option<PathBuf>,
+
+        /// Refuse source files larger than the given number of bytes.
+        #[clap(long, value_parser, default_value_t = DEFAULT_MAX_SOURCE_SIZE)]
+        max_source_size: u64,
+    },
+
+    /// Translate a brainfuck program into paroxy source
+    Translate {
+        /// Brainfuck program file, or '-' to read stdin.
+        #[clap(value_parser)]
+        source: PathBuf,
+
+        /// The output path. Prints to stdout if not given.
+        #[clap(short, long, value_parser)]
+        out: Option<PathBuf>,
+
+        /// Refuse source files larger than the given number of bytes.
+        #[clap(long, value_parser, default_value_t = DEFAULT_MAX_SOURCE_SIZE)]
+        max_source_size: u64,
+    },
+
+    /// Reformat a paroxy source file, indenting loops
+    Format {
+        /// Program file, or '-' to read stdin. Includes are kept as they are.
+        #[clap(value_parser)]
+        source: PathBuf,
+
+        /// Indentation per loop level: a number of spaces or 'tab'.
+        #[clap(long, value_parser, default_value = "2")]
+        indent: Indent,
+
+        /// Character starting a comment until the end of the line.
+        #[clap(long, value_parser = parse_comment_char, default_value = ";")]
+        comment_char: char,
+
+        /// The output path. Prints to stdout if not given.
+        #[clap(short, long, value_parser)]
+        out: Option<PathBuf>,
+
+        /// Refuse source files larger than the given number of bytes.
+        #[clap(long, value_parser, default_value_t = DEFAULT_MAX_SOURCE_SIZE)]
+        max_source_size: u64,
+    },
+
+    /// Print the instructions of a compiled chunk
+    Disassemble {
+        /// Compiled chunk file, or a source file ending in '.px'.
+        #[clap(value_parser)]
+        file: PathBuf,
+
+        /// Only print the header of the chunk.
+        #[clap(long, action)]
+        header: bool,
+
+        /// Only print the constant pool of the chunk.
+        #[clap(long, action, conflicts_with = "header")]
+        constants: bool,
+
+        /// Refuse source files larger than the given number of bytes.
+        #[clap(long, value_parser, default_value_t = DEFAULT_MAX_SOURCE_SIZE)]
+        max_source_size: u64,
+    },
+
+    /// Describe an opcode
+    Explain {
+        /// Opcode name or byte value.
+        #[clap(value_parser = parse_opcode)]
+        opcode: OpCode,
+    },
+
+    /// Step through a program interactively
+    Debug {
+        /// Program string or file.
+        #[clap(value_parser)]
+        source: String,
+
+        /// The source is a file.
+        #[clap(short, long, action)]
+        file: bool,
+
+        /// The source is compiled binary data. Requires '--file'.
+        #[clap(short, long, action, requires = "file")]
+        compiled: bool,
+    },
+
+    /// Step through a trace written by 'run --trace' without running the
+    /// program again
+    Replay {
+        /// Trace file.
+        #[clap(value_parser)]
+        trace: PathBuf,
+    },
+
+    /// Interactively run lines of code on a persistent tape
+    Repl,
+
+    /// Print the include graph of a program file
+    Deps {
+        /// Program file.
+        #[clap(value_parser)]
+        source: PathBuf,
+
+        /// Output format of the graph.
+        #[clap(long, value_enum, default_value = "tree")]
+        format: GraphFormat,
+
+        /// Character starting a comment until the end of the line.
+        #[clap(long, value_parser = parse_comment_char, default_value = ";")]
+        comment_char: char,
+
+        /// Refuse source files larger than the given number of bytes.
+        #[clap(long, value_parser, default_value_t = DEFAULT_MAX_SOURCE_SIZE)]
+        max_source_size: u64,
+    },
+}
+
+/// Options reading and compiling the source, shared by the commands
+/// compiling a program.
+#[derive(Args, Debug)]
+pub struct SourceArgs {
+    /// Treat every non brainfuck character as a comment.
+    #[clap(long, action)]
+    pub bf_compat: bool,
+
+    /// Character starting a comment until the end of the line.
+    #[clap(long, value_parser = parse_comment_char, default_value = ";")]
+    pub comment_char: char,
+
+    /// Refuse source files larger than the given number of bytes.
+    #[clap(long, value_parser, default_value_t = DEFAULT_MAX_SOURCE_SIZE)]
+    pub max_source_size: u64,
+
+    /// Optimize the compiled instructions: clear and scan loops become
+    /// single instructions, runs of operators are folded and equal
+    /// constants are shared.
+    #[clap(short = 'O', long, action)]
+    pub optimize: bool,
+
+    /// Emit exactly one instruction for every source operator, which
+    /// is the default without '--optimize'.
+    #[clap(long, action, conflicts_with = "optimize")]
+    pub no_optimize: bool,
+
+    /// Only allow the given comma separated intrinsics.
+    #[clap(long, value_parser = intrinsic::check_name, use_value_delimiter = true)]
+    pub allow_intrinsics: Option<Vec<String>>,
+
+    /// Forbid the given comma separated intrinsics.
+    #[clap(
+        long,
+        value_parser = intrinsic::check_name,
+        use_value_delimiter = true,
+        conflicts_with = "allow-intrinsics"
+    )]
+    pub deny_intrinsics: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum GraphFormat {
+    /// Indented tree.
+    Tree,
+
+    /// Graphviz dot graph.
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum RedefineTape {
+    /// Zero every cell and move the pointer to the first cell.
+    Reset,
+
+    /// Resize the tape keeping the cells both sizes share.
+    Preserve,
+
+    /// Fail with a runtime error.
+    Error,
+}
+
+impl From<RedefineTape> for TapeRedefinition {
+    fn from(policy: RedefineTape) -> Self {
+        match policy {
+            RedefineTape::Reset => TapeRedefinition::Reset,
+            RedefineTape::Preserve => TapeRedefinition::Preserve,
+            RedefineTape::Error => TapeRedefinition::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Eof {
+    /// Write zero.
+    Zero,
+
+    /// Leave the cell unchanged.
+    Unchanged,
+
+    /// Write the largest cell value, 0xff with 8 bit cells.
+    Ff,
+}
+
+impl From<Eof> for EofMode {
+    fn from(mode: Eof) -> Self {
+        match mode {
+            Eof::Zero => EofMode::Zero,
+            Eof::Unchanged => EofMode::Unchanged,
+            Eof::Ff => EofMode::NegativeOne,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Emit {
+    /// Binary chunk.
+    Pxb,
+
+    /// Shell script running the embedded chunk.
+    Sh,
+
+    /// Textual assembly, one instruction per line.
+    Asm,
+
+    /// Nothing, only compile the source.
+    None,
+}
+
+/// How to print the value of a cell.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CellFormat {
+    /// Decimal number.
+    Dec,
+
+    /// Character, escaped unless printable ASCII.
+    Char,
+
+    /// Hexadecimal number.
+    Hex,
+}
+
+impl CellFormat {
+    pub fn format(&self, cell: Cell) -> String {
+        match self {
+            CellFormat::Dec => cell.to_string(),
+            CellFormat::Char => match u8::try_from(cell) {
+                Ok(byte) => format!("'{}'", ascii::escape_default(byte)),
+                Err(_) => match char::from_u32(cell) {
+                    Some(char) => format!("'{}'", char.escape_default()),
+                    None => format!("{cell:#x}"),
+                },
+            },
+            CellFormat::Hex => format!("{cell:#04x}"),
+        }
+    }
+}
+
+/// Where the cells of the tape are stored.
+#[derive(Debug, Clone)]
+pub enum TapeBacking {
+    Memory,
+    Mmap(PathBuf),
+}
+
+impl FromStr for TapeBacking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "memory" {
+            return Ok(TapeBacking::Memory);
+        }
+
+        match s.strip_prefix("mmap:") {
+            Some(path) if !path.is_empty() => Ok(TapeBacking::Mmap(PathBuf::from(path))),
+            _ => Err(format!(
+                "invalid tape backing '{s}', expected 'memory' or 'mmap:<path>'"
+            )),
+        }
+    }
+}
+
+/// The intrinsics left by the allow or deny list, every one if neither
+/// is given.
+pub fn intrinsics(allow: Option<Vec<String>>, deny: Option<Vec<String>>) -> Intrinsics {
+    match (allow, deny) {
+        (Some(allow), _) => Intrinsics::only(&allow),
+        (None, Some(deny)) => Intrinsics::except(&deny),
+        (None, None) => Intrinsics::all(),
+    }
+}
+
+fn parse_opcode(s: &str) -> Result<OpCode, String> {
+    OpCode::lookup(s).ok_or_else(|| format!("unknown opcode '{s}'"))
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s"),
+    };
+
+    let scale = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        _ => return Err(format!("unknown unit '{unit}', expected 'ms', 's' or 'm'")),
+    };
+
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|number| Duration::try_from_secs_f64(number * scale).ok())
+        .ok_or_else(|| format!("invalid duration '{s}'"))
+}
+
+fn parse_cell_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("invalid range '{s}', expected 'START..END'"))?;
+
+    let start = start
+        .parse::<usize>()
+        .map_err(|_| format!("invalid range start '{start}'"))?;
+    let end = match end {
+        "" => usize::MAX,
+        end => end
+            .parse::<usize>()
+            .map_err(|_| format!("invalid range end '{end}'"))?,
+    };
+
+    if start > end {
+        return Err(format!("range '{s}' ends before it starts"));
+    }
+
+    Ok(start..end)
+}
+
+fn parse_comment_char(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => scanner::check_comment_char(c),
+        _ => Err(String::from("expected a single character")),
+    }
 }
@@ -1,10 +1,9 @@
-use crate::{chunk::Chunk, opcode::OpCode};
+use std::fmt::Write;
 
-#[cfg(feature = "debug")]
-pub const DEBUG_PRINT_CODE: bool = true;
-
-#[cfg(not(feature = "debug"))]
-pub const DEBUG_PRINT_CODE: bool = false;
+use crate::{
+    chunk::{Chunk, Value},
+    opcode::OpCode,
+};
 
 #[cfg(feature = "debug")]
 pub const DEBUG_TRACE_EXECUTION: bool = true;
@@ -13,81 +12,399 @@ pub const DEBUG_TRACE_EXECUTION: bool = true;
 pub const DEBUG_TRACE_EXECUTION: bool = false;
 
 pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("== {name} ==");
+    print!("{}", disassemble_to_string(chunk, name));
+}
+
+/// Disassemble every instruction of the chunk into a listing headed by
+/// the given name.
+pub fn disassemble_to_string(chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {name} ==\n");
 
     let mut offset = 0;
     while offset < chunk.code.len() {
-        offset = disassemble_instruction(chunk, offset);
+        offset = write_instruction(&mut out, chunk, offset);
     }
+
+    out
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-    print!("{offset:04} ");
-    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
-        print!("   | ");
-    } else {
-        print!("{:4} ", chunk.lines[offset]);
+/// Disassemble the single instruction at the given offset, without a
+/// trailing line break.
+pub fn instruction_to_string(chunk: &Chunk, offset: usize) -> String {
+    let mut out = String::new();
+    write_instruction(&mut out, chunk, offset);
+
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Print the constant pool of the chunk with the type of every value.
+pub fn disassemble_constants(chunk: &Chunk, name: &str) {
+    print!("{}", constants_to_string(chunk, name));
+}
+
+/// List the constant pool of the chunk with the type of every value.
+pub fn constants_to_string(chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {name} constants ==\n");
+
+    for (index, value) in chunk.constants.iter().enumerate() {
+        let _ = writeln!(out, "{}", constant_entry(index, value));
+    }
+
+    out
+}
+
+fn constant_entry(index: usize, value: &Value) -> String {
+    match value {
+        Value::Int(value) => format!("{index:04} Int    {value}"),
+        Value::String(value) => format!("{index:04} String {value:?} ({} bytes)", value.len()),
     }
+}
+
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
+    let mut out = String::new();
+    let next = write_instruction(&mut out, chunk, offset);
+    print!("{out}");
+
+    next
+}
 
-    let instruction = match OpCode::try_from(chunk.code[offset]) {
+fn write_instruction(out: &mut String, chunk: &Chunk, offset: usize) -> usize {
+    let _ = write!(out, "{offset:04} ");
+    let _ = match chunk.line_for(offset) {
+        None => write!(out, "   - "),
+        Some(line) if offset > 0 && chunk.line_for(offset - 1) == Some(line) => {
+            write!(out, "   | ")
+        }
+        Some(line) => write!(out, "{line:4} "),
+    };
+
+    // The instruction pointer of a malformed chunk may run past its end.
+    let Some(byte) = chunk.code.get(offset) else {
+        let _ = writeln!(out, "<truncated>");
+        return offset + 1;
+    };
+
+    let instruction = match OpCode::try_from(*byte) {
         Ok(code) => code,
         Err(value) => {
-            println!("Unknown opcode {value}");
+            let _ = writeln!(out, "Unknown opcode {value}");
             return offset + 1;
         }
     };
 
+    // Malformed chunks may end in the middle of the operands.
+    if offset + instruction.length() > chunk.code.len() {
+        let _ = writeln!(out, "{instruction:?} <truncated>");
+        return chunk.code.len();
+    }
+
+    let name = opcode_name(instruction);
     match instruction {
-        OpCode::DefineTape => simple_instruction("OP_DEFINE_TAPE", offset),
-        OpCode::PointerValue => simple_instruction("OP_POINTER_VALUE", offset),
-        OpCode::SetPointer => simple_instruction("OP_SET_POINTER", offset),
-        OpCode::MoveLeft => simple_instruction("OP_MOVE_LEFT", offset),
-        OpCode::MoveRight => simple_instruction("OP_MOVE_RIGHT", offset),
-        OpCode::ShiftLeft => simple_instruction("OP_SHIFT_LEFT", offset),
-        OpCode::ShiftRight => simple_instruction("OP_SHIFT_RIGHT", offset),
-        OpCode::Increment => byte_instruction("OP_INCREMENT", chunk, offset),
-        OpCode::Decrement => byte_instruction("OP_DECREMENT", chunk, offset),
-        OpCode::IncrementSingular => simple_instruction("OP_INCREMENT_SINGLE", offset),
-        OpCode::DecrementSingular => simple_instruction("OP_DECREMENT_SINGLE", offset),
-        OpCode::Input => simple_instruction("OP_INPUT", offset),
-        OpCode::MultiInput => byte_instruction("OP_MULTI_INPUT", chunk, offset),
-        OpCode::Constant => constant_instruction("OP_CONSTANT", chunk, offset),
-        OpCode::Pop => simple_instruction("OP_POP", offset),
-        OpCode::WriteString => simple_instruction("OP_WRITE_STRING", offset),
-        OpCode::WriteCell => byte_instruction("OP_WRITE_CELL", chunk, offset),
-        OpCode::Print => simple_instruction("OP_PRINT", offset),
-        OpCode::PrintRange => simple_instruction("OP_PRINT_RANGE", offset),
-        OpCode::JumpIfZero => jump_instruction("OP_JUMP_IF_ZERO", 1, chunk, offset),
-        OpCode::Loop => jump_instruction("OP_LOOP", -1, chunk, offset),
-        OpCode::Return => simple_instruction("OP_RETURN", offset),
+        OpCode::Constant | OpCode::Constant16 => constant_instruction(out, name, chunk, offset),
+        OpCode::Increment
+        | OpCode::Decrement
+        | OpCode::MultiInput
+        | OpCode::WriteCell
+        | OpCode::SortRange => byte_instruction(out, name, chunk, offset),
+        OpCode::JumpIfZero | OpCode::Loop | OpCode::JumpIfZeroWide | OpCode::LoopWide => {
+            jump_instruction(out, name, chunk, offset)
+        }
+        _ => simple_instruction(out, name, offset),
+    }
+}
+
+/// Mnemonic of the opcode in listings and reports.
+pub fn opcode_name(opcode: OpCode) -> &'static str {
+    match opcode {
+        OpCode::DefineTape => "OP_DEFINE_TAPE",
+        OpCode::PointerValue => "OP_POINTER_VALUE",
+        OpCode::SetPointer => "OP_SET_POINTER",
+        OpCode::MoveLeft => "OP_MOVE_LEFT",
+        OpCode::MoveRight => "OP_MOVE_RIGHT",
+        OpCode::ShiftLeft => "OP_SHIFT_LEFT",
+        OpCode::ShiftRight => "OP_SHIFT_RIGHT",
+        OpCode::Increment => "OP_INCREMENT",
+        OpCode::Decrement => "OP_DECREMENT",
+        OpCode::IncrementSingular => "OP_INCREMENT_SINGLE",
+        OpCode::DecrementSingular => "OP_DECREMENT_SINGLE",
+        OpCode::Input => "OP_INPUT",
+        OpCode::MultiInput => "OP_MULTI_INPUT",
+        OpCode::Constant => "OP_CONSTANT",
+        OpCode::Constant16 => "OP_CONSTANT_16",
+        OpCode::Pop => "OP_POP",
+        OpCode::WriteString => "OP_WRITE_STRING",
+        OpCode::WriteCell => "OP_WRITE_CELL",
+        OpCode::Print => "OP_PRINT",
+        OpCode::PrintRange => "OP_PRINT_RANGE",
+        OpCode::PrintNumber => "OP_PRINT_NUMBER",
+        OpCode::JumpIfZero => "OP_JUMP_IF_ZERO",
+        OpCode::Loop => "OP_LOOP",
+        OpCode::JumpIfZeroWide => "OP_JUMP_IF_ZERO_WIDE",
+        OpCode::LoopWide => "OP_LOOP_WIDE",
+        OpCode::ClearCell => "OP_CLEAR_CELL",
+        OpCode::ScanRight => "OP_SCAN_RIGHT",
+        OpCode::ScanLeft => "OP_SCAN_LEFT",
+        OpCode::Call => "OP_CALL",
+        OpCode::Return => "OP_RETURN",
+        OpCode::ClearIf => "OP_CLEAR_IF",
+        OpCode::DupRight => "OP_DUP_RIGHT",
+        OpCode::Checksum => "OP_CHECKSUM",
+        OpCode::FindByte => "OP_FIND_BYTE",
+        OpCode::Halt => "OP_HALT",
+        OpCode::Transpose => "OP_TRANSPOSE",
+        OpCode::ReadEnv => "OP_READ_ENV",
+        OpCode::PeekInput => "OP_PEEK_INPUT",
+        OpCode::InputNumber => "OP_INPUT_NUMBER",
+        OpCode::ReverseRange => "OP_REVERSE_RANGE",
+        OpCode::Yield => "OP_YIELD",
+        OpCode::SortRange => "OP_SORT_RANGE",
+        OpCode::IncrementBy => "OP_INCREMENT_BY",
+        OpCode::DecrementBy => "OP_DECREMENT_BY",
+        OpCode::WriteValue => "OP_WRITE_VALUE",
     }
 }
 
-fn simple_instruction(name: &str, offset: usize) -> usize {
-    println!("{name}");
+fn simple_instruction(out: &mut String, name: &str, offset: usize) -> usize {
+    let _ = writeln!(out, "{name}");
 
     offset + 1
 }
 
-fn byte_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn byte_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
     let byte = chunk.code[offset + 1];
-    println!("{name:16} {byte:4}",);
+    let _ = writeln!(out, "{name:16} {byte:4}");
 
     offset + 2
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-    let constant = chunk.code[offset + 1];
-    let value = &chunk.constants[constant as usize];
-    println!("{name:16} {constant:4} {value}",);
+fn constant_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let wide = chunk.code[offset] == OpCode::Constant16 as u8;
+    let constant = match wide {
+        true => u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]),
+        false => chunk.code[offset + 1] as u16,
+    };
 
-    offset + 2
+    let _ = match chunk.constants.get(constant as usize) {
+        Some(value) => writeln!(out, "{name:16} {constant:4} {value}"),
+        None => writeln!(out, "{name:16} {constant:4} <missing>"),
+    };
+
+    offset + if wide { 3 } else { 2 }
 }
 
-fn jump_instruction(name: &str, sign: i32, chunk: &Chunk, offset: usize) -> usize {
-    let jump = ((chunk.code[offset + 1] as u16) << 8) | chunk.code[offset + 2] as u16;
-    let dest = offset as i32 + 3 + sign * jump as i32;
-    println!("{name:16} {offset:4} -> {dest}");
+fn jump_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let (jump, next) = jump_operand(chunk, offset).unwrap_or((0, offset + 3));
+
+    let _ = match jump_target(chunk, offset) {
+        Some(dest) => match chunk.lines.get(dest) {
+            Some(line) => writeln!(out, "{name:16} {jump:4} -> {dest:04} (line {line})"),
+            None => writeln!(out, "{name:16} {jump:4} -> {dest:04}"),
+        },
+        None => writeln!(out, "{name:16} {jump:4} -> invalid"),
+    };
 
-    offset + 3
+    next
+}
+
+/// The operand of the jump or loop instruction at the given offset, with
+/// the offset of the next instruction.
+fn jump_operand(chunk: &Chunk, offset: usize) -> Option<(usize, usize)> {
+    let instruction = OpCode::try_from(*chunk.code.get(offset)?).ok()?;
+    let next = offset + instruction.length();
+    let operand = chunk.code.get(offset + 1..next)?;
+
+    let jump = operand
+        .iter()
+        .fold(0usize, |jump, byte| (jump << 8) | *byte as usize);
+
+    Some((jump, next))
+}
+
+/// Absolute offset the jump or loop instruction at the given offset
+/// continues at. Returns `None` for other instructions and for jumps
+/// leaving the chunk.
+pub fn jump_target(chunk: &Chunk, offset: usize) -> Option<usize> {
+    let instruction = OpCode::try_from(*chunk.code.get(offset)?).ok()?;
+    let (jump, next) = jump_operand(chunk, offset)?;
+
+    let dest = match instruction {
+        OpCode::JumpIfZero | OpCode::JumpIfZeroWide => next.checked_add(jump)?,
+        OpCode::Loop | OpCode::LoopWide => next.checked_sub(jump)?,
+        _ => return None,
+    };
+
+    if dest > chunk.code.len() {
+        return None;
+    }
+
+    Some(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    #[test]
+    fn should_resolve_jump_targets() {
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new("{4}[\n+]"), &mut chunk).compile());
+
+        // Define tape, then the jump, the increment and the loop.
+        let jump = 3;
+        let increment = jump + 3;
+        let repeat = increment + 1;
+
+        assert_eq!(jump_target(&chunk, jump), Some(repeat + 3));
+        assert_eq!(jump_target(&chunk, repeat), Some(jump));
+        assert_eq!(jump_target(&chunk, increment), None);
+        assert_eq!(chunk.lines[repeat + 3], 2);
+    }
+
+    #[test]
+    fn should_disassemble_to_string() {
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new("{4}\n+"), &mut chunk).compile());
+
+        let listing = disassemble_to_string(&chunk, "main.px");
+        let lines: Vec<_> = listing.lines().collect();
+
+        assert_eq!(lines[0], "== main.px ==");
+        assert!(lines[1].starts_with("0000    1 OP_CONSTANT"));
+        assert!(lines
+            .iter()
+            .any(|line| line.ends_with("OP_INCREMENT_SINGLE")));
+        assert!(listing.ends_with("OP_RETURN\n"));
+    }
+
+    #[test]
+    fn should_keep_disassembly_format_stable() {
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new("{4}+3>\n[+]<\n'hi'"), &mut chunk).compile());
+
+        assert_eq!(
+            disassemble_to_string(&chunk, "golden.px"),
+            "\
+== golden.px ==
+0000    1 OP_CONSTANT         0 4
+0002    | OP_DEFINE_TAPE
+0003    | OP_INCREMENT        3
+0005    | OP_SHIFT_RIGHT
+0006    2 OP_JUMP_IF_ZERO     4 -> 0013 (line 2)
+0009    | OP_INCREMENT_SINGLE
+0010    | OP_LOOP             7 -> 0006 (line 2)
+0013    | OP_SHIFT_LEFT
+0014    3 OP_CONSTANT         1 hi
+0016    | OP_WRITE_STRING
+0017    | OP_RETURN
+"
+        );
+        assert_eq!(
+            constants_to_string(&chunk, "golden.px"),
+            "\
+== golden.px constants ==
+0000 Int    4
+0001 String \"hi\" (2 bytes)
+"
+        );
+    }
+
+    #[test]
+    fn should_disassemble_clear_loop_as_one_instruction() {
+        let mut chunk = Chunk::new();
+        let parser = Parser::new(Scanner::new("{1}+[-]"), &mut chunk);
+        assert!(parser.optimize(true).compile());
+
+        assert_eq!(
+            disassemble_to_string(&chunk, "clear.px"),
+            "\
+== clear.px ==
+0000    1 OP_CONSTANT         0 1
+0002    | OP_DEFINE_TAPE
+0003    | OP_INCREMENT_SINGLE
+0004    | OP_CLEAR_CELL
+0005    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    fn should_disassemble_scan_loops_as_one_instruction() {
+        let mut chunk = Chunk::new();
+        let parser = Parser::new(Scanner::new("{3}[>][<]"), &mut chunk);
+        assert!(parser.optimize(true).compile());
+
+        assert_eq!(
+            disassemble_to_string(&chunk, "scan.px"),
+            "\
+== scan.px ==
+0000    1 OP_CONSTANT         0 3
+0002    | OP_DEFINE_TAPE
+0003    | OP_SCAN_RIGHT
+0004    | OP_SCAN_LEFT
+0005    | OP_RETURN
+"
+        );
+
+        // Without optimizations the loops are kept.
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new("{3}[>][<]"), &mut chunk).compile());
+        assert!(!chunk.code.contains(&(OpCode::ScanRight as u8)));
+        assert!(!chunk.code.contains(&(OpCode::ScanLeft as u8)));
+    }
+
+    #[test]
+    fn should_decode_operands_of_every_opcode() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Int(7));
+
+        for opcode in OpCode::ALL {
+            let offset = chunk.code.len();
+            chunk.write_chunk(*opcode as u8, 1);
+            for _ in 1..opcode.length() {
+                chunk.write_chunk(0, 1);
+            }
+
+            let mut listing = String::new();
+            let next = write_instruction(&mut listing, &chunk, offset);
+
+            assert_eq!(next, offset + opcode.length(), "{opcode:?}");
+            assert!(!listing.contains("Unknown opcode"), "{opcode:?}");
+        }
+    }
+
+    #[test]
+    fn should_disassemble_malformed_chunk_without_panicking() {
+        let mut chunk = Chunk::new();
+        for byte in [OpCode::Constant as u8, 9, OpCode::Loop as u8, 0] {
+            chunk.write_chunk(byte, 1);
+        }
+
+        let listing = disassemble_to_string(&chunk, "bad");
+
+        assert!(listing.contains("OP_CONSTANT         9 <missing>"));
+        assert!(listing.ends_with("Loop <truncated>\n"));
+
+        let mut chunk = Chunk::new();
+        for byte in [OpCode::Constant16 as u8, 1, 2, OpCode::Return as u8] {
+            chunk.write_chunk(byte, 1);
+        }
+
+        let listing = disassemble_to_string(&chunk, "wide");
+        assert!(listing.contains("OP_CONSTANT_16    258 <missing>"));
+        assert!(listing.contains("OP_RETURN"));
+
+        // Tracing a vm whose instruction pointer ran past the end.
+        assert!(instruction_to_string(&chunk, 4).ends_with("<truncated>"));
+    }
+
+    #[test]
+    fn should_describe_constants() {
+        assert_eq!(constant_entry(3, &Value::Int(30000)), "0003 Int    30000");
+        assert_eq!(
+            constant_entry(12, &Value::String("Hé\n".into())),
+            "0012 String \"Hé\\n\" (4 bytes)"
+        );
+    }
 }
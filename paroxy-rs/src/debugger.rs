@@ -0,0 +1,252 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    error::Result,
+    vm::{Snapshot, StepResult, VM},
+};
+
+/// Name of the snapshot used when none is given.
+const DEFAULT_SNAPSHOT: &str = "default";
+
+pub const HELP: &str = "\
+Commands:
+  step [N], s [N]  execute the next N instructions (default 1)
+  continue, c      run until the program halts
+  where, w         show the next instruction and the pointer
+  get N            print the value of cell N
+  set N V          write V into cell N
+  snapshot [NAME]  save the tape, pointer and position
+  restore [NAME]   return to a saved snapshot
+  help, h          show this help
+  quit, q          stop debugging";
+
+/// Command understood by the [`Debugger`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Where,
+    Get(usize),
+    Set(usize, u32),
+    Snapshot(String),
+    Restore(String),
+    Help,
+    Quit,
+}
+
+/// Drives a vm one instruction at a time.
+pub struct Debugger {
+    vm: VM,
+    snapshots: HashMap<String, Snapshot>,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            snapshots: HashMap::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.vm.is_halted()
+    }
+
+    /// Execute the command and return the text to show to the user.
+    pub fn execute(&mut self, command: Command) -> Result<String> {
+        match command {
+            Command::Step(count) => {
+                for _ in 0..count {
+                    if !self.advance()? {
+                        break;
+                    }
+                }
+
+                Ok(self.location())
+            }
+            Command::Continue => {
+                while self.advance()? {}
+                Ok(self.location())
+            }
+            Command::Where => Ok(self.location()),
+            Command::Get(index) => match self.vm.cell(index) {
+                Some(value) => Ok(format!("[{index}] = {value}")),
+                None => Ok(format!("Cell {index} is outside the tape.")),
+            },
+            Command::Set(index, value) => {
+                self.vm.set_cell(index, value)?;
+                Ok(format!("[{index}] = {value}"))
+            }
+            Command::Snapshot(name) => {
+                let message = format!("Saved snapshot '{name}'.");
+                self.snapshots.insert(name, self.vm.snapshot());
+
+                Ok(message)
+            }
+            Command::Restore(name) => match self.snapshots.get(&name) {
+                Some(snapshot) => {
+                    self.vm.restore(snapshot)?;
+                    Ok(self.location())
+                }
+                None => Ok(format!("No snapshot named '{name}'.")),
+            },
+            Command::Help => Ok(String::from(HELP)),
+            Command::Quit => Ok(String::new()),
+        }
+    }
+
+    /// Execute one instruction. Returns false if the program has halted.
+    fn advance(&mut self) -> Result<bool> {
+        Ok(self.vm.step()? != StepResult::Halted)
+    }
+
+    fn location(&self) -> String {
+        if self.vm.is_halted() {
+            return String::from("Program halted.");
+        }
+
+        let instruction = match self.vm.current_instruction() {
+            Some(instruction) => format!("{instruction:?}"),
+            None => String::from("<unknown>"),
+        };
+
+        let pointer = self.vm.pointer();
+        match self.vm.cell(pointer) {
+            Some(cell) => format!("{:04} {instruction} [{pointer}] = {cell}", self.vm.ip()),
+            None => format!("{:04} {instruction} [{pointer}]", self.vm.ip()),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or("step");
+
+        let mut number = |what: &str| -> std::result::Result<u32, String> {
+            let word = words
+                .next()
+                .ok_or_else(|| format!("Expect {what} after '{name}'."))?;
+
+            word.parse::<u32>()
+                .map_err(|_| format!("Expect {what} to be a number, found '{word}'."))
+        };
+
+        let command = match name {
+            "step" | "s" => match s.split_whitespace().nth(1) {
+                Some(_) => Command::Step(number("count")? as usize),
+                None => Command::Step(1),
+            },
+            "continue" | "c" => Command::Continue,
+            "where" | "w" => Command::Where,
+            "get" => Command::Get(number("cell index")? as usize),
+            "set" => {
+                let index = number("cell index")? as usize;
+                Command::Set(index, number("value")?)
+            }
+            "snapshot" => Command::Snapshot(snapshot_name(s)),
+            "restore" => Command::Restore(snapshot_name(s)),
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => {
+                return Err(format!(
+                    "Unknown command '{name}'. Type 'help' for commands."
+                ))
+            }
+        };
+
+        Ok(command)
+    }
+}
+
+fn snapshot_name(command: &str) -> String {
+    let name = command.split_whitespace().nth(1);
+    String::from(name.unwrap_or(DEFAULT_SNAPSHOT))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::sink;
+
+    use super::*;
+    use crate::{error::Error, testing::compile};
+
+    fn debugger(program: &str) -> Debugger {
+        Debugger::new(VM::with_output(compile(program), Box::new(sink())))
+    }
+
+    #[test]
+    fn should_parse_commands() {
+        assert_eq!("".parse(), Ok(Command::Step(1)));
+        assert_eq!("s 4".parse(), Ok(Command::Step(4)));
+        assert_eq!("get 3".parse(), Ok(Command::Get(3)));
+        assert_eq!("set 3 42".parse(), Ok(Command::Set(3, 42)));
+        assert_eq!(
+            "snapshot".parse(),
+            Ok(Command::Snapshot(String::from("default")))
+        );
+        assert_eq!("restore a".parse(), Ok(Command::Restore(String::from("a"))));
+        assert!("set 3".parse::<Command>().is_err());
+        assert!("poke".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn should_get_and_set_cells_while_paused() {
+        let mut debugger = debugger("{4}+2>+");
+
+        // Define the tape and execute the first increment.
+        debugger.execute(Command::Step(3)).unwrap();
+        assert_eq!(debugger.execute(Command::Get(0)).unwrap(), "[0] = 2");
+
+        debugger.execute(Command::Set(1, 40)).unwrap();
+        debugger.execute(Command::Continue).unwrap();
+
+        assert!(debugger.is_halted());
+        assert_eq!(debugger.vm().tape(), &[2, 41, 0, 0]);
+    }
+
+    #[test]
+    fn should_restore_named_snapshots() {
+        let mut debugger = debugger("{4}+>+2");
+        debugger.execute(Command::Step(3)).unwrap();
+
+        let first = String::from("first");
+        debugger.execute(Command::Snapshot(first.clone())).unwrap();
+        debugger.execute(Command::Continue).unwrap();
+        debugger
+            .execute(Command::Snapshot(String::from("end")))
+            .unwrap();
+
+        let location = debugger.execute(Command::Restore(first)).unwrap();
+        assert_eq!(location, "0004 ShiftRight [0] = 1");
+        assert_eq!(debugger.vm().tape(), &[1, 0, 0, 0]);
+        assert!(!debugger.is_halted());
+
+        debugger.execute(Command::Step(2)).unwrap();
+        debugger
+            .execute(Command::Restore(String::from("end")))
+            .unwrap();
+        assert_eq!(debugger.vm().tape(), &[1, 2, 0, 0]);
+        assert!(debugger.is_halted());
+    }
+
+    #[test]
+    fn should_reject_values_wider_than_a_cell() {
+        let mut debugger = debugger("{4}");
+        debugger.execute(Command::Step(2)).unwrap();
+
+        let result = debugger.execute(Command::Set(0, 256));
+
+        assert!(matches!(result, Err(Error::Runtime { .. })));
+        assert!(matches!(
+            debugger.execute(Command::Set(4, 1)),
+            Err(Error::Runtime { .. })
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+use std::{fmt::Display, io, path::PathBuf, time::Duration};
+
+use crate::{asm::AssembleError, chunk::ChunkError, opcode::OpCode, vm::SnapshotError};
+
+/// Every failure that can occur while loading, compiling or running
+/// a paroxy program.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file or stream failed.
+    Io(io::Error),
+
+    /// A file (indirectly) includes itself. Holds the chain of includes.
+    IncludeCycle(Vec<PathBuf>),
+
+    /// An included file could not be read.
+    Include {
+        path: PathBuf,
+        included_by: PathBuf,
+        error: io::Error,
+    },
+
+    /// A source file is larger than the allowed size.
+    SourceTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+
+    /// The program could not be compiled. Diagnostics have already
+    /// been reported by the parser.
+    Compile,
+
+    /// An assembly listing could not be assembled into a chunk.
+    Assemble(AssembleError),
+
+    /// The program failed while executing. The location is missing for
+    /// failures outside of an instruction, like setting a cell from the
+    /// debugger.
+    Runtime {
+        error: RuntimeError,
+        location: Option<Location>,
+    },
+
+    /// The chunk could not be serialized into binary data.
+    Serialize(bincode::Error),
+
+    /// The binary data could not be deserialized into a chunk.
+    Deserialize(ChunkError),
+
+    /// A snapshot could not be loaded or does not belong to the chunk.
+    Snapshot(SnapshotError),
+
+    /// A line of an execution trace could not be read.
+    Trace { line: usize, message: String },
+
+    /// A compiled chunk was not compiled from the current contents of its
+    /// source file.
+    StaleBytecode(PathBuf),
+}
+
+/// Why a program failed while executing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// The pointer moved below the first cell.
+    PointerUnderflow,
+
+    /// The pointer moved to the given cell past the end of the tape.
+    PointerOutOfBounds { index: usize, tape_size: usize },
+
+    /// An operand indexes a cell outside the tape.
+    CellOutOfBounds,
+
+    /// A range of cells read or written by the instruction leaves the tape.
+    RangeOutOfBounds,
+
+    /// A cell would be changed to a value outside zero and the maximum
+    /// of the cell width.
+    CellOverflow { value: i64, max: u32 },
+
+    /// The input line does not fit into the cells following the pointer.
+    InputTooLarge,
+
+    /// The input word read as a number is not a decimal integer.
+    InvalidNumber(String),
+
+    /// A jump or loop continues outside the chunk.
+    InvalidJump,
+
+    /// The byte at the instruction pointer is not an opcode.
+    InvalidOpcode(u8),
+
+    /// An instruction refers to a constant missing from the chunk.
+    InvalidConstant(u16),
+
+    /// The chunk ends in the middle of an instruction or without returning.
+    UnexpectedEnd,
+
+    /// An instruction found the stack empty.
+    StackUnderflow,
+
+    /// Subroutines called each other deeper than the given number of
+    /// calls without returning.
+    CallStackOverflow(usize),
+
+    /// An instruction found a value of the wrong type on the stack, which
+    /// only happens with malformed bytecode.
+    TypeMismatch {
+        opcode: Option<OpCode>,
+        expected: &'static str,
+        found: &'static str,
+    },
+
+    /// A value is outside the range the instruction accepts.
+    InvalidArgument(&'static str),
+
+    /// The tape was defined a second time while redefinitions are errors.
+    TapeRedefined,
+
+    /// The instruction accesses the environment in sandbox mode.
+    Sandboxed,
+
+    /// The program executed the given maximum number of instructions
+    /// without halting.
+    StepLimitExceeded(u64),
+
+    /// The program ran for longer than the given duration without halting.
+    Timeout(Duration),
+}
+
+/// Where in the chunk a runtime error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// Source line of the failing instruction.
+    Line(usize),
+
+    /// Offset of the failing instruction, for chunks without a line table.
+    Offset(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Exit code of the process when failing with this error.
+    ///
+    /// | Code | Failure                                       |
+    /// | ---- | --------------------------------------------- |
+    /// | 1    | The program failed while executing.           |
+    /// | 2    | The program could not be compiled or          |
+    /// |      | assembled.                                    |
+    /// | 3    | Reading, writing or (de)serializing failed,   |
+    /// |      | an included file is missing, a source file    |
+    /// |      | is too large, a trace is malformed, a chunk   |
+    /// |      | is stale or a snapshot cannot be restored.    |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Runtime { .. } => 1,
+            Error::Compile | Error::Assemble(_) | Error::IncludeCycle(_) => 2,
+            Error::Io(_)
+            | Error::Include { .. }
+            | Error::SourceTooLarge { .. }
+            | Error::Serialize(_)
+            | Error::Deserialize(_)
+            | Error::Snapshot(_)
+            | Error::Trace { .. }
+            | Error::StaleBytecode(_) => 3,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<AssembleError> for Error {
+    fn from(error: AssembleError) -> Self {
+        Error::Assemble(error)
+    }
+}
+
+impl From<ChunkError> for Error {
+    fn from(error: ChunkError) -> Self {
+        Error::Deserialize(error)
+    }
+}
+
+impl From<SnapshotError> for Error {
+    fn from(error: SnapshotError) -> Self {
+        Error::Snapshot(error)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {error}"),
+            Error::IncludeCycle(chain) => {
+                let chain = chain
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+
+                write!(f, "Include cycle: {chain}")
+            }
+            Error::Include {
+                path,
+                included_by,
+                error,
+            } => write!(
+                f,
+                "Cannot include '{}' from '{}': {error}",
+                path.display(),
+                included_by.display()
+            ),
+            Error::SourceTooLarge { path, size, limit } => write!(
+                f,
+                "Source file '{}' is {size} bytes, more than the limit of {limit} bytes.",
+                path.display()
+            ),
+            Error::Compile => write!(f, "Compilation failed."),
+            Error::Assemble(error) => write!(f, "Failed to assemble: {error}"),
+            Error::Runtime { error, location } => match location {
+                Some(location) => write!(f, "Runtime error: {location} {error}"),
+                None => write!(f, "Runtime error: {error}"),
+            },
+            Error::Serialize(error) => write!(f, "Failed to serialize chunk: {error}"),
+            Error::Deserialize(error @ ChunkError::BadMagic) => write!(
+                f,
+                "Failed to load chunk: {error}. It may have been compiled by an older paroxy-rs, compile it again."
+            ),
+            Error::Deserialize(error @ ChunkError::VersionMismatch { .. }) => {
+                write!(f, "Failed to load chunk: {error}. Compile it again.")
+            }
+            Error::Deserialize(error) => {
+                write!(f, "Failed to load chunk from binary data: {error}")
+            }
+            Error::Snapshot(error) => write!(f, "Failed to restore snapshot: {error}."),
+            Error::Trace { line, message } => write!(f, "Invalid trace at line {line}: {message}"),
+            Error::StaleBytecode(source) => write!(
+                f,
+                "Bytecode is stale relative to source '{}'.",
+                source.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Include { error, .. } => Some(error),
+            Error::Serialize(error) => Some(error),
+            Error::Deserialize(error) => Some(error),
+            Error::Snapshot(error) => Some(error),
+            Error::Assemble(error) => Some(error),
+            Error::IncludeCycle(_)
+            | Error::SourceTooLarge { .. }
+            | Error::Compile
+            | Error::Runtime { .. }
+            | Error::Trace { .. }
+            | Error::StaleBytecode(_) => None,
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::PointerUnderflow => write!(f, "Pointer cannot move below zero."),
+            RuntimeError::PointerOutOfBounds { index, tape_size } => write!(
+                f,
+                "Pointer exceeds tape size: cell {index} of a {tape_size} cell tape."
+            ),
+            RuntimeError::CellOutOfBounds => write!(f, "Cell index exceeds tape size."),
+            RuntimeError::RangeOutOfBounds => write!(f, "Range exceeds tape size."),
+            RuntimeError::CellOverflow { value, .. } if *value < 0 => {
+                write!(f, "Cannot be less than 0 [{value}]")
+            }
+            RuntimeError::CellOverflow { value, max } => {
+                write!(f, "Cannot be greater than {max} [{value}]")
+            }
+            RuntimeError::InputTooLarge => write!(f, "The input is too large for following cells"),
+            RuntimeError::InvalidNumber(word) => {
+                write!(f, "Expected a decimal number in the input, found '{word}'.")
+            }
+            RuntimeError::InvalidJump => write!(f, "Jump target is outside the chunk."),
+            RuntimeError::InvalidOpcode(byte) => write!(f, "Unknown opcode {byte}."),
+            RuntimeError::InvalidConstant(index) => write!(f, "Constant {index} does not exist."),
+            RuntimeError::UnexpectedEnd => write!(f, "Unexpected end of bytecode."),
+            RuntimeError::StackUnderflow => write!(f, "Stack is empty."),
+            RuntimeError::CallStackOverflow(depth) => {
+                write!(f, "Subroutine calls nested deeper than {depth}.")
+            }
+            RuntimeError::TypeMismatch {
+                opcode: Some(opcode),
+                expected,
+                found,
+            } => write!(
+                f,
+                "Type mismatch: {opcode:?} expects {expected}, found {found}."
+            ),
+            RuntimeError::TypeMismatch {
+                opcode: None,
+                expected,
+                found,
+            } => write!(f, "Type mismatch: expected {expected}, found {found}."),
+            RuntimeError::InvalidArgument(message) => write!(f, "{message}"),
+            RuntimeError::TapeRedefined => write!(f, "Tape is already defined."),
+            RuntimeError::Sandboxed => write!(
+                f,
+                "Reading environment variables is disabled in sandbox mode."
+            ),
+            RuntimeError::StepLimitExceeded(limit) => {
+                write!(f, "Exceeded the limit of {limit} executed instructions.")
+            }
+            RuntimeError::Timeout(timeout) => write!(f, "Exceeded the timeout of {timeout:?}."),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Line(line) => write!(f, "[line {line}]"),
+            Location::Offset(offset) => write!(f, "[offset {offset:04}]"),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{chunk::Chunk, error::Result, output::Capture, vm::VM};
+
+/// First difference between the output of a program and the expected output.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Offset of the first differing byte.
+    pub offset: usize,
+
+    /// Line of the first differing byte, starting at 1.
+    pub line: usize,
+
+    /// The expected line, `None` if the expected output already ended.
+    pub expected: Option<String>,
+
+    /// The printed line, `None` if the program output already ended.
+    pub actual: Option<String>,
+}
+
+/// Program of a test suite with the files of its input and expected output.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Case {
+    pub program: PathBuf,
+
+    /// The input is empty if there is no input file.
+    pub input: Option<PathBuf>,
+    pub expected: PathBuf,
+}
+
+/// Find the `foo.px` programs of the directory which have a `foo.expected`
+/// file, together with their optional `foo.in` input, ordered by path.
+/// Programs without expected output, such as included files, are skipped.
+pub fn discover(dir: &Path) -> Result<Vec<Case>> {
+    let mut cases = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let program = entry?.path();
+        if program.extension().and_then(|extension| extension.to_str()) != Some("px") {
+            continue;
+        }
+
+        let expected = program.with_extension("expected");
+        if !expected.is_file() {
+            continue;
+        }
+
+        let input = Some(program.with_extension("in")).filter(|input| input.is_file());
+
+        cases.push(Case {
+            program,
+            input,
+            expected,
+        });
+    }
+
+    cases.sort_by(|a, b| a.program.cmp(&b.program));
+    Ok(cases)
+}
+
+/// Run the program with the given input and compare its output byte for
+/// byte against the expected output. Fails if the program runs for longer
+/// than the timeout.
+pub fn check(
+    chunk: Chunk,
+    input: Box<dyn Read>,
+    expected: &[u8],
+    timeout: Duration,
+) -> Result<Option<Mismatch>> {
+    let output = Capture::new();
+
+    let mut vm = VM::with_io(chunk, input, Box::new(output.clone()));
+    vm.run_with_timeout(timeout)?;
+
+    Ok(compare(&output.contents(), expected))
+}
+
+/// Find the first difference between the two outputs.
+pub fn compare(actual: &[u8], expected: &[u8]) -> Option<Mismatch> {
+    let offset = actual
+        .iter()
+        .zip(expected)
+        .position(|(a, e)| a != e)
+        .or_else(|| (actual.len() != expected.len()).then(|| actual.len().min(expected.len())))?;
+
+    let start = expected[..offset]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+
+    Some(Mismatch {
+        offset,
+        line: expected[..offset]
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count()
+            + 1,
+        expected: line_at(expected, start),
+        actual: line_at(actual, start),
+    })
+}
+
+/// The line starting at the given offset, without its line break.
+fn line_at(output: &[u8], start: usize) -> Option<String> {
+    if start >= output.len() {
+        return None;
+    }
+
+    let line = output[start..].split(|byte| *byte == b'\n').next()?;
+    Some(String::from_utf8_lossy(line).into_owned())
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Output differs at byte {} (line {}).",
+            self.offset, self.line
+        )?;
+        writeln!(f, "--- expected")?;
+        writeln!(f, "+++ actual")?;
+        writeln!(f, "@@ line {} @@", self.line)?;
+
+        match &self.expected {
+            Some(line) => writeln!(f, "-{line}")?,
+            None => writeln!(f, "-<end of output>")?,
+        }
+
+        match &self.actual {
+            Some(line) => write!(f, "+{line}"),
+            None => write!(f, "+<end of output>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        error::{Error, RuntimeError},
+        testing::compile,
+    };
+
+    #[test]
+    fn should_find_first_differing_line() {
+        let mismatch = compare(b"one\ntwo\nthree\n", b"one\ntwo\nthrew\n").unwrap();
+
+        assert_eq!(
+            mismatch,
+            Mismatch {
+                offset: 12,
+                line: 3,
+                expected: Some(String::from("threw")),
+                actual: Some(String::from("three")),
+            }
+        );
+    }
+
+    #[test]
+    fn should_report_missing_output() {
+        let mismatch = compare(b"one\n", b"one\ntwo").unwrap();
+
+        assert_eq!((mismatch.offset, mismatch.line), (4, 2));
+        assert_eq!(mismatch.expected.as_deref(), Some("two"));
+        assert_eq!(mismatch.actual, None);
+        assert!(mismatch.to_string().ends_with("-two\n+<end of output>"));
+
+        assert_eq!(compare(b"same", b"same"), None);
+    }
+
+    #[test]
+    fn should_discover_test_cases() {
+        let dir = std::env::temp_dir().join(format!("paroxy-discover-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["b.px", "b.expected", "a.px", "a.in", "a.expected", "lib.px"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let cases = discover(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            cases,
+            vec![
+                Case {
+                    program: dir.join("a.px"),
+                    input: Some(dir.join("a.in")),
+                    expected: dir.join("a.expected"),
+                },
+                Case {
+                    program: dir.join("b.px"),
+                    input: None,
+                    expected: dir.join("b.expected"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_check_program_output_with_input() {
+        let chunk = compile("{8},*.>.");
+
+        let input = Box::new(Cursor::new("hi\n"));
+        let mismatch = check(chunk, input, b"hx", Duration::from_secs(10)).unwrap();
+        assert_eq!(mismatch.map(|m| m.offset), Some(1));
+    }
+
+    #[test]
+    fn should_fail_programs_running_past_the_timeout() {
+        let chunk = compile("+[]");
+
+        let result = check(chunk, Box::new(Cursor::new("")), b"", Duration::ZERO);
+        assert!(matches!(
+            result,
+            Err(Error::Runtime {
+                error: RuntimeError::Timeout(_),
+                ..
+            })
+        ));
+    }
+}
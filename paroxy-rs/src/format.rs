@@ -0,0 +1,208 @@
+use std::str::FromStr;
+
+/// Unit of indentation for every level of loop nesting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces(2)
+    }
+}
+
+impl Indent {
+    fn unit(&self) -> String {
+        match self {
+            Indent::Spaces(width) => " ".repeat(*width),
+            Indent::Tab => String::from("\t"),
+        }
+    }
+}
+
+impl FromStr for Indent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "tab" {
+            return Ok(Indent::Tab);
+        }
+
+        s.parse::<usize>()
+            .map(Indent::Spaces)
+            .map_err(|_| format!("invalid indent '{s}', expected a number of spaces or 'tab'"))
+    }
+}
+
+/// Reformat paroxy source.
+///
+/// Every line is indented by the loop depth it starts at, trailing
+/// whitespace is removed and runs of blank lines are collapsed into one.
+/// Lines continuing a multi-line string are left untouched, and brackets
+/// in comments started by the given character do not count. Formatting
+/// already formatted source does not change it. Line breaks are written
+/// as `\n` and a leading byte order mark is dropped.
+pub fn format(source: &str, indent: Indent, comment: char) -> String {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let unit = indent.unit();
+
+    let mut lines: Vec<String> = vec![];
+    let mut depth = 0usize;
+    let mut string: Option<char> = None;
+
+    for line in source.lines() {
+        if string.is_some() {
+            lines.push(String::from(line));
+            string = scan_line(line, &mut depth, string, comment);
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            if lines.last().is_some_and(|last| !last.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+
+        let closing = trimmed.chars().take_while(|c| *c == ']').count();
+        let level = depth.saturating_sub(closing);
+        lines.push(format!("{}{trimmed}", unit.repeat(level)));
+
+        string = scan_line(trimmed, &mut depth, None, comment);
+    }
+
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    let mut formatted = lines.join("\n");
+    formatted.push('\n');
+    formatted
+}
+
+/// Track the loop depth across the line, skipping strings and comments.
+/// Returns the terminator of the string still open at the end of the line.
+fn scan_line(
+    line: &str,
+    depth: &mut usize,
+    mut string: Option<char>,
+    comment: char,
+) -> Option<char> {
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (string, c) {
+            // Skip the escaped character so an escaped quote does not end
+            // the string.
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(terminator), c) if c == terminator => string = None,
+            (Some(_), _) => (),
+            (None, c) if c == comment => break,
+            (None, '\'' | '"') => string = Some(c),
+            (None, '[') => *depth += 1,
+            (None, ']') => *depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::DEFAULT_COMMENT;
+
+    const SOURCE: &str = "{10}  \n\n\n+5 [\n>+2 [ -\n  ]\n  ]<\n\n";
+
+    #[test]
+    fn should_indent_loops() {
+        assert_eq!(
+            format(SOURCE, Indent::default(), DEFAULT_COMMENT),
+            "{10}\n\n+5 [\n  >+2 [ -\n  ]\n]<\n"
+        );
+        assert_eq!(
+            format(SOURCE, Indent::Tab, DEFAULT_COMMENT),
+            "{10}\n\n+5 [\n\t>+2 [ -\n\t]\n]<\n"
+        );
+    }
+
+    #[test]
+    fn should_be_idempotent_for_any_indent() {
+        for indent in [Indent::Spaces(0), Indent::Spaces(4), Indent::Tab] {
+            let formatted = format(SOURCE, indent, DEFAULT_COMMENT);
+            assert_eq!(format(&formatted, indent, DEFAULT_COMMENT), formatted);
+        }
+    }
+
+    #[test]
+    fn should_skip_brackets_and_quotes_in_comments() {
+        let formatted = "; see [x, don't\n+[\n  -\n]\n.\n";
+
+        assert_eq!(
+            format(
+                "; see [x, don't\n+[\n-\n]\n.",
+                Indent::default(),
+                DEFAULT_COMMENT
+            ),
+            formatted
+        );
+        assert_eq!(
+            format(formatted, Indent::default(), DEFAULT_COMMENT),
+            formatted
+        );
+        assert_eq!(
+            format("% see [x\n+[\n-\n]", Indent::default(), '%'),
+            "% see [x\n+[\n  -\n]\n"
+        );
+    }
+
+    #[test]
+    fn should_skip_escaped_quotes_in_strings() {
+        assert_eq!(
+            format(
+                "'don\\'t [' +[\n-\n]\n\"\\\\\" [\n-\n]",
+                Indent::default(),
+                DEFAULT_COMMENT
+            ),
+            "'don\\'t [' +[\n  -\n]\n\"\\\\\" [\n  -\n]\n"
+        );
+    }
+
+    #[test]
+    fn should_normalize_windows_source() {
+        assert_eq!(
+            format(
+                "\u{feff}[\r\n+\r\n]\r\n",
+                Indent::default(),
+                DEFAULT_COMMENT
+            ),
+            "[\n  +\n]\n"
+        );
+    }
+
+    #[test]
+    fn should_keep_multiline_strings() {
+        let source = "[\n'a [\n   b'\n.]";
+
+        assert_eq!(
+            format(source, Indent::Spaces(4), DEFAULT_COMMENT),
+            "[\n    'a [\n   b'\n    .]\n"
+        );
+    }
+
+    #[test]
+    fn should_parse_indent() {
+        assert_eq!("tab".parse(), Ok(Indent::Tab));
+        assert_eq!("4".parse(), Ok(Indent::Spaces(4)));
+        assert!("wide".parse::<Indent>().is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    fmt::Write,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    scanner::DEFAULT_COMMENT,
+};
+
+const DIRECTIVE: &str = "@include";
+
+/// Size in bytes above which source files are refused unless another
+/// limit is given.
+pub const DEFAULT_MAX_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A file and the files it includes, in order of appearance.
+#[derive(Debug)]
+pub struct Dependency {
+    pub path: PathBuf,
+    pub includes: Vec<Dependency>,
+
+    /// The file is already being included further up the tree.
+    pub cycle: bool,
+}
+
+/// Paths of the `@include "path"` directives in the source, in order.
+/// Directives in strings and in comments started by the given character
+/// do not count.
+pub fn directives(source: &str, comment: char) -> Vec<String> {
+    find_directives(source, comment)
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// Read the file at the given path and replace every include directive
+/// with the contents of the included file. Included paths are resolved
+/// relative to the including file.
+pub fn expand<P: AsRef<Path>>(path: P) -> Result<String> {
+    expand_limited(path, DEFAULT_MAX_SOURCE_SIZE, DEFAULT_COMMENT)
+}
+
+/// Like [`expand`], but refuse any file larger than the given number of
+/// bytes before reading it, and skip directives in comments started by
+/// the given character rather than `;`.
+pub fn expand_limited<P: AsRef<Path>>(path: P, max_size: u64, comment: char) -> Result<String> {
+    let options = Options { max_size, comment };
+    expand_file(path.as_ref(), None, &mut vec![], options)
+}
+
+/// Like [`expand_limited`], but read the source from the reader, such as
+/// stdin. Included paths are resolved relative to the current directory.
+pub fn expand_reader<R: Read>(reader: R, max_size: u64, comment: char) -> Result<String> {
+    let source = read_reader(reader, max_size)?;
+    let stdin = Path::new("<stdin>");
+    let options = Options { max_size, comment };
+
+    expand_source(&source, stdin, Path::new(""), &mut vec![], options)
+}
+
+/// Read a source file without its byte order mark, so included files
+/// do not leave one in the middle of the expanded source. Includes are
+/// not expanded. Refuses files larger than the given number of bytes.
+pub fn read_limited<P: AsRef<Path>>(path: P, max_size: u64) -> Result<String> {
+    let path = path.as_ref();
+    let size = fs::metadata(path)?.len();
+    if size > max_size {
+        return Err(Error::SourceTooLarge {
+            path: path.to_path_buf(),
+            size,
+            limit: max_size,
+        });
+    }
+
+    Ok(strip_bom(fs::read_to_string(path)?))
+}
+
+/// Like [`read_limited`], but read from the reader, such as stdin.
+pub fn read_reader<R: Read>(mut reader: R, max_size: u64) -> Result<String> {
+    let mut source = String::new();
+    reader
+        .by_ref()
+        .take(max_size.saturating_add(1))
+        .read_to_string(&mut source)?;
+
+    let size = source.len() as u64;
+    if size > max_size {
+        // Count the rest to report the real size.
+        let rest = io::copy(&mut reader, &mut io::sink())?;
+        return Err(Error::SourceTooLarge {
+            path: PathBuf::from("<stdin>"),
+            size: size + rest,
+            limit: max_size,
+        });
+    }
+
+    Ok(strip_bom(source))
+}
+
+/// Resolve the include graph starting at the given file. Cycles are
+/// marked rather than followed.
+pub fn dependencies<P: AsRef<Path>>(path: P) -> Result<Dependency> {
+    dependencies_limited(path, DEFAULT_MAX_SOURCE_SIZE, DEFAULT_COMMENT)
+}
+
+/// Like [`dependencies`], but refuse any file larger than the given number
+/// of bytes before reading it, and skip directives in comments started by
+/// the given character rather than `;`.
+pub fn dependencies_limited<P: AsRef<Path>>(
+    path: P,
+    max_size: u64,
+    comment: char,
+) -> Result<Dependency> {
+    let options = Options { max_size, comment };
+    resolve(path.as_ref().to_path_buf(), None, &mut vec![], options)
+}
+
+/// How source files are read while expanding includes.
+#[derive(Clone, Copy)]
+struct Options {
+    max_size: u64,
+    comment: char,
+}
+
+impl Dependency {
+    /// Render the graph as an indented tree.
+    pub fn tree(&self) -> String {
+        let mut output = String::new();
+        self.write_tree(&mut output, 0);
+        output
+    }
+
+    /// Render the graph in the graphviz dot format.
+    pub fn dot(&self) -> String {
+        let mut output = String::from("digraph includes {\n");
+        self.write_edges(&mut output);
+        output.push_str("}\n");
+        output
+    }
+
+    fn write_tree(&self, output: &mut String, depth: usize) {
+        let marker = if self.cycle { " (cycle)" } else { "" };
+        let _ = writeln!(
+            output,
+            "{:indent$}{}{marker}",
+            "",
+            self.path.display(),
+            indent = depth * 2
+        );
+
+        for include in &self.includes {
+            include.write_tree(output, depth + 1);
+        }
+    }
+
+    fn write_edges(&self, output: &mut String) {
+        for include in &self.includes {
+            let style = if include.cycle {
+                " [color=red, label=\"cycle\"]"
+            } else {
+                ""
+            };
+
+            let _ = writeln!(
+                output,
+                "    \"{}\" -> \"{}\"{style};",
+                self.path.display(),
+                include.path.display()
+            );
+
+            include.write_edges(output);
+        }
+    }
+}
+
+fn resolve(
+    path: PathBuf,
+    included_by: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+    options: Options,
+) -> Result<Dependency> {
+    let canonical =
+        fs::canonicalize(&path).map_err(|error| include_error(error.into(), &path, included_by))?;
+    if stack.contains(&canonical) {
+        return Ok(Dependency {
+            path,
+            includes: vec![],
+            cycle: true,
+        });
+    }
+
+    let source = read_limited(&path, options.max_size)
+        .map_err(|error| include_error(error, &path, included_by))?;
+    let parent = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+    stack.push(canonical);
+    let includes = directives(&source, options.comment)
+        .into_iter()
+        .map(|include| resolve(parent.join(include), Some(&path), stack, options))
+        .collect::<Result<Vec<_>>>();
+    stack.pop();
+
+    Ok(Dependency {
+        path,
+        includes: includes?,
+        cycle: false,
+    })
+}
+
+fn expand_file(
+    path: &Path,
+    included_by: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+    options: Options,
+) -> Result<String> {
+    let canonical =
+        fs::canonicalize(path).map_err(|error| include_error(error.into(), path, included_by))?;
+    if stack.contains(&canonical) {
+        stack.push(canonical);
+        return Err(Error::IncludeCycle(stack.clone()));
+    }
+
+    let source = read_limited(path, options.max_size)
+        .map_err(|error| include_error(error, path, included_by))?;
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+
+    stack.push(canonical);
+    let expanded = expand_source(&source, path, parent, stack, options)?;
+    stack.pop();
+
+    Ok(expanded)
+}
+
+/// Replace the include directives of the source of the given file,
+/// resolving included paths relative to the given directory.
+fn expand_source(
+    source: &str,
+    file: &Path,
+    parent: &Path,
+    stack: &mut Vec<PathBuf>,
+    options: Options,
+) -> Result<String> {
+    let mut expanded = String::new();
+    let mut last = 0;
+    for (range, include) in find_directives(source, options.comment) {
+        expanded.push_str(&source[last..range.start]);
+        expanded.push_str(&expand_file(
+            &parent.join(include),
+            Some(file),
+            stack,
+            options,
+        )?);
+        last = range.end;
+    }
+    expanded.push_str(&source[last..]);
+
+    Ok(expanded)
+}
+
+/// Name the included file and the file including it in I/O errors, which
+/// otherwise do not say which file could not be read.
+fn include_error(error: Error, path: &Path, included_by: Option<&Path>) -> Error {
+    match (error, included_by) {
+        (Error::Io(error), Some(included_by)) => Error::Include {
+            path: path.to_path_buf(),
+            included_by: included_by.to_path_buf(),
+            error,
+        },
+        (error, _) => error,
+    }
+}
+
+fn strip_bom(source: String) -> String {
+    match source.strip_prefix('\u{feff}') {
+        Some(stripped) => String::from(stripped),
+        None => source,
+    }
+}
+
+/// Byte ranges and paths of the include directives, skipping string literals.
+fn find_directives(source: &str, comment: char) -> Vec<(std::ops::Range<usize>, String)> {
+    let mut directives = vec![];
+
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                while let Some((_, next)) = chars.next() {
+                    // Skip the escaped character so an escaped quote does
+                    // not end the string.
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c {
+                        break;
+                    }
+                }
+            }
+            '@' if source[start..].starts_with(DIRECTIVE) => {
+                let rest = &source[start + DIRECTIVE.len()..];
+                let trimmed = rest.trim_start();
+                let quote_at = start + DIRECTIVE.len() + (rest.len() - trimmed.len());
+
+                let quote = match trimmed.chars().next() {
+                    Some(quote @ ('\'' | '"')) => quote,
+                    _ => continue,
+                };
+
+                if let Some(length) = trimmed[1..].find(quote) {
+                    let path = String::from(&trimmed[1..1 + length]);
+                    let end = quote_at + length + 2;
+
+                    directives.push((start..end, path));
+                    while chars.peek().is_some_and(|(offset, _)| *offset < end) {
+                        chars.next();
+                    }
+                }
+            }
+            c if c == comment => {
+                for (_, next) in chars.by_ref() {
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    directives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("paroxy-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn should_find_directives_outside_strings() {
+        let source = "@include \"a.px\" '@include \"b.px\"' @include 'c.px' @4";
+
+        assert_eq!(directives(source, ';'), vec!["a.px", "c.px"]);
+    }
+
+    #[test]
+    fn should_skip_escaped_quotes_in_strings() {
+        let source = "'it\\'s @include \"a.px\"' @include 'b.px' \"\\\\\" @include 'c.px'";
+
+        assert_eq!(directives(source, ';'), vec!["b.px", "c.px"]);
+    }
+
+    #[test]
+    fn should_skip_directives_in_comments() {
+        let source = "; @include 'a.px'\n@include 'b.px' % @include 'c.px'\n'; x'@include 'd.px'";
+
+        assert_eq!(directives(source, ';'), vec!["b.px", "c.px", "d.px"]);
+        assert_eq!(directives(source, '%'), vec!["a.px", "b.px", "d.px"]);
+    }
+
+    #[test]
+    fn should_expand_nested_includes() {
+        let dir = write_files(
+            "expand",
+            &[
+                ("main.px", "@include \"a.px\".3"),
+                ("a.px", "\u{feff}'hi'@include 'b.px'"),
+                ("b.px", ">2"),
+            ],
+        );
+
+        let expanded = expand(dir.join("main.px")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(expanded, "'hi'>2.3");
+    }
+
+    #[test]
+    fn should_mark_cycles_in_dependencies() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("main.px", "@include 'a.px'"),
+                ("a.px", "@include 'main.px'"),
+            ],
+        );
+
+        let graph = dependencies(dir.join("main.px")).unwrap();
+        let expanded = expand(dir.join("main.px"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let tree = graph.tree().replace(&format!("{}/", dir.display()), "");
+        assert_eq!(tree, "main.px\n  a.px\n    main.px (cycle)\n");
+        assert!(matches!(expanded, Err(Error::IncludeCycle(_))));
+    }
+
+    #[test]
+    fn should_name_missing_includes_and_their_includer() {
+        let dir = write_files("missing", &[("main.px", "+@include 'gone.px'")]);
+
+        let expanded = expand(dir.join("main.px"));
+        let graph = dependencies(dir.join("main.px"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        for error in [expanded.unwrap_err(), graph.unwrap_err()] {
+            assert_eq!(error.exit_code(), 3);
+            assert!(matches!(
+                &error,
+                Error::Include { path, included_by, .. }
+                    if path.ends_with("gone.px") && included_by.ends_with("main.px")
+            ));
+            assert!(error.to_string().starts_with("Cannot include '"));
+        }
+
+        // The file given directly is not included by anything.
+        assert!(matches!(expand(dir.join("main.px")), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn should_refuse_files_over_size_limit() {
+        let dir = write_files(
+            "limit",
+            &[
+                ("main.px", "@include 'big.px'"),
+                ("big.px", &"+".repeat(30)),
+            ],
+        );
+
+        let expanded = expand_limited(dir.join("main.px"), 30, DEFAULT_COMMENT);
+        let refused = expand_limited(dir.join("main.px"), 20, DEFAULT_COMMENT);
+        let graph = dependencies_limited(dir.join("main.px"), 20, DEFAULT_COMMENT);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(graph, Err(Error::SourceTooLarge { size: 30, .. })));
+        assert_eq!(expanded.unwrap(), "+".repeat(30));
+        assert!(matches!(
+            refused,
+            Err(Error::SourceTooLarge { path, size: 30, limit: 20 }) if path.ends_with("big.px")
+        ));
+    }
+
+    #[test]
+    fn should_expand_source_read_from_reader() {
+        let expanded = expand_reader("\u{feff}+2.".as_bytes(), 30, DEFAULT_COMMENT).unwrap();
+        let refused = expand_reader("+".repeat(30).as_bytes(), 20, DEFAULT_COMMENT);
+
+        assert_eq!(expanded, "+2.");
+        assert!(matches!(
+            refused,
+            Err(Error::SourceTooLarge {
+                size: 30,
+                limit: 20,
+                ..
+            })
+        ));
+    }
+}
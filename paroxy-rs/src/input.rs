@@ -0,0 +1,122 @@
+use std::io::{self, stdin, Read};
+
+/// Source of program input which can look one byte ahead without
+/// consuming it.
+pub struct Input {
+    reader: Box<dyn Read>,
+    peeked: Option<u8>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new(Box::new(stdin()))
+    }
+}
+
+impl Input {
+    pub fn new(reader: Box<dyn Read>) -> Self {
+        Self {
+            reader,
+            peeked: None,
+        }
+    }
+
+    /// Next byte of the input without consuming it, `None` at the end.
+    pub fn peek(&mut self) -> io::Result<Option<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_byte()?;
+        }
+
+        Ok(self.peeked)
+    }
+
+    /// Consume the next byte of the input, `None` at the end.
+    pub fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.peeked.take() {
+            Some(byte) => Ok(Some(byte)),
+            None => self.next_byte(),
+        }
+    }
+
+    /// Consume the input up to and including the next line break. The line
+    /// is empty at the end of the input.
+    pub fn read_line(&mut self) -> io::Result<String> {
+        let mut line = vec![];
+
+        while let Some(byte) = self.read_byte()? {
+            line.push(byte);
+
+            if byte == b'\n' {
+                break;
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Skip leading whitespace and consume the input up to the next
+    /// whitespace, which is left unread. The word is empty at the end of
+    /// the input.
+    pub fn read_word(&mut self) -> io::Result<String> {
+        while self.peek()?.is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.read_byte()?;
+        }
+
+        let mut word = vec![];
+        while let Some(byte) = self.peek()? {
+            if byte.is_ascii_whitespace() {
+                break;
+            }
+
+            word.push(byte);
+            self.read_byte()?;
+        }
+
+        Ok(String::from_utf8_lossy(&word).into_owned())
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn should_peek_without_consuming() {
+        let mut input = Input::new(Box::new(Cursor::new("ab\ncd")));
+
+        assert_eq!(input.peek().unwrap(), Some(b'a'));
+        assert_eq!(input.peek().unwrap(), Some(b'a'));
+        assert_eq!(input.read_line().unwrap(), "ab\n");
+        assert_eq!(input.peek().unwrap(), Some(b'c'));
+        assert_eq!(input.read_byte().unwrap(), Some(b'c'));
+        assert_eq!(input.read_line().unwrap(), "d");
+        assert_eq!(input.peek().unwrap(), None);
+        assert_eq!(input.read_line().unwrap(), "");
+    }
+
+    #[test]
+    fn should_read_whitespace_delimited_words() {
+        let mut input = Input::new(Box::new(Cursor::new("  12 ab\n\t3\n")));
+
+        assert_eq!(input.read_word().unwrap(), "12");
+        assert_eq!(input.read_word().unwrap(), "ab");
+        assert_eq!(input.read_word().unwrap(), "3");
+        assert_eq!(input.peek().unwrap(), Some(b'\n'));
+        assert_eq!(input.read_word().unwrap(), "");
+    }
+}
@@ -0,0 +1,100 @@
+/// Names of every intrinsic, without the leading '$'.
+pub const NAMES: [&str; 10] = [
+    "clearif",
+    "dup",
+    "yield",
+    "checksum",
+    "reverse",
+    "sort",
+    "find",
+    "halt",
+    "transpose",
+    "env",
+];
+
+/// Set of intrinsics a program may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Intrinsics(u16);
+
+impl Intrinsics {
+    /// Every intrinsic.
+    pub const fn all() -> Self {
+        Self((1 << NAMES.len()) - 1)
+    }
+
+    /// No intrinsic at all.
+    pub const fn none() -> Self {
+        Self(0)
+    }
+
+    /// Only the named intrinsics. Unknown names are ignored.
+    pub fn only<S: AsRef<str>>(names: &[S]) -> Self {
+        names
+            .iter()
+            .filter_map(|name| bit(name.as_ref()))
+            .fold(Self::none(), |set, bit| Self(set.0 | bit))
+    }
+
+    /// Every intrinsic but the named ones. Unknown names are ignored.
+    pub fn except<S: AsRef<str>>(names: &[S]) -> Self {
+        Self(Self::all().0 & !Self::only(names).0)
+    }
+
+    /// Whether the program may use the intrinsic. Unknown intrinsics are
+    /// always contained so they are reported as unknown instead.
+    pub fn contains(&self, name: &str) -> bool {
+        bit(name).is_none_or(|bit| self.0 & bit != 0)
+    }
+}
+
+impl Default for Intrinsics {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Check that the name is a known intrinsic, with or without the '$'.
+pub fn check_name(name: &str) -> Result<String, String> {
+    let name = name.trim().trim_start_matches('$');
+
+    match bit(name) {
+        Some(_) => Ok(String::from(name)),
+        None => Err(format!(
+            "unknown intrinsic '{name}', expected one of {}",
+            NAMES.join(", ")
+        )),
+    }
+}
+
+fn bit(name: &str) -> Option<u16> {
+    NAMES
+        .iter()
+        .position(|known| *known == name)
+        .map(|index| 1 << index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_restrict_intrinsics() {
+        let allowed = Intrinsics::only(&["sort", "find"]);
+        assert!(allowed.contains("sort"));
+        assert!(allowed.contains("find"));
+        assert!(!allowed.contains("env"));
+
+        let denied = Intrinsics::except(&["env"]);
+        assert!(!denied.contains("env"));
+        assert!(denied.contains("sort"));
+
+        assert!(NAMES.iter().all(|name| Intrinsics::all().contains(name)));
+        assert!(Intrinsics::none().contains("unknown"));
+    }
+
+    #[test]
+    fn should_check_intrinsic_names() {
+        assert_eq!(check_name("$env"), Ok(String::from("env")));
+        assert!(check_name("loadfile").is_err());
+    }
+}
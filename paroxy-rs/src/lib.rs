@@ -0,0 +1,53 @@
+//! Compile and run paroxy programs from rust.
+//!
+//! ```
+//! let chunk = paroxy::compile("{4}#72>#105<.2").unwrap();
+//! paroxy::run(chunk).unwrap();
+//!
+//! let error = paroxy::compile("+300").unwrap_err();
+//! assert_eq!(error.to_string(), "[line 1:2] Error: Expect integer between 0-255.");
+//! ```
+
+pub mod asm;
+pub mod chunk;
+pub mod debug;
+pub mod debugger;
+pub mod error;
+pub mod expect;
+pub mod format;
+pub mod include;
+pub mod input;
+pub mod intrinsic;
+pub mod opcode;
+pub mod output;
+pub mod parser;
+pub mod profile;
+pub mod repl;
+pub mod scanner;
+pub mod scheduler;
+pub mod script;
+pub mod tape;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod token;
+pub mod trace;
+pub mod translate;
+pub mod vm;
+
+pub use chunk::{Chunk, Value};
+pub use error::{Error, Result};
+pub use opcode::OpCode;
+pub use parser::{CompileError, Diagnostic, DiagnosticKind, Parser};
+pub use scanner::Scanner;
+pub use vm::VM;
+
+/// Compile the source into a chunk, or collect the diagnostics of why it
+/// could not be compiled.
+pub fn compile(source: &str) -> std::result::Result<Chunk, CompileError> {
+    parser::compile(source).map_err(|diagnostics| CompileError { diagnostics })
+}
+
+/// Run the chunk to completion, printing its output to stdout.
+pub fn run(chunk: Chunk) -> Result<()> {
+    VM::new(chunk).run()
+}
@@ -1,107 +1,1314 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
-use chunk::Chunk;
 use clap::Parser;
-use scanner::Scanner;
-use vm::VM;
+use paroxy::{
+    asm,
+    chunk::{self, Chunk},
+    debug,
+    debugger::{Command, Debugger},
+    error::{Error, Result, RuntimeError},
+    expect, format,
+    include::{self, DEFAULT_MAX_SOURCE_SIZE},
+    intrinsic::Intrinsics,
+    output::{CountingSink, LastByte, Tee},
+    parser,
+    repl::{self, Repl},
+    scanner::{Scanner, DEFAULT_COMMENT},
+    scheduler::Scheduler,
+    script,
+    tape::{self, Cell, CellWidth, GrowableTape, Tape, VecTape},
+    trace::{self, Replay},
+    translate,
+    vm::{HaltReason, Snapshot, StepResult, VM},
+};
 
-mod chunk;
-mod opcode;
+#[cfg(unix)]
+use paroxy::tape::MmapTape;
 
 mod cli;
-mod debug;
-mod parser;
-mod scanner;
-mod token;
-mod vm;
+
+/// Source file argument which reads the program from stdin.
+const STDIN_SOURCE: &str = "-";
+
+/// Exit code of the `test` command when the output differs from the
+/// expected output.
+const TEST_FAILED: i32 = 4;
+
+/// Exit code of the `check` command when the program has compile errors.
+const CHECK_FAILED: i32 = 1;
+
+/// Exit code for options which cannot be used together, the same as
+/// for the usage errors reported while parsing the command line.
+const USAGE_ERROR: i32 = 2;
+
+/// How source code is read and compiled.
+#[derive(Debug, Clone, Copy)]
+struct SourceOptions {
+    bf_compat: bool,
+    comment_char: char,
+    optimize: bool,
+
+    /// Cells of the vm running the program wrap around.
+    wrapping: bool,
+
+    intrinsics: Intrinsics,
+    cell_width: CellWidth,
+
+    /// Print the disassembly of the compiled chunk.
+    dump_bytecode: bool,
+
+    /// Size in bytes above which source files are refused.
+    max_size: u64,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        Self {
+            bf_compat: false,
+            comment_char: DEFAULT_COMMENT,
+            optimize: false,
+            wrapping: false,
+            intrinsics: Intrinsics::all(),
+            cell_width: CellWidth::Eight,
+            dump_bytecode: false,
+            max_size: DEFAULT_MAX_SOURCE_SIZE,
+        }
+    }
+}
+
+impl From<cli::SourceArgs> for SourceOptions {
+    fn from(args: cli::SourceArgs) -> Self {
+        Self {
+            bf_compat: args.bf_compat,
+            comment_char: args.comment_char,
+            optimize: args.optimize && !args.no_optimize,
+            intrinsics: cli::intrinsics(args.allow_intrinsics, args.deny_intrinsics),
+            max_size: args.max_source_size,
+            ..SourceOptions::default()
+        }
+    }
+}
 
 fn main() {
     let cli = cli::Cli::parse();
 
-    match cli.command {
+    match execute(cli.command) {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            eprintln!("{error}");
+            process::exit(error.exit_code());
+        }
+    }
+}
+
+/// Execute the command and return the exit code of the process.
+fn execute(command: cli::Commands) -> Result<i32> {
+    match command {
         cli::Commands::Run {
             source,
+            others,
+            interleave,
             file,
             compiled,
+            source_args,
+            bf_strict,
+            cell_width,
+            dump_bytecode,
+            profile_loops,
+            profile,
+            coverage,
+            count_only,
+            ensure_newline,
+            run_steps,
+            snapshot_out,
+            resume,
+            max_steps,
+            timeout,
+            sandbox,
+            stop_on_output,
+            dump_tape,
+            print_final_cell,
+            redefine_tape,
+            eof,
+            tape_backing,
+            growable,
+            max_tape_size,
+            trace,
+            trace_execution,
+            check_source,
+            strict,
         } => {
-            if compiled && !file {
-                panic!("use '--file' flag when running compiled chunk.");
+            let options = SourceOptions::from(source_args);
+            let options = SourceOptions {
+                bf_compat: options.bf_compat || bf_strict,
+                wrapping: bf_strict,
+                cell_width,
+                dump_bytecode,
+                ..options
+            };
+
+            let eof = if bf_strict { cli::Eof::Unchanged } else { eof };
+
+            // Settings shared by every program, interleaved or not.
+            let configure = |vm: &mut VM| {
+                if growable {
+                    vm.set_tape(Box::new(GrowableTape::new(max_tape_size)));
+                }
+
+                vm.set_sandbox(sandbox);
+                vm.set_redefine_tape(redefine_tape.into());
+                vm.set_eof_mode(eof.into());
+                vm.set_wrapping(bf_strict);
+                vm.set_byte_io(bf_strict);
+                vm.set_stop_on_output(stop_on_output.map(Cell::from));
+            };
+
+            if interleave {
+                let mut vms = vec![];
+                for source in iter::once(source).chain(others) {
+                    let mut vm = VM::new(get_chunk(source, file, compiled, options)?);
+                    configure(&mut vm);
+                    vms.push(vm);
+                }
+
+                return Ok(Scheduler::new(vms).run()? as i32);
             }
 
-            match get_chunk(source, file, compiled) {
-                Ok(chunk) => run(chunk),
-                Err(error) => panic!("{error}"),
+            let chunk = get_chunk(source, file, compiled, options)?;
+
+            if let Some(path) = check_source {
+                if is_stale(&chunk, &path, options)? {
+                    let error = Error::StaleBytecode(path);
+                    if strict {
+                        return Err(error);
+                    }
+
+                    eprintln!("Warning: {error}");
+                }
             }
-        }
-        cli::Commands::Compile { source, file, out } => {
-            if !file && out.is_none() {
-                println!("'--out' must be used when using raw program code.");
-                return;
+
+            let mut vm = VM::new(chunk);
+            configure(&mut vm);
+            if !growable {
+                vm.set_tape(get_tape(tape_backing)?);
+            }
+
+            let counter = CountingSink::new();
+            if count_only {
+                vm.set_output(Box::new(counter.clone()));
             }
 
-            let program = get_program(source.clone(), file);
+            let last_byte = LastByte::new();
+            if ensure_newline {
+                let output = vm.take_output();
+                vm.set_output(Box::new(Tee::new(output, last_byte.clone())));
+            }
 
-            let chunk = match parse(program) {
-                Ok(chunk) => chunk,
-                Err(_) => return,
+            if profile_loops || coverage {
+                vm.enable_profiling();
+            }
+
+            if profile {
+                vm.enable_opcode_counts();
+            }
+
+            if let Some(path) = resume {
+                vm.restore(&Snapshot::from_bytes(&fs::read(path)?)?)?;
+            }
+
+            if trace_execution {
+                vm.set_trace(Some(Box::new(io::BufWriter::new(io::stderr()))));
+            }
+
+            let result = match trace {
+                Some(path) => {
+                    let mut file = io::BufWriter::new(fs::File::create(path)?);
+                    trace::record(&mut vm, &mut file, run_steps)
+                }
+                None => match run_steps {
+                    Some(limit) => vm.run_steps(limit),
+                    None => vm
+                        .run_with_limits(max_steps, timeout)
+                        .map(|_| StepResult::Halted),
+                },
             };
 
-            let bytes = chunk.as_bytes().expect("Failed to serialize data");
+            if let (Ok(StepResult::Continue), Some(limit)) = (&result, run_steps) {
+                eprintln!("Paused after {limit} steps.");
+
+                if let Some(path) = snapshot_out {
+                    fs::write(path, vm.snapshot().as_bytes()?)?;
+                }
+            }
+
+            let halted = matches!(result, Ok(StepResult::Halted));
+            if halted && last_byte.get().is_some_and(|byte| byte != b'\n') {
+                println!();
+            }
+
+            if let Some(HaltReason::Sentinel(byte)) = vm.halt_reason() {
+                eprintln!("Stopped on output byte {byte}.");
+            }
+
+            if let Some(range) = dump_tape {
+                eprint!("{}", tape::dump_table(&vm.tape(), vm.pointer(), range));
+            }
+
+            if let Some(format) = print_final_cell {
+                if let Some(cell) = vm.cell(vm.pointer()) {
+                    eprintln!("Cell {}: {}", vm.pointer(), format.format(cell));
+                }
+            }
+
+            if let Some(profile) = vm.profile() {
+                if profile_loops {
+                    eprint!("{}", profile.loop_report(vm.chunk()));
+                }
+
+                if coverage {
+                    eprint!("{}", profile.coverage_report(vm.chunk()));
+                }
+            }
+
+            if let Some(counts) = vm.opcode_counts() {
+                eprint!("{}", counts.report());
+            }
+
+            if count_only {
+                println!("{}", counter.count());
+            }
+
+            result?;
+
+            match vm.halt_reason() {
+                Some(HaltReason::Exit(code)) => Ok(code as i32),
+                _ => Ok(0),
+            }
+        }
+        cli::Commands::Compile {
+            source,
+            file,
+            out,
+            source_args,
+            cell_width,
+            dump_bytecode,
+            emit,
+            show_disasm,
+            strip,
+            verbose,
+        } => {
+            let extension = match emit {
+                cli::Emit::Pxb => Some("pxb"),
+                cli::Emit::Sh => Some("sh"),
+                cli::Emit::Asm => Some("asm"),
+                cli::Emit::None => None,
+            };
+
+            let out = match (out, extension) {
+                (None, Some(extension)) => {
+                    if !file {
+                        eprintln!("'--out' must be used when using raw program code.");
+                        return Ok(USAGE_ERROR);
+                    }
+
+                    if source == STDIN_SOURCE {
+                        eprintln!("'--out' must be used when reading the program from stdin.");
+                        return Ok(USAGE_ERROR);
+                    }
+
+                    match default_out(Path::new(&source), extension) {
+                        Some(path) => Some(path),
+                        None => {
+                            eprintln!("'--out' must be used when '{source}' has no file name.");
+                            return Ok(USAGE_ERROR);
+                        }
+                    }
+                }
+                (out, _) => out,
+            };
+
+            let options = SourceOptions {
+                cell_width,
+                dump_bytecode,
+                ..SourceOptions::from(source_args)
+            };
+
+            let program = get_program(source.clone(), file, options)?;
+            let mut chunk = parse(program, options)?;
+            if strip {
+                chunk.strip();
+            }
+
+            let bytes = chunk.as_bytes()?;
+
+            if verbose {
+                eprintln!(
+                    "{} bytes of bytecode, {} instructions, {}/{} constants",
+                    chunk.code.len(),
+                    chunk.instruction_count(),
+                    chunk.constant_count(),
+                    u16::MAX as usize + 1
+                );
+            }
+
+            if show_disasm {
+                let name = if file { source.as_str() } else { "<script>" };
+                print!("{}", debug::disassemble_to_string(&chunk, name));
+            }
 
             let file = match out {
                 Some(path) => path,
+                None => return Ok(0),
+            };
+
+            match emit {
+                cli::Emit::Pxb => fs::write(file, bytes)?,
+                cli::Emit::Sh => write_executable(file, script::shell_script(&bytes))?,
+                cli::Emit::Asm => fs::write(file, asm::to_asm(&chunk))?,
+                cli::Emit::None => (),
+            }
+
+            Ok(0)
+        }
+        cli::Commands::Check {
+            source,
+            file,
+            source_args,
+            cell_width,
+        } => {
+            let options = SourceOptions {
+                cell_width,
+                ..SourceOptions::from(source_args)
+            };
+            let program = get_program(source, file, options)?;
+
+            // The diagnostics were already reported by the parser.
+            match parse(program, options) {
+                Ok(_) => Ok(0),
+                Err(Error::Compile) => Ok(CHECK_FAILED),
+                Err(error) => Err(error),
+            }
+        }
+        cli::Commands::Test {
+            program,
+            input,
+            expect,
+            timeout,
+        } => {
+            if program.is_dir() {
+                return test_suite(&program, timeout);
+            }
+
+            let expected = match expect {
+                Some(expected) => expected,
                 None => {
-                    let source_file = PathBuf::from(source);
-                    let parent = source_file.parent().unwrap();
+                    eprintln!("'--expect' must be used when testing a single program.");
+                    return Ok(USAGE_ERROR);
+                }
+            };
 
-                    let out_stem = source_file.file_stem().unwrap().to_string_lossy();
-                    let out_name = format!("{out_stem}.pxb");
+            let case = expect::Case {
+                program,
+                input,
+                expected,
+            };
 
-                    parent.join(out_name)
+            match run_test(&case, timeout) {
+                Ok(None) => {
+                    println!("ok: {}", case.program.display());
+                    Ok(0)
+                }
+                Ok(Some(mismatch)) => {
+                    println!("FAILED: {}\n{mismatch}", case.program.display());
+                    Ok(TEST_FAILED)
+                }
+                Err(
+                    error @ Error::Runtime {
+                        error: RuntimeError::Timeout(_),
+                        ..
+                    },
+                ) => {
+                    println!("FAILED: {}\n{error}", case.program.display());
+                    Ok(TEST_FAILED)
+                }
+                Err(error) => Err(error),
+            }
+        }
+        cli::Commands::Assemble {
+            source,
+            out,
+            max_source_size,
+        } => {
+            let out = match out {
+                Some(out) => out,
+                None if source == Path::new(STDIN_SOURCE) => {
+                    eprintln!("'--out' must be used when reading the listing from stdin.");
+                    return Ok(USAGE_ERROR);
                 }
+                None => source.with_extension("pxb"),
+            };
+
+            let chunk = asm::assemble(&read_source(&source, max_source_size)?)?;
+            fs::write(out, chunk.as_bytes()?)?;
+            Ok(0)
+        }
+        cli::Commands::Translate {
+            source,
+            out,
+            max_source_size,
+        } => {
+            let program = read_source(&source, max_source_size)?;
+            let translated = translate::translate(&program);
+
+            match out {
+                Some(path) => fs::write(path, translated)?,
+                None => print!("{translated}"),
+            }
+
+            Ok(0)
+        }
+        cli::Commands::Format {
+            source,
+            indent,
+            comment_char,
+            out,
+            max_source_size,
+        } => {
+            let program = read_source(&source, max_source_size)?;
+            let formatted = format::format(&program, indent, comment_char);
+
+            match out {
+                Some(path) => fs::write(path, formatted)?,
+                None => print!("{formatted}"),
+            }
+
+            Ok(0)
+        }
+        cli::Commands::Disassemble {
+            file,
+            header,
+            constants,
+            max_source_size,
+        } => {
+            if header {
+                println!("{}", Chunk::header(&fs::read(&file)?)?);
+                return Ok(0);
+            }
+
+            let chunk = if file.extension().is_some_and(|extension| extension == "px") {
+                let program = include::expand_limited(&file, max_source_size, DEFAULT_COMMENT)?;
+                parse(program, SourceOptions::default())?
+            } else {
+                Chunk::from_bytes(&fs::read(&file)?)?
             };
 
-            fs::write(file, bytes).expect("Failed to write bytecode.");
+            let name = file.display().to_string();
+            if constants {
+                print!("{}", debug::constants_to_string(&chunk, &name));
+            } else {
+                print!("{}", debug::disassemble_to_string(&chunk, &name));
+            }
+
+            Ok(0)
+        }
+        cli::Commands::Explain { opcode } => {
+            println!("{}", opcode.explain());
+            Ok(0)
+        }
+        cli::Commands::Debug {
+            source,
+            file,
+            compiled,
+        } => {
+            let chunk = get_chunk(source, file, compiled, SourceOptions::default())?;
+            debug(VM::new(chunk))?;
+            Ok(0)
+        }
+        cli::Commands::Replay { trace } => {
+            let steps = trace::parse(&fs::read_to_string(trace)?)?;
+            replay(Replay::new(steps))?;
+            Ok(0)
+        }
+        cli::Commands::Repl => {
+            repl()?;
+            Ok(0)
         }
+        cli::Commands::Deps {
+            source,
+            format,
+            comment_char,
+            max_source_size,
+        } => {
+            let graph = include::dependencies_limited(source, max_source_size, comment_char)?;
+
+            match format {
+                cli::GraphFormat::Tree => print!("{}", graph.tree()),
+                cli::GraphFormat::Dot => print!("{}", graph.dot()),
+            }
+
+            Ok(0)
+        }
+    }
+}
+
+fn write_executable(path: PathBuf, contents: String) -> Result<()> {
+    fs::write(&path, contents)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
     }
+
+    Ok(())
 }
 
-fn get_chunk(source: String, file: bool, compiled: bool) -> Result<Chunk, &'static str> {
+fn get_chunk(source: String, file: bool, compiled: bool, options: SourceOptions) -> Result<Chunk> {
     if compiled {
-        let bytes = fs::read(source).expect("Unable to read file.");
+        let bytes = fs::read(source)?;
+        Ok(Chunk::from_bytes(&bytes)?)
+    } else {
+        let program = get_program(source, file, options)?;
+        parse(program, options)
+    }
+}
+
+/// Whether the chunk was compiled from other source than the current
+/// contents of the file. Chunks without a source hash are always stale.
+fn is_stale(chunk: &Chunk, source: &Path, options: SourceOptions) -> Result<bool> {
+    let program = include::expand_limited(source, options.max_size, options.comment_char)?;
 
-        match Chunk::from_bytes(&bytes) {
-            Ok(chunk) => Ok(chunk),
-            Err(_) => Err("Failed to load chunk from binary data."),
+    Ok(chunk.source_hash != Some(chunk::source_hash(&program)))
+}
+
+fn debug(vm: VM) -> Result<()> {
+    let mut debugger = Debugger::new(vm);
+    println!("{}", debugger.execute(Command::Where)?);
+
+    loop {
+        print!("(pxdb) ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
         }
+
+        let command = match line.parse::<Command>() {
+            Ok(Command::Quit) => return Ok(()),
+            Ok(command) => command,
+            Err(message) => {
+                eprintln!("{message}");
+                continue;
+            }
+        };
+
+        match debugger.execute(command) {
+            Ok(text) => println!("{text}"),
+            Err(error) => eprintln!("{error}"),
+        }
+    }
+}
+
+fn replay(mut replay: Replay) -> Result<()> {
+    println!("{}", replay.execute(trace::Command::Where));
+
+    loop {
+        print!("(pxreplay) ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        match line.parse::<trace::Command>() {
+            Ok(trace::Command::Quit) => return Ok(()),
+            Ok(command) => println!("{}", replay.execute(command)),
+            Err(message) => eprintln!("{message}"),
+        }
+    }
+}
+
+fn repl() -> Result<()> {
+    let mut repl = Repl::new(Box::new(io::stdout()));
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        let line = line.trim_end();
+        let result = match repl::Command::parse(line) {
+            Some(repl::Command::Tape) => {
+                print!("{}", repl.tape());
+                Ok(())
+            }
+            Some(repl::Command::Save("")) => {
+                eprintln!("Expect a path after ':save'.");
+                Ok(())
+            }
+            Some(repl::Command::Save(path)) => repl.save(path),
+            Some(repl::Command::Quit) => return Ok(()),
+            None => repl.eval(line),
+        };
+
+        match result {
+            // Compile errors were already reported by the parser.
+            Err(Error::Compile) => (),
+            Err(error) => eprintln!("{error}"),
+            Ok(()) => println!(),
+        }
+    }
+}
+
+fn run_test(case: &expect::Case, timeout: Duration) -> Result<Option<expect::Mismatch>> {
+    let chunk = parse(include::expand(&case.program)?, SourceOptions::default())?;
+    let input: Box<dyn io::Read> = match &case.input {
+        Some(path) => Box::new(fs::File::open(path)?),
+        None => Box::new(io::empty()),
+    };
+
+    expect::check(chunk, input, &fs::read(&case.expected)?, timeout)
+}
+
+/// Run every test case of the directory and print a summary. Cases running
+/// for longer than the timeout fail.
+fn test_suite(dir: &Path, timeout: Duration) -> Result<i32> {
+    let cases = expect::discover(dir)?;
+    let mut failed = 0;
+
+    for case in &cases {
+        let failure = match run_test(case, timeout) {
+            Ok(None) => None,
+            Ok(Some(mismatch)) => Some(mismatch.to_string()),
+            Err(error) => Some(error.to_string()),
+        };
+
+        match failure {
+            None => println!("ok: {}", case.program.display()),
+            Some(report) => {
+                failed += 1;
+                println!("FAILED: {}\n{report}", case.program.display());
+            }
+        }
+    }
+
+    println!(
+        "\n{} passed, {failed} failed, {} total",
+        cases.len() - failed,
+        cases.len()
+    );
+
+    if failed > 0 {
+        Ok(TEST_FAILED)
+    } else {
+        Ok(0)
+    }
+}
+
+fn get_tape(backing: cli::TapeBacking) -> Result<Box<dyn Tape>> {
+    match backing {
+        cli::TapeBacking::Memory => Ok(Box::new(VecTape::new())),
+        #[cfg(unix)]
+        cli::TapeBacking::Mmap(path) => Ok(Box::new(MmapTape::open(path)?)),
+        #[cfg(not(unix))]
+        cli::TapeBacking::Mmap(_) => Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "memory mapped tapes are only supported on unix",
+        ))),
+    }
+}
+
+/// Path next to the source file with the given extension, or `None` if
+/// the source path does not name a file, like `.` or `..`.
+fn default_out(source: &Path, extension: &str) -> Option<PathBuf> {
+    let parent = source.parent()?;
+    let stem = source.file_stem()?.to_string_lossy();
+
+    Some(parent.join(format!("{stem}.{extension}")))
+}
+
+/// Read the file, or stdin for '-', without expanding its includes.
+fn read_source(source: &Path, max_source_size: u64) -> Result<String> {
+    if source == Path::new(STDIN_SOURCE) {
+        include::read_reader(io::stdin().lock(), max_source_size)
     } else {
-        let program = get_program(source, file);
-        parse(program)
+        include::read_limited(source, max_source_size)
     }
 }
 
-fn get_program(source: String, file: bool) -> String {
-    if file {
-        fs::read_to_string(source).expect("Unable to read file.")
+fn get_program(source: String, file: bool, options: SourceOptions) -> Result<String> {
+    if file && source == STDIN_SOURCE {
+        include::expand_reader(io::stdin().lock(), options.max_size, options.comment_char)
+    } else if file {
+        include::expand_limited(source, options.max_size, options.comment_char)
     } else {
-        source
+        Ok(source)
     }
 }
 
-fn parse(program: String) -> Result<Chunk, &'static str> {
+fn parse(program: String, options: SourceOptions) -> Result<Chunk> {
     let mut chunk = Chunk::new();
+    chunk.source_hash = Some(chunk::source_hash(&program));
+
+    let scanner = Scanner::new(program.as_str())
+        .bf_compat(options.bf_compat)
+        .comment_char(options.comment_char);
+    let mut parser = parser::Parser::new(scanner, &mut chunk)
+        .intrinsics(options.intrinsics)
+        .cell_width(options.cell_width)
+        .optimize(options.optimize)
+        .wrapping(options.wrapping)
+        .dump_bytecode(options.dump_bytecode)
+        .report_to(io::stderr());
 
-    let scanner = Scanner::new(program.as_str());
-    let success = parser::Parser::new(scanner, &mut chunk).compile();
+    let success = parser.compile();
+    drop(parser);
 
     if success {
         Ok(chunk)
     } else {
-        Err("Compilation failed")
+        Err(Error::Compile)
     }
 }
 
-fn run(chunk: Chunk) {
-    let mut vm = VM::new(chunk);
-    vm.run();
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    use super::*;
+    use paroxy::{opcode::OpCode, tape::DEFAULT_MAX_TAPE_SIZE};
+
+    const BF_COMPAT: SourceOptions = SourceOptions {
+        bf_compat: true,
+        comment_char: DEFAULT_COMMENT,
+        optimize: false,
+        wrapping: false,
+        intrinsics: Intrinsics::all(),
+        cell_width: CellWidth::Eight,
+        dump_bytecode: false,
+        max_size: DEFAULT_MAX_SOURCE_SIZE,
+    };
+
+    /// Output writer whose contents stay readable after handing it to the vm.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_captured(chunk: Chunk) -> String {
+        let output = SharedOutput::default();
+        VM::with_output(chunk, Box::new(output.clone()))
+            .run()
+            .unwrap();
+
+        let bytes = output.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn should_report_io_error_for_missing_file() {
+        let result = get_program(
+            String::from("does/not/exist.px"),
+            true,
+            SourceOptions::default(),
+        );
+
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn should_report_io_error_for_missing_compiled_file() {
+        let result = get_chunk(
+            String::from("does/not/exist.pxb"),
+            true,
+            true,
+            SourceOptions::default(),
+        );
+
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn should_report_compile_error() {
+        let result = parse(String::from("#300"), SourceOptions::default());
+
+        assert!(matches!(result, Err(Error::Compile)));
+    }
+
+    #[test]
+    fn should_report_runtime_error() {
+        let chunk = parse(String::from("<5"), SourceOptions::default()).unwrap();
+
+        assert!(matches!(VM::new(chunk).run(), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_map_failures_to_exit_codes() {
+        let runtime = VM::new(parse(String::from("<5"), SourceOptions::default()).unwrap())
+            .run()
+            .unwrap_err();
+        let compile = parse(String::from("#300"), SourceOptions::default()).unwrap_err();
+        let io = get_chunk(
+            String::from("missing.pxb"),
+            true,
+            true,
+            SourceOptions::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(runtime.exit_code(), 1);
+        assert_eq!(compile.exit_code(), 2);
+        assert_eq!(io.exit_code(), 3);
+    }
+
+    /// The source options of the commands when none are given.
+    fn source_args() -> cli::SourceArgs {
+        cli::SourceArgs {
+            bf_compat: false,
+            comment_char: DEFAULT_COMMENT,
+            max_source_size: DEFAULT_MAX_SOURCE_SIZE,
+            optimize: false,
+            no_optimize: false,
+            allow_intrinsics: None,
+            deny_intrinsics: None,
+        }
+    }
+
+    /// The run command with the default options.
+    fn run_command(source: &str) -> cli::Commands {
+        cli::Commands::Run {
+            source: String::from(source),
+            others: vec![],
+            interleave: false,
+            file: false,
+            compiled: false,
+            source_args: source_args(),
+            bf_strict: false,
+            cell_width: CellWidth::Eight,
+            dump_bytecode: false,
+            profile_loops: false,
+            profile: false,
+            coverage: false,
+            count_only: false,
+            ensure_newline: false,
+            run_steps: None,
+            snapshot_out: None,
+            resume: None,
+            max_steps: None,
+            timeout: None,
+            sandbox: false,
+            stop_on_output: None,
+            dump_tape: None,
+            print_final_cell: None,
+            redefine_tape: cli::RedefineTape::Preserve,
+            eof: cli::Eof::Zero,
+            tape_backing: cli::TapeBacking::Memory,
+            growable: false,
+            max_tape_size: DEFAULT_MAX_TAPE_SIZE,
+            trace: None,
+            trace_execution: false,
+            check_source: None,
+            strict: false,
+        }
+    }
+
+    fn check_command(source: &str) -> cli::Commands {
+        cli::Commands::Check {
+            source: String::from(source),
+            file: false,
+            source_args: source_args(),
+            cell_width: CellWidth::Eight,
+        }
+    }
+
+    #[test]
+    fn should_only_check_for_compile_errors() {
+        assert!(matches!(
+            execute(check_command("{4}+[>+<-]$halt(3)")),
+            Ok(0)
+        ));
+        assert!(matches!(execute(check_command("<5")), Ok(0)));
+        assert!(matches!(
+            execute(check_command("#300+256")),
+            Ok(CHECK_FAILED)
+        ));
+    }
+
+    #[test]
+    fn should_exit_with_halt_code() {
+        assert!(matches!(execute(run_command("$halt(7)")), Ok(7)));
+    }
+
+    #[test]
+    fn should_apply_run_options_to_interleaved_programs() {
+        let interleaved = |others: &[&str], growable| {
+            let mut command = run_command("$yield$halt(0)");
+            if let cli::Commands::Run {
+                others: o,
+                interleave: i,
+                growable: g,
+                ..
+            } = &mut command
+            {
+                *o = others.iter().map(|other| String::from(*other)).collect();
+                *i = true;
+                *g = growable;
+            }
+
+            execute(command)
+        };
+
+        assert!(matches!(
+            interleaved(&["$halt(5)", "$halt(6)"], false),
+            Ok(5)
+        ));
+        assert!(interleaved(&["{2}>>>+"], false).is_err());
+        assert!(matches!(interleaved(&["{2}>>>+$halt(3)"], true), Ok(3)));
+
+        // Reports about a single run cannot be combined with interleaving.
+        let parsed = cli::Cli::try_parse_from([
+            "paroxy-rs",
+            "run",
+            "--interleave",
+            "--count-only",
+            "+",
+            "-",
+        ]);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn should_grow_tape_only_when_growable() {
+        let source = "{2}>>>+$halt(3)";
+        let growable = |max_size| {
+            let mut command = run_command(source);
+            if let cli::Commands::Run {
+                growable,
+                max_tape_size,
+                ..
+            } = &mut command
+            {
+                *growable = true;
+                *max_tape_size = max_size;
+            }
+            command
+        };
+
+        assert!(matches!(
+            execute(run_command(source)),
+            Err(Error::Runtime { .. })
+        ));
+        assert!(matches!(execute(growable(8)), Ok(3)));
+        assert!(matches!(execute(growable(3)), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_run_brainfuck_unchanged_in_bf_strict() {
+        // Outside strict mode '-' underflows and '#7' writes the cell.
+        let source = "-#7$halt(1)";
+        let strict = || {
+            let mut command = run_command(source);
+            if let cli::Commands::Run { bf_strict, .. } = &mut command {
+                *bf_strict = true;
+            }
+            execute(command)
+        };
+
+        assert!(matches!(
+            execute(run_command(source)),
+            Err(Error::Runtime { .. })
+        ));
+        assert!(matches!(strict(), Ok(0)));
+        assert!(
+            cli::Cli::try_parse_from(["paroxy-rs", "run", "+", "--bf-strict", "--eof", "ff"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn should_stop_runaway_programs_at_max_steps() {
+        let limited = |source, limit| {
+            let mut command = run_command(source);
+            if let cli::Commands::Run { max_steps, .. } = &mut command {
+                *max_steps = Some(limit);
+            }
+            execute(command)
+        };
+
+        assert!(matches!(
+            limited("+[]", 1000),
+            Err(Error::Runtime {
+                error: RuntimeError::StepLimitExceeded(1000),
+                ..
+            })
+        ));
+        assert!(matches!(limited("$halt(4)", 1000), Ok(4)));
+    }
+
+    #[test]
+    fn should_require_file_for_compiled_chunks() {
+        for command in ["run", "debug"] {
+            let parse = |args: &[&str]| {
+                cli::Cli::try_parse_from([&["paroxy-rs", command, "main.pxb"], args].concat())
+            };
+
+            assert!(parse(&["--compiled"]).is_err());
+            assert!(parse(&["--compiled", "--file"]).is_ok());
+        }
+    }
+
+    #[test]
+    fn should_fail_to_compile_raw_source_without_out() {
+        let cli = cli::Cli::try_parse_from(["paroxy-rs", "compile", "+"]).unwrap();
+
+        assert_eq!(execute(cli.command).unwrap(), USAGE_ERROR);
+    }
+
+    #[test]
+    fn should_fail_to_compile_to_default_out_without_file_name() {
+        for source in [".", "..", "scripts/.."] {
+            let cli = cli::Cli::try_parse_from(["paroxy-rs", "compile", "--file", source]).unwrap();
+
+            assert_eq!(execute(cli.command).unwrap(), USAGE_ERROR);
+        }
+
+        assert_eq!(
+            default_out(Path::new("scripts/hello.px"), "pxb"),
+            Some(PathBuf::from("scripts/hello.pxb"))
+        );
+    }
+
+    #[test]
+    fn should_limit_source_size_of_every_command() {
+        let translate = cli::Commands::Translate {
+            source: PathBuf::from("scripts/hello_world.bf"),
+            out: None,
+            max_source_size: 16,
+        };
+        let format = cli::Commands::Format {
+            source: PathBuf::from("scripts/main.px"),
+            indent: format::Indent::Spaces(2),
+            comment_char: DEFAULT_COMMENT,
+            out: None,
+            max_source_size: 1,
+        };
+
+        for command in [translate, format] {
+            assert!(matches!(
+                execute(command),
+                Err(Error::SourceTooLarge { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn should_fail_to_assemble_stdin_without_out() {
+        let command = cli::Commands::Assemble {
+            source: PathBuf::from(STDIN_SOURCE),
+            out: None,
+            max_source_size: DEFAULT_MAX_SOURCE_SIZE,
+        };
+
+        assert_eq!(execute(command).unwrap(), USAGE_ERROR);
+    }
+
+    #[test]
+    fn should_fail_to_test_single_program_without_expect() {
+        let command = cli::Commands::Test {
+            program: PathBuf::from("scripts/hello.px"),
+            input: None,
+            expect: None,
+            timeout: Duration::from_secs(10),
+        };
+
+        assert_eq!(execute(command).unwrap(), USAGE_ERROR);
+    }
+
+    #[test]
+    fn should_fail_tests_running_past_the_timeout() {
+        let dir = std::env::temp_dir().join(format!("paroxy-timeout-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("loop.px"), "+[]").unwrap();
+        fs::write(dir.join("loop.expected"), "").unwrap();
+
+        let code = test_suite(&dir, Duration::ZERO);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(code.unwrap(), TEST_FAILED);
+    }
+
+    #[test]
+    fn should_parse_timeouts() {
+        let timeout =
+            |value| match cli::Cli::try_parse_from(["paroxy-rs", "run", "+", "--timeout", value]) {
+                Ok(cli::Cli {
+                    command: cli::Commands::Run { timeout, .. },
+                }) => timeout,
+                result => panic!("expected a run command, got {result:?}"),
+            };
+
+        assert_eq!(timeout("2"), Some(Duration::from_secs(2)));
+        assert_eq!(timeout("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(timeout("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(timeout("250ms"), Some(Duration::from_millis(250)));
+        assert!(cli::Cli::try_parse_from(["paroxy-rs", "run", "+", "--timeout", "2h"]).is_err());
+        assert!(cli::Cli::try_parse_from(["paroxy-rs", "run", "+", "--timeout", "-1s"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_dump_tape_ranges() {
+        let dump_tape = |args: &[&str]| match cli::Cli::try_parse_from(
+            ["paroxy-rs", "run", "+"].iter().chain(args),
+        ) {
+            Ok(cli::Cli {
+                command: cli::Commands::Run { dump_tape, .. },
+            }) => dump_tape,
+            result => panic!("expected a run command, got {result:?}"),
+        };
+
+        assert_eq!(dump_tape(&[]), None);
+        assert_eq!(dump_tape(&["--dump-tape"]), Some(None));
+        assert_eq!(dump_tape(&["--dump-tape=0..32"]), Some(Some(0..32)));
+        assert_eq!(dump_tape(&["--dump-tape=16.."]), Some(Some(16..usize::MAX)));
+        assert!(cli::Cli::try_parse_from(["paroxy-rs", "run", "+", "--dump-tape=8..4"]).is_err());
+        assert!(cli::Cli::try_parse_from(["paroxy-rs", "run", "+", "--dump-tape=8"]).is_err());
+    }
+
+    #[test]
+    fn should_only_optimize_with_optimize_flag() {
+        let clears = |options| {
+            let chunk = parse(String::from("+[-]"), options).unwrap();
+            chunk.code.contains(&(OpCode::ClearCell as u8))
+        };
+
+        assert!(!clears(SourceOptions::default()));
+        assert!(clears(SourceOptions {
+            optimize: true,
+            ..SourceOptions::default()
+        }));
+
+        for command in ["run", "compile", "check"] {
+            let parse = |args: &[&str]| {
+                cli::Cli::try_parse_from([&["paroxy-rs", command, "+"], args].concat())
+            };
+            assert!(parse(&["-O"]).is_ok());
+            assert!(parse(&["--optimize", "--no-optimize"]).is_err());
+        }
+    }
+
+    #[test]
+    fn should_fail_to_compile_denied_intrinsic() {
+        let options = SourceOptions {
+            intrinsics: cli::intrinsics(None, Some(vec![String::from("env")])),
+            ..SourceOptions::default()
+        };
+
+        assert!(parse(String::from("$dup"), options).is_ok());
+        assert!(matches!(
+            parse(String::from("$env('HOME', 0, 1)"), options),
+            Err(Error::Compile)
+        ));
+    }
+
+    #[test]
+    fn should_run_commented_brainfuck_in_bf_compat() {
+        let program = get_program(
+            String::from("scripts/hello_world.bf"),
+            true,
+            SourceOptions::default(),
+        )
+        .unwrap();
+        let chunk = parse(program, BF_COMPAT).unwrap();
+
+        assert_eq!(run_captured(chunk), "Hello World!\n");
+    }
+
+    #[test]
+    fn should_count_the_bytes_a_real_run_prints() {
+        let program = get_program(
+            String::from("scripts/hello_world.bf"),
+            true,
+            SourceOptions::default(),
+        )
+        .unwrap();
+        let expected = run_captured(parse(program.clone(), BF_COMPAT).unwrap());
+
+        let counter = CountingSink::new();
+        VM::with_output(
+            parse(program, BF_COMPAT).unwrap(),
+            Box::new(counter.clone()),
+        )
+        .run()
+        .unwrap();
+
+        assert_eq!(counter.count(), expected.len() as u64);
+    }
+
+    #[test]
+    fn should_translate_brainfuck_faithfully() {
+        let program = get_program(
+            String::from("scripts/hello_world.bf"),
+            true,
+            SourceOptions::default(),
+        )
+        .unwrap();
+
+        let original = run_captured(parse(program.clone(), BF_COMPAT).unwrap());
+        let translated =
+            run_captured(parse(translate::translate(&program), SourceOptions::default()).unwrap());
+
+        assert_eq!(original, "Hello World!\n");
+        assert_eq!(translated, original);
+    }
+
+    #[test]
+    fn should_detect_stale_chunks() {
+        let source = Path::new("scripts/main.px");
+        let program = get_program(
+            String::from("scripts/main.px"),
+            true,
+            SourceOptions::default(),
+        )
+        .unwrap();
+
+        let fresh = parse(program, SourceOptions::default()).unwrap();
+        let stale = parse(String::from("+"), SourceOptions::default()).unwrap();
+
+        assert!(!is_stale(&fresh, source, SourceOptions::default()).unwrap());
+        assert!(is_stale(&stale, source, SourceOptions::default()).unwrap());
+        assert!(is_stale(&Chunk::new(), source, SourceOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn should_format_final_cell() {
+        assert_eq!(cli::CellFormat::Dec.format(42), "42");
+        assert_eq!(cli::CellFormat::Char.format('*' as Cell), "'*'");
+        assert_eq!(cli::CellFormat::Char.format('\n' as Cell), "'\\n'");
+        assert_eq!(cli::CellFormat::Char.format('ā' as Cell), "'\\u{101}'");
+        assert_eq!(cli::CellFormat::Hex.format(10), "0x0a");
+    }
 }
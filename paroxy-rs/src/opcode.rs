@@ -7,6 +7,11 @@ macro_rules! back_to_enum {
             $($(#[$vmeta])* $vname $(= $val)?,)*
         }
 
+        impl $name {
+            /// Every variant, in order of their byte values.
+            pub const ALL: &'static [$name] = &[$($name::$vname,)*];
+        }
+
         impl std::convert::TryFrom<u8> for $name {
             type Error = u8;
 
@@ -22,7 +27,7 @@ macro_rules! back_to_enum {
 
 back_to_enum!(
     #[repr(u8)]
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum OpCode {
         /// Creates and adds the tape where the
         /// state is stored into the stack.
@@ -92,11 +97,454 @@ back_to_enum!(
 
         /// Return the stack value.
         Return,
+
+        /// Clear the target cell if the flag cell is not zero.
+        /// Both indexes are taken from the stack.
+        ClearIf,
+
+        /// Copy the current cell into the cell to its right.
+        DupRight,
+
+        /// Sum a range of cells into a 32 bit big endian value written
+        /// to the current cell and the three to its right. The start
+        /// index and length of the range are taken from the stack.
+        Checksum,
+
+        /// Write the index of the first cell equal to a value, relative to
+        /// the start of a range, into a result cell. Writes 255 if no cell
+        /// matches. The start index, length (at most 255), value and result
+        /// index are taken from the stack.
+        FindByte,
+
+        /// Stop the program with the exit code taken from the stack.
+        Halt,
+
+        /// Write the transpose of a row major block of cells to a
+        /// destination, which may be the block itself. The start index,
+        /// width, height and destination index are taken from the stack.
+        Transpose,
+
+        /// Write the bytes of an environment variable, followed by a zero
+        /// cell, to the tape and its length into a cell. The variable name,
+        /// start index and length index are taken from the stack.
+        ReadEnv,
+
+        /// Write the next input byte to the current cell without
+        /// consuming it, so the following input reads it again.
+        PeekInput,
+
+        /// Reverse a range of cells in place. The start index and length
+        /// are taken from the stack.
+        ReverseRange,
+
+        /// Let other interleaved programs run. Does nothing when the
+        /// program runs alone.
+        Yield,
+
+        /// Sort a range of cells in place, ascending unless bit 0 of the
+        /// operand is set. The start index and length are taken from the stack.
+        SortRange,
+
+        /// Add the value taken from the stack to the current cell. Emitted
+        /// for amounts too large for [`OpCode::Increment`].
+        IncrementBy,
+
+        /// Subtract the value taken from the stack from the current cell.
+        /// Emitted for amounts too large for [`OpCode::Decrement`].
+        DecrementBy,
+
+        /// Replace the current cell with the value taken from the stack.
+        /// Emitted for values too large for [`OpCode::WriteCell`].
+        WriteValue,
+
+        /// Adds the constant in the defined position to the stack. Emitted
+        /// for constants past the first 256 of the chunk.
+        Constant16,
+
+        /// Jump to the defined place if current cell is zero. Emitted for
+        /// loop bodies too large for [`OpCode::JumpIfZero`].
+        JumpIfZeroWide,
+
+        /// Jump to the defined place (usually before this instruction).
+        /// Emitted for loop bodies too large for [`OpCode::Loop`].
+        LoopWide,
+
+        /// Output the current cell as a decimal number.
+        PrintNumber,
+
+        /// Read a whitespace delimited decimal number from the input
+        /// into the current cell.
+        InputNumber,
+
+        /// Set the current cell to zero. Emitted for `[-]` loops.
+        ClearCell,
+
+        /// Move the pointer right until it is on a zero cell. Emitted for
+        /// `[>]` loops.
+        ScanRight,
+
+        /// Move the pointer left until it is on a zero cell. Emitted for
+        /// `[<]` loops.
+        ScanLeft,
+
+        /// Continue at the offset taken from the stack, remembering the
+        /// next instruction for [`OpCode::Return`] to come back to.
+        Call,
     }
 );
 
-impl Into<u8> for OpCode {
-    fn into(self) -> u8 {
-        self as u8
+impl OpCode {
+    /// Number of bytes the instruction takes up in the chunk,
+    /// including its operands.
+    pub fn length(&self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::Increment
+            | OpCode::Decrement
+            | OpCode::WriteCell
+            | OpCode::MultiInput
+            | OpCode::SortRange => 2,
+            OpCode::JumpIfZero | OpCode::Loop | OpCode::Constant16 => 3,
+            OpCode::JumpIfZeroWide | OpCode::LoopWide => 5,
+            _ => 1,
+        }
+    }
+}
+
+/// Reference documentation of an opcode.
+#[derive(Debug)]
+pub struct Info {
+    pub summary: &'static str,
+
+    /// Layout of the operand bytes following the opcode.
+    pub operands: &'static str,
+
+    /// Values taken from or pushed to the stack.
+    pub stack: &'static str,
+
+    /// Source producing the opcode, if the compiler emits it.
+    pub example: Option<&'static str>,
+}
+
+impl OpCode {
+    /// Find an opcode by its name, ignoring case, or by its byte value.
+    pub fn lookup(name: &str) -> Option<OpCode> {
+        if let Ok(byte) = name.parse::<u8>() {
+            return OpCode::try_from(byte).ok();
+        }
+
+        OpCode::ALL
+            .iter()
+            .find(|code| format!("{code:?}").eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    pub fn info(&self) -> Info {
+        let (summary, operands, stack, example) = match self {
+            OpCode::DefineTape => (
+                "Resize the tape to the given number of cells.",
+                "none",
+                "pops the size",
+                Some("{100}"),
+            ),
+            OpCode::PointerValue => (
+                "Push the value of the current cell.",
+                "none",
+                "pushes the cell value",
+                None,
+            ),
+            OpCode::SetPointer => (
+                "Move the pointer to the given cell index.",
+                "none",
+                "pops the index",
+                Some("@5"),
+            ),
+            OpCode::Constant => (
+                "Push a value of the constant table.",
+                "1 byte, index of the constant",
+                "pushes the constant",
+                Some("{100}"),
+            ),
+            OpCode::MoveLeft => (
+                "Move the pointer to the left by the given number of cells.",
+                "none",
+                "pops the count",
+                Some("<5"),
+            ),
+            OpCode::MoveRight => (
+                "Move the pointer to the right by the given number of cells.",
+                "none",
+                "pops the count",
+                Some(">5"),
+            ),
+            OpCode::ShiftLeft => (
+                "Move the pointer one cell to the left.",
+                "none",
+                "none",
+                Some("<"),
+            ),
+            OpCode::ShiftRight => (
+                "Move the pointer one cell to the right.",
+                "none",
+                "none",
+                Some(">"),
+            ),
+            OpCode::Increment => (
+                "Add to the current cell.",
+                "1 byte, amount to add",
+                "none",
+                Some("+5"),
+            ),
+            OpCode::Decrement => (
+                "Subtract from the current cell.",
+                "1 byte, amount to subtract",
+                "none",
+                Some("-5"),
+            ),
+            OpCode::IncrementSingular => {
+                ("Add one to the current cell.", "none", "none", Some("+"))
+            }
+            OpCode::DecrementSingular => (
+                "Subtract one from the current cell.",
+                "none",
+                "none",
+                Some("-"),
+            ),
+            OpCode::WriteString => (
+                "Write a string to the tape starting at the current cell.",
+                "none",
+                "pops the string",
+                Some("'hi'"),
+            ),
+            OpCode::WriteCell => (
+                "Replace the value of the current cell.",
+                "1 byte, the new value",
+                "none",
+                Some("#65"),
+            ),
+            OpCode::Print => ("Output the current cell.", "none", "none", Some(".")),
+            OpCode::PrintRange => (
+                "Output the given number of cells starting at the current cell.",
+                "none",
+                "pops the count",
+                Some(".5"),
+            ),
+            OpCode::Input => (
+                "Read the first character of an input line into the current cell.",
+                "none",
+                "none",
+                Some(","),
+            ),
+            OpCode::MultiInput => (
+                "Read an input line into the cells starting at the current cell.",
+                "1 byte, flags (bit 0 moves the pointer past the input)",
+                "none",
+                Some(",*^"),
+            ),
+            OpCode::JumpIfZero => (
+                "Jump forward if the current cell is zero.",
+                "2 bytes, big endian offset from the next instruction",
+                "none",
+                Some("["),
+            ),
+            OpCode::Loop => (
+                "Jump backward to the start of the loop.",
+                "2 bytes, big endian offset back from the next instruction",
+                "none",
+                Some("]"),
+            ),
+            OpCode::Pop => (
+                "Discard the last value of the stack.",
+                "none",
+                "pops a value",
+                None,
+            ),
+            OpCode::Return => (
+                "Return from the innermost call, or stop the program outside of one.",
+                "none",
+                "none",
+                Some("(end of every program and subroutine)"),
+            ),
+            OpCode::ClearIf => (
+                "Clear the target cell if the flag cell is not zero.",
+                "none",
+                "pops the target and flag indexes",
+                Some("$clearif(0, 1)"),
+            ),
+            OpCode::DupRight => (
+                "Copy the current cell into the cell to its right.",
+                "none",
+                "none",
+                Some("$dup"),
+            ),
+            OpCode::Checksum => (
+                "Write the 32 bit sum of a range big endian into the current and next three cells.",
+                "none",
+                "pops the length and start index",
+                Some("$checksum(0, 10)"),
+            ),
+            OpCode::FindByte => (
+                "Write the relative index of a value in a range, or 255, into a cell.",
+                "none",
+                "pops the result index, value, length and start index",
+                Some("$find(0, 10, 32, 11)"),
+            ),
+            OpCode::Halt => (
+                "Stop the program with an exit code.",
+                "none",
+                "pops the exit code",
+                Some("$halt(1)"),
+            ),
+            OpCode::Transpose => (
+                "Write the transpose of a block of cells to a destination.",
+                "none",
+                "pops the destination, height, width and start index",
+                Some("$transpose(0, 3, 2, 6)"),
+            ),
+            OpCode::ReadEnv => (
+                "Write an environment variable and its length to the tape.",
+                "none",
+                "pops the length index, start index and variable name",
+                Some("$env('HOME', 0, 32)"),
+            ),
+            OpCode::PeekInput => (
+                "Write the next input byte to the current cell without consuming it.",
+                "none",
+                "none",
+                Some(",?"),
+            ),
+            OpCode::ReverseRange => (
+                "Reverse a range of cells in place.",
+                "none",
+                "pops the length and start index",
+                Some("$reverse(0, 5)"),
+            ),
+            OpCode::Yield => (
+                "Let other interleaved programs run.",
+                "none",
+                "none",
+                Some("$yield"),
+            ),
+            OpCode::SortRange => (
+                "Sort a range of cells in place.",
+                "1 byte, flags (bit 0 sorts descending)",
+                "pops the length and start index",
+                Some("$sort(0, 5, 1)"),
+            ),
+            OpCode::IncrementBy => (
+                "Add to the current cell an amount larger than a byte.",
+                "none",
+                "pops the amount",
+                Some("+300 (with 16 bit cells)"),
+            ),
+            OpCode::DecrementBy => (
+                "Subtract from the current cell an amount larger than a byte.",
+                "none",
+                "pops the amount",
+                Some("-300 (with 16 bit cells)"),
+            ),
+            OpCode::WriteValue => (
+                "Replace the value of the current cell with a value larger than a byte.",
+                "none",
+                "pops the new value",
+                Some("#300 (with 16 bit cells)"),
+            ),
+            OpCode::Constant16 => (
+                "Push a value of the constant table past the first 256.",
+                "2 bytes, big endian index of the constant",
+                "pushes the constant",
+                None,
+            ),
+            OpCode::JumpIfZeroWide => (
+                "Jump forward if the current cell is zero, over a large loop body.",
+                "4 bytes, big endian offset from the next instruction",
+                "none",
+                None,
+            ),
+            OpCode::LoopWide => (
+                "Jump backward to the start of a large loop.",
+                "4 bytes, big endian offset back from the next instruction",
+                "none",
+                None,
+            ),
+            OpCode::PrintNumber => (
+                "Output the current cell as a decimal number.",
+                "none",
+                "none",
+                Some(":"),
+            ),
+            OpCode::InputNumber => (
+                "Read a decimal number from the input into the current cell.",
+                "none",
+                "none",
+                Some(",#"),
+            ),
+            OpCode::ClearCell => ("Set the current cell to zero.", "none", "none", Some("[-]")),
+            OpCode::ScanRight => (
+                "Move the pointer right to the nearest zero cell.",
+                "none",
+                "none",
+                Some("[>]"),
+            ),
+            OpCode::ScanLeft => (
+                "Move the pointer left to the nearest zero cell.",
+                "none",
+                "none",
+                Some("[<]"),
+            ),
+            OpCode::Call => (
+                "Call the subroutine starting at the given offset.",
+                "none",
+                "pops the offset",
+                Some("!name()"),
+            ),
+        };
+
+        Info {
+            summary,
+            operands,
+            stack,
+            example,
+        }
+    }
+
+    /// Describe the opcode for the `explain` command.
+    pub fn explain(&self) -> String {
+        let info = self.info();
+
+        format!(
+            "{self:?} (byte {})\n  {}\n  operands: {}\n  stack:    {}\n  example:  {}",
+            *self as u8,
+            info.summary,
+            info.operands,
+            info.stack,
+            info.example.unwrap_or("not emitted by the compiler"),
+        )
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(code: OpCode) -> Self {
+        code as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_list_every_opcode_by_byte() {
+        for (byte, code) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(OpCode::try_from(byte as u8), Ok(*code));
+        }
+    }
+
+    #[test]
+    fn should_look_up_by_name_or_byte() {
+        assert_eq!(OpCode::lookup("increment"), Some(OpCode::Increment));
+        assert_eq!(OpCode::lookup("8"), Some(OpCode::Increment));
+        assert_eq!(OpCode::lookup("Nope"), None);
+        assert_eq!(OpCode::lookup("255"), None);
     }
 }
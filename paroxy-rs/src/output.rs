@@ -0,0 +1,125 @@
+use std::{
+    cell::{Cell, RefCell},
+    io::Write,
+    rc::Rc,
+};
+
+/// Writer which discards everything written to it while counting the
+/// bytes. Clones share the same count.
+#[derive(Debug, Clone, Default)]
+pub struct CountingSink {
+    count: Rc<Cell<u64>>,
+}
+
+impl CountingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count.get()
+    }
+}
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.count.set(self.count.get() + buf.len() as u64);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer which keeps everything written to it in memory. Clones share
+/// the same buffer.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+
+    /// Bytes written since the last call, emptying the buffer.
+    pub fn take(&self) -> Vec<u8> {
+        self.bytes.take()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer which discards everything written to it but the last byte.
+/// Clones share the same byte.
+#[derive(Debug, Clone, Default)]
+pub struct LastByte {
+    last: Rc<Cell<Option<u8>>>,
+}
+
+impl LastByte {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last byte written, `None` if nothing was written yet.
+    pub fn get(&self) -> Option<u8> {
+        self.last.get()
+    }
+}
+
+impl Write for LastByte {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(byte) = buf.last() {
+            self.last.set(Some(*byte));
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer which passes everything written to it on to both writers.
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
@@ -1,9 +1,11 @@
-use std::{mem, rc::Rc};
+use std::{collections::HashMap, fmt::Display, io::Write, mem, rc::Rc};
 
 use crate::{
     chunk::{Chunk, Value},
-    debug::{disassemble_chunk, DEBUG_PRINT_CODE},
+    debug::disassemble_chunk,
+    intrinsic::Intrinsics,
     opcode::OpCode,
+    tape::CellWidth,
 };
 
 use super::{
@@ -11,6 +13,200 @@ use super::{
     token::{Token, TokenKind},
 };
 
+/// Jump operand emitted before the jump is patched, and kept when the
+/// jump is too large to be encoded.
+const JUMP_PLACEHOLDER: u16 = 0xffff;
+
+/// What kind of compile error a diagnostic reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A token other than the expected one, or one which cannot start an
+    /// expression.
+    UnexpectedToken,
+
+    /// The scanner could not read a token, like an unterminated string.
+    InvalidToken,
+
+    /// A string literal contains an unknown escape sequence.
+    InvalidEscape,
+
+    /// An integer does not fit where it is used.
+    IntegerOutOfRange,
+
+    /// A string literal contains a character above the largest value of
+    /// a cell.
+    CharacterOutOfRange,
+
+    /// An intrinsic with an unknown name.
+    UnknownIntrinsic,
+
+    /// An intrinsic the program may not use.
+    IntrinsicNotAllowed,
+
+    /// The chunk has no room for another constant.
+    TooManyConstants,
+
+    /// A loop body is too large for its jump offsets.
+    JumpTooLarge,
+
+    /// A call to a subroutine which is never defined.
+    UnknownSubroutine,
+
+    /// A subroutine defined a second time.
+    SubroutineRedefined,
+}
+
+/// A compile error reported by the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub line: usize,
+
+    /// Column of the offending token, counted in characters from 1.
+    pub column: usize,
+
+    /// The error was found at the end of the source.
+    pub at_end: bool,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}:{}] Error", self.line, self.column)?;
+        if self.at_end {
+            write!(f, " at end")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Why a source could not be compiled, with every diagnostic in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// The line of the token with its lexeme underlined, like:
+///
+/// ```text
+///   2 | +300
+///     |  ^^^
+/// ```
+fn source_context(scanner: &Scanner, token: &Token) -> String {
+    let text = scanner.line_text(token.line);
+    let gutter = token.line.to_string();
+    let blank = " ".repeat(gutter.len());
+
+    // Keep tabs so the carets line up with the text above.
+    let indent: String = text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(token.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    // Tokens spanning lines, like strings, are underlined to the end
+    // of their first line.
+    let width = scanner
+        .token_source(token)
+        .lines()
+        .next()
+        .map_or(0, |first| first.chars().count())
+        .max(1);
+
+    format!(
+        "  {gutter} | {text}\n  {blank} | {indent}{}\n",
+        "^".repeat(width)
+    )
+}
+
+/// Value of an integer literal, which may be hexadecimal with a `0x`
+/// prefix or binary with a `0b` prefix. `None` if it does not fit the type.
+fn parse_integer<T: TryFrom<u64>>(lexeme: &str) -> Option<T> {
+    let (digits, radix) = match lexeme.get(..2) {
+        Some("0x") => (&lexeme[2..], 16),
+        Some("0b") => (&lexeme[2..], 2),
+        _ => (lexeme, 10),
+    };
+
+    u64::from_str_radix(digits, radix).ok()?.try_into().ok()
+}
+
+/// Decode the escape sequences of a string literal without its quotes.
+fn unescape(literal: &str) -> std::result::Result<String, String> {
+    let mut value = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('r') => value.push('\r'),
+            Some('0') => value.push('\0'),
+            Some(c @ ('\\' | '"' | '\'')) => value.push(c),
+            Some(c) => {
+                return Err(format!(
+                    "Unknown escape sequence '\\{}'.",
+                    c.escape_default()
+                ))
+            }
+            None => return Err(String::from("Unterminated escape sequence.")),
+        }
+    }
+
+    Ok(value)
+}
+
+/// Compile the source into a new chunk, or collect the diagnostics of
+/// why it could not be compiled.
+pub fn compile(source: &str) -> std::result::Result<Chunk, Vec<Diagnostic>> {
+    let mut chunk = Chunk::new();
+
+    let mut parser = Parser::new(Scanner::new(source), &mut chunk);
+    if !parser.compile() {
+        return Err(mem::take(&mut parser.diagnostics));
+    }
+
+    drop(parser);
+    Ok(chunk)
+}
+
+/// A subroutine defined or called in the source. Bodies are compiled
+/// apart and appended to the chunk after the program, so they may be
+/// called before their definition.
+struct Subroutine {
+    name: String,
+
+    /// Constant holding the offset of the body, set once it is appended.
+    constant: u16,
+
+    /// The first call, reported if the subroutine is never defined.
+    call: Option<Token>,
+
+    /// Code and line table of the body, once defined.
+    body: Option<(Vec<u8>, Vec<usize>)>,
+}
+
 pub struct Parser<'a> {
     scanner: Scanner<'a>,
     chunk: &'a mut Chunk,
@@ -18,6 +214,19 @@ pub struct Parser<'a> {
     current: Token,
     had_error: bool,
     panic_mode: bool,
+    default_tape: bool,
+    optimize: bool,
+    wrapping: bool,
+    dump_bytecode: bool,
+    intrinsics: Intrinsics,
+    subroutines: Vec<Subroutine>,
+
+    /// Index of every constant made so far, to reuse equal ones. The
+    /// constants of subroutines are left out, their value changes.
+    constants: HashMap<Value, u16>,
+
+    diagnostics: Vec<Diagnostic>,
+    report: Option<Box<dyn Write + 'a>>,
 }
 
 impl<'a> Parser<'a> {
@@ -29,20 +238,87 @@ impl<'a> Parser<'a> {
             current: Token::empty(),
             had_error: false,
             panic_mode: false,
+            default_tape: true,
+            optimize: false,
+            wrapping: false,
+            dump_bytecode: false,
+            intrinsics: Intrinsics::all(),
+            subroutines: vec![],
+            constants: HashMap::new(),
+            diagnostics: vec![],
+            report: None,
         }
     }
 
+    /// Write every diagnostic to the given writer as it is reported.
+    /// Without one, diagnostics are only collected.
+    pub fn report_to<W: Write + 'a>(mut self, writer: W) -> Self {
+        self.report = Some(Box::new(writer));
+        self
+    }
+
+    /// The diagnostics reported so far, in order.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Do not define the default tape when the program does not define
+    /// one. Used when continuing on the tape of a previous chunk.
+    pub fn without_default_tape(mut self) -> Self {
+        self.default_tape = false;
+        self
+    }
+
+    /// Optimize the emitted instructions. Without optimizations, which is
+    /// the default, the instructions are emitted exactly as written in the
+    /// source, one for every operator.
+    pub fn optimize(mut self, enabled: bool) -> Self {
+        self.optimize = enabled;
+        self
+    }
+
+    /// Compile for a vm whose cells wrap around, which lets optimizations
+    /// treat `[+]` as clearing the cell like `[-]`.
+    pub fn wrapping(mut self, enabled: bool) -> Self {
+        self.wrapping = enabled;
+        self
+    }
+
+    /// Only allow the given intrinsics. Using any other one is a compile
+    /// error.
+    pub fn intrinsics(mut self, intrinsics: Intrinsics) -> Self {
+        self.intrinsics = intrinsics;
+        self
+    }
+
+    /// Print the disassembly of the chunk to stdout once it is compiled.
+    pub fn dump_bytecode(mut self, enabled: bool) -> Self {
+        self.dump_bytecode = enabled;
+        self
+    }
+
+    /// Compile for cells of the given width, which bounds the values
+    /// written by '+', '-' and '#'.
+    pub fn cell_width(self, width: CellWidth) -> Self {
+        self.chunk.cell_width = width;
+        self
+    }
+
     pub fn compile(&mut self) -> bool {
         self.advance();
 
         // Default tape definition
-        if self.current.kind != TokenKind::LeftBracket {
+        if self.default_tape && self.current.kind != TokenKind::LeftBrace {
             self.emit_constant(Value::Int(30000));
             self.emit_byte(OpCode::DefineTape);
         }
 
-        while !self.matches(TokenKind::EOF) {
+        while !self.matches(TokenKind::Eof) {
             self.expression();
+
+            if self.panic_mode {
+                self.synchronize();
+            }
         }
 
         self.end()
@@ -50,60 +326,156 @@ impl<'a> Parser<'a> {
 
     pub fn expression(&mut self) {
         match &self.current.kind {
-            TokenKind::Plus => self.sized_code(OpCode::IncrementSingular, OpCode::Increment),
-            TokenKind::Minus => self.sized_code(OpCode::DecrementSingular, OpCode::Decrement),
+            TokenKind::Plus => self.sized_code(
+                OpCode::IncrementSingular,
+                OpCode::Increment,
+                OpCode::IncrementBy,
+            ),
+            TokenKind::Minus => self.sized_code(
+                OpCode::DecrementSingular,
+                OpCode::Decrement,
+                OpCode::DecrementBy,
+            ),
             TokenKind::LeftAngle => self.sized_constant(OpCode::ShiftLeft, OpCode::MoveLeft),
             TokenKind::RightAngle => self.sized_constant(OpCode::ShiftRight, OpCode::MoveRight),
             TokenKind::Dot => self.sized_constant(OpCode::Print, OpCode::PrintRange),
+            TokenKind::Colon => {
+                self.advance();
+                self.emit_byte(OpCode::PrintNumber);
+            }
             TokenKind::Comma => self.input_expression(),
             TokenKind::Hash => self.replace_current(),
             TokenKind::At => self.set_pointer_expression(),
             TokenKind::LeftBrace => self.define_tape(),
             TokenKind::LeftBracket => self.loop_expression(),
             TokenKind::String => self.string(),
-            _ => (),
+            TokenKind::Intrinsic => self.intrinsic(),
+            TokenKind::Subroutine => self.define_subroutine(),
+            TokenKind::Call => self.call(),
+            _ => {
+                self.error_at_current(
+                    DiagnosticKind::UnexpectedToken,
+                    format!("Unexpected '{}'.", self.current.lexeme).as_str(),
+                );
+                self.advance();
+            }
         }
     }
 
+    /// Emit `one` for a lone operator or `many` after a constant with the
+    /// amount. With optimizations, runs of moves are folded into a single
+    /// instruction.
     fn sized_constant(&mut self, one: OpCode, many: OpCode) {
-        self.advance();
-        if self.matches(TokenKind::Integer) {
-            let size = self.previous.lexeme.parse::<u32>().unwrap();
+        let kind = self.current.kind.clone();
+
+        // Every print of a run outputs the same cell, they do not add up.
+        let fold = self.optimize && kind != TokenKind::Dot;
+
+        let mut size: u32 = 0;
+        loop {
+            match self.sized_operand::<u32>() {
+                Some(amount) => size = size.saturating_add(amount),
+                None => return,
+            }
+
+            if !fold || !self.check(kind.clone()) {
+                break;
+            }
+        }
+
+        if size == 1 && (self.optimize || self.previous.kind != TokenKind::Integer) {
+            self.emit_byte(one);
+        } else {
             self.emit_constant(Value::Int(size));
             self.emit_byte(many);
-        } else {
-            self.emit_byte(one);
         }
     }
 
-    fn sized_code(&mut self, one: OpCode, many: OpCode) {
-        self.advance();
-        if self.matches(TokenKind::Integer) {
-            let size = self.previous.lexeme.parse::<usize>().unwrap();
-
-            if size > u8::MAX as usize {
-                self.error_at_current("Expect integer between 0-255.");
+    /// Emit `one` for a lone operator, `many` with the amount as operand,
+    /// or `wide` after a constant when the amount does not fit in a byte.
+    /// With optimizations, runs of the operator are folded into a single
+    /// instruction.
+    fn sized_code(&mut self, one: OpCode, many: OpCode, wide: OpCode) {
+        let kind = self.current.kind.clone();
+        let max = self.current_chunk().cell_width.max() as usize;
+        let mut size: usize = 0;
+        loop {
+            let amount = match self.sized_operand::<usize>() {
+                Some(amount) => amount,
+                None => return,
+            };
+
+            if amount > max {
+                self.error(
+                    DiagnosticKind::IntegerOutOfRange,
+                    &format!("Expect integer between 0-{max}."),
+                );
                 return;
             }
 
-            self.emit_byte(many);
-            self.emit_byte(size as u8);
-        } else {
+            size = size.saturating_add(amount);
+            if !self.optimize || !self.check(kind.clone()) {
+                break;
+            }
+        }
+
+        if size == 1 && (self.optimize || self.previous.kind != TokenKind::Integer) {
             self.emit_byte(one);
+            return;
+        }
+
+        match u8::try_from(size) {
+            Ok(size) => self.emit_two_bytes(many as u8, size),
+            Err(_) if size <= max => {
+                self.emit_constant(Value::Int(size as u32));
+                self.emit_byte(wide);
+            }
+            Err(_) => {
+                // A folded run may add up past the cell width, which only
+                // fails once executed, so it is split into several steps.
+                let mut left = size;
+                while left > 0 {
+                    let step = left.min(u8::MAX as usize);
+                    self.emit_two_bytes(many as u8, step as u8);
+                    left -= step;
+                }
+            }
+        }
+    }
+
+    /// Consume an operator and its optional amount, which defaults to 1.
+    /// Returns `None` if the amount was invalid.
+    fn sized_operand<T: TryFrom<u64> + From<u8>>(&mut self) -> Option<T> {
+        self.advance();
+        if self.matches(TokenKind::Integer) {
+            self.integer::<T>()
+        } else {
+            Some(T::from(1))
         }
     }
 
     fn input_expression(&mut self) {
         self.advance();
 
+        if self.matches(TokenKind::Question) {
+            self.emit_byte(OpCode::PeekInput);
+            return;
+        }
+
+        if self.matches(TokenKind::Hash) {
+            self.emit_byte(OpCode::InputNumber);
+            return;
+        }
+
         if !self.matches(TokenKind::Star) {
             self.emit_byte(OpCode::Input);
+            return;
         }
 
         self.emit_byte(OpCode::MultiInput);
         let mut flags: u8 = 0x00000000;
         if self.matches(TokenKind::Caret) {
-            flags = flags | 0x00000001;
+            flags |= 0x00000001;
         }
 
         self.emit_byte(flags);
@@ -113,24 +485,37 @@ impl<'a> Parser<'a> {
         self.advance();
 
         self.consume(TokenKind::Integer, "Expect integer after '#'.");
-        let value = self.previous.lexeme.parse::<usize>().unwrap();
-        if value > u8::MAX as usize {
+        let value = match self.integer::<usize>() {
+            Some(value) => value,
+            None => return,
+        };
+        let max = self.current_chunk().cell_width.max();
+        if value > max as usize {
             self.error_at(
                 self.previous.clone(),
-                "Expect integer between 0 and 255 (included).",
+                DiagnosticKind::IntegerOutOfRange,
+                &format!("Expect integer between 0 and {max} (included)."),
             );
             return;
         }
 
-        self.emit_byte(OpCode::WriteCell);
-        self.emit_byte(value as u8);
+        match u8::try_from(value) {
+            Ok(value) => self.emit_two_bytes(OpCode::WriteCell as u8, value),
+            Err(_) => {
+                self.emit_constant(Value::Int(value as u32));
+                self.emit_byte(OpCode::WriteValue);
+            }
+        }
     }
 
     fn set_pointer_expression(&mut self) {
         self.advance();
 
         self.consume(TokenKind::Integer, "Expect integer after '@'.");
-        let value = self.previous.lexeme.parse::<u32>().unwrap();
+        let value = match self.integer::<u32>() {
+            Some(value) => value,
+            None => return,
+        };
 
         self.emit_constant(Value::Int(value));
         self.emit_byte(OpCode::SetPointer);
@@ -139,7 +524,10 @@ impl<'a> Parser<'a> {
     fn define_tape(&mut self) {
         self.advance();
         self.consume(TokenKind::Integer, "Expect a number after '{'.");
-        let size = self.previous.lexeme.parse::<u32>().unwrap();
+        let size = match self.integer::<u32>() {
+            Some(size) => size,
+            None => return,
+        };
 
         self.emit_constant(Value::Int(size));
         self.emit_byte(OpCode::DefineTape);
@@ -148,27 +536,87 @@ impl<'a> Parser<'a> {
     }
 
     fn loop_expression(&mut self) {
+        self.advance();
+
         let loop_start = self.current_chunk().code.len();
         let repeat_jump = self.emit_jump(OpCode::JumpIfZero);
 
-        self.advance();
-        while !self.matches(TokenKind::RightBracket) {
+        while !self.check(TokenKind::RightBracket) && !self.check(TokenKind::Eof) {
             self.expression();
+
+            if self.panic_mode {
+                self.synchronize();
+            }
+        }
+
+        self.consume(TokenKind::RightBracket, "Expect ']' after loop body.");
+
+        // A loop only decrementing the cell clears it, and one only moving
+        // a single cell scans for the next zero cell. Incrementing loops
+        // only clear the cell when cells wrap around, otherwise they overflow.
+        let optimize = self.optimize;
+        let wrapping = self.wrapping;
+        let body = &self.current_chunk().code[repeat_jump + 2..];
+        let replacement = match body {
+            [byte] if *byte == OpCode::DecrementSingular as u8 => Some(OpCode::ClearCell),
+            [byte] if *byte == OpCode::IncrementSingular as u8 && wrapping => {
+                Some(OpCode::ClearCell)
+            }
+            [byte] if *byte == OpCode::ShiftRight as u8 => Some(OpCode::ScanRight),
+            [byte] if *byte == OpCode::ShiftLeft as u8 => Some(OpCode::ScanLeft),
+            _ => None,
+        };
+        if let Some(replacement) = replacement.filter(|_| optimize) {
+            let chunk = self.current_chunk();
+            let line = chunk.lines[loop_start];
+
+            chunk.code.truncate(loop_start);
+            chunk.lines.truncate(loop_start);
+            chunk.write_chunk(replacement as u8, line);
+            return;
         }
 
-        self.emit_loop(loop_start);
+        // Both jumps of the loop are wide when the jump back does not fit
+        // in two bytes.
+        let body = self.current_chunk().code.len() - (repeat_jump + 2);
+        let wide = OpCode::JumpIfZero.length() + body + OpCode::Loop.length() > u16::MAX as usize;
+        if wide {
+            self.widen_jump(repeat_jump);
+        }
+
+        self.emit_loop(loop_start, wide);
         self.patch_jump(repeat_jump);
     }
 
     pub fn string(&mut self) {
-        let value = String::from(&self.current.lexeme[1..self.current.lexeme.len() - 1]);
-        let length = value.len();
+        let value = match unescape(&self.current.lexeme[1..self.current.lexeme.len() - 1]) {
+            Ok(value) => value,
+            Err(message) => {
+                self.error_at_current(DiagnosticKind::InvalidEscape, &message);
+                String::new()
+            }
+        };
+
+        let max = self.current_chunk().cell_width.max();
+        if let Some(c) = value.chars().find(|c| *c as u32 > max) {
+            self.error_at_current(
+                DiagnosticKind::CharacterOutOfRange,
+                &format!(
+                    "Expect characters between 0-{max}, found '{c}' ({}).",
+                    c as u32
+                ),
+            );
+        }
+
+        // Every character takes up one cell.
+        let length = value.chars().count();
 
         let rc = Rc::from(value);
 
+        // Advance first so the instructions are on the line of the string.
+        self.advance();
         self.emit_constant(Value::String(rc));
         self.emit_byte(OpCode::WriteString);
-        self.advance();
 
         if self.matches(TokenKind::Dollar) {
             self.emit_constant(Value::Int(length as u32));
@@ -181,6 +629,273 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn intrinsic(&mut self) {
+        let name = String::from(&self.current.lexeme[1..]);
+        self.advance();
+
+        if !self.intrinsics.contains(&name) {
+            self.error(
+                DiagnosticKind::IntrinsicNotAllowed,
+                format!("Intrinsic '${name}' is not allowed.").as_str(),
+            );
+            self.skip_arguments();
+            return;
+        }
+
+        match name.as_str() {
+            "clearif" => {
+                if let Some(arguments) = self.arguments(2) {
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::ClearIf);
+                }
+            }
+            "dup" => self.emit_byte(OpCode::DupRight),
+            "yield" => self.emit_byte(OpCode::Yield),
+            "checksum" => {
+                if let Some(arguments) = self.arguments(2) {
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::Checksum);
+                }
+            }
+            "reverse" => {
+                if let Some(arguments) = self.arguments(2) {
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::ReverseRange);
+                }
+            }
+            "sort" => {
+                if let Some((arguments, descending)) = self.arguments_with_flag(2) {
+                    self.emit_indexes(&arguments);
+                    self.emit_two_bytes(OpCode::SortRange as u8, descending as u8);
+                }
+            }
+            "find" => {
+                if let Some(arguments) = self.arguments(4) {
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::FindByte);
+                }
+            }
+            "halt" => {
+                if let Some(arguments) = self.arguments(1) {
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::Halt);
+                }
+            }
+            "transpose" => {
+                if let Some(arguments) = self.arguments(4) {
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::Transpose);
+                }
+            }
+            "env" => {
+                if let Some((name, arguments)) = self.string_arguments(2) {
+                    self.emit_constant(Value::String(Rc::from(name)));
+                    self.emit_indexes(&arguments);
+                    self.emit_byte(OpCode::ReadEnv);
+                }
+            }
+            _ => {
+                self.error(
+                    DiagnosticKind::UnknownIntrinsic,
+                    format!("Unknown intrinsic '${name}'.").as_str(),
+                );
+                self.skip_arguments();
+            }
+        }
+    }
+
+    fn define_subroutine(&mut self) {
+        let token = self.current.clone();
+        self.advance();
+        self.consume(TokenKind::LeftParen, "Expect '(' after subroutine name.");
+
+        let start = self.current_chunk().code.len();
+        while !self.check(TokenKind::RightParen) && !self.check(TokenKind::Eof) {
+            self.expression();
+
+            if self.panic_mode {
+                self.synchronize();
+            }
+        }
+
+        self.consume(TokenKind::RightParen, "Expect ')' after subroutine body.");
+        self.emit_return();
+
+        // Move the body out of the program, which continues after it.
+        let chunk = self.current_chunk();
+        let body = (chunk.code.split_off(start), chunk.lines.split_off(start));
+
+        let index = self.subroutine(&token.lexeme[1..]);
+        if self.subroutines[index].body.is_some() {
+            let message = format!("Subroutine '{}' is already defined.", token.lexeme);
+            self.error_at(token, DiagnosticKind::SubroutineRedefined, &message);
+            return;
+        }
+
+        self.subroutines[index].body = Some(body);
+    }
+
+    fn call(&mut self) {
+        let token = self.current.clone();
+        self.advance();
+        self.consume(TokenKind::LeftParen, "Expect '(' after subroutine name.");
+        self.consume(TokenKind::RightParen, "Expect ')' after '('.");
+
+        let index = self.subroutine(&token.lexeme[1..]);
+        let subroutine = &mut self.subroutines[index];
+        let constant = subroutine.constant;
+        subroutine.call.get_or_insert(token);
+
+        self.emit_constant_index(constant);
+        self.emit_byte(OpCode::Call);
+    }
+
+    /// Index of the subroutine with the given name, which is added with
+    /// a constant of its own on first use.
+    fn subroutine(&mut self, name: &str) -> usize {
+        if let Some(index) = self.subroutines.iter().position(|known| known.name == name) {
+            return index;
+        }
+
+        let constant = self.current_chunk().add_constant(Value::Int(0));
+        let constant = match u16::try_from(constant) {
+            Ok(constant) => constant,
+            Err(_) => {
+                self.error(
+                    DiagnosticKind::TooManyConstants,
+                    "Too many constants in one chunk.",
+                );
+                0
+            }
+        };
+
+        self.subroutines.push(Subroutine {
+            name: String::from(name),
+            constant,
+            call: None,
+            body: None,
+        });
+        self.subroutines.len() - 1
+    }
+
+    /// Skip the argument list of an intrinsic which is not compiled.
+    fn skip_arguments(&mut self) {
+        if self.matches(TokenKind::LeftParen) {
+            while !self.check(TokenKind::RightParen) && !self.check(TokenKind::Eof) {
+                self.advance();
+            }
+
+            self.matches(TokenKind::RightParen);
+        }
+    }
+
+    /// Parse a parenthesized list of the given number of integers.
+    fn arguments(&mut self, count: usize) -> Option<Vec<u32>> {
+        self.consume(TokenKind::LeftParen, "Expect '(' after intrinsic.");
+        let arguments = self.integer_arguments(count, false);
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+
+        if self.had_error {
+            return None;
+        }
+
+        Some(arguments)
+    }
+
+    /// Parse a parenthesized list of the given number of integers followed
+    /// by an optional flag, which is either 0 or 1 and 0 if left out.
+    fn arguments_with_flag(&mut self, count: usize) -> Option<(Vec<u32>, bool)> {
+        self.consume(TokenKind::LeftParen, "Expect '(' after intrinsic.");
+        let arguments = self.integer_arguments(count, false);
+
+        let mut flag = false;
+        if self.matches(TokenKind::Comma) {
+            self.consume(TokenKind::Integer, "Expect flag argument.");
+            match self.integer::<u32>() {
+                Some(0) => (),
+                Some(1) => flag = true,
+                Some(_) => self.error(
+                    DiagnosticKind::IntegerOutOfRange,
+                    "Expect flag to be 0 or 1.",
+                ),
+                None => (),
+            }
+        }
+
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+
+        if self.had_error {
+            return None;
+        }
+
+        Some((arguments, flag))
+    }
+
+    /// Parse a parenthesized string followed by the given number of integers.
+    fn string_arguments(&mut self, count: usize) -> Option<(String, Vec<u32>)> {
+        self.consume(TokenKind::LeftParen, "Expect '(' after intrinsic.");
+        self.consume(TokenKind::String, "Expect string argument.");
+
+        let lexeme = &self.previous.lexeme;
+        let string = match self.previous.kind {
+            TokenKind::String => match unescape(&lexeme[1..lexeme.len() - 1]) {
+                Ok(string) => string,
+                Err(message) => {
+                    self.error(DiagnosticKind::InvalidEscape, &message);
+                    String::new()
+                }
+            },
+            _ => String::new(),
+        };
+
+        let arguments = self.integer_arguments(count, true);
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+
+        if self.had_error {
+            return None;
+        }
+
+        Some((string, arguments))
+    }
+
+    fn integer_arguments(&mut self, count: usize, after_first: bool) -> Vec<u32> {
+        let mut arguments = vec![];
+        for i in 0..count {
+            if i > 0 || after_first {
+                self.consume(TokenKind::Comma, "Expect ',' between arguments.");
+            }
+
+            self.consume(TokenKind::Integer, "Expect integer argument.");
+            if let Some(value) = self.integer::<u32>() {
+                arguments.push(value);
+            }
+        }
+
+        arguments
+    }
+
+    /// Parse the previous token as an integer of the given type. Reports
+    /// integers which do not fit, a missing integer was already reported.
+    fn integer<T: TryFrom<u64>>(&mut self) -> Option<T> {
+        if self.previous.kind != TokenKind::Integer {
+            return None;
+        }
+
+        match parse_integer(&self.previous.lexeme) {
+            Some(value) => Some(value),
+            None => {
+                self.error(DiagnosticKind::IntegerOutOfRange, "Integer is too large.");
+                None
+            }
+        }
+    }
+
+    fn emit_indexes(&mut self, indexes: &[u32]) {
+        for index in indexes {
+            self.emit_constant(Value::Int(*index));
+        }
+    }
+
     fn advance(&mut self) {
         mem::swap(&mut self.current, &mut self.previous);
 
@@ -193,7 +908,9 @@ impl<'a> Parser<'a> {
                 _ => break,
             }
 
-            self.error_at_current("unexpected token.");
+            // Error tokens carry their message as lexeme.
+            let message = self.current.lexeme.clone();
+            self.error_at_current(DiagnosticKind::InvalidToken, &message);
         }
     }
 
@@ -216,32 +933,65 @@ impl<'a> Parser<'a> {
             return;
         }
 
-        self.error_at_current(message);
+        self.error_at_current(DiagnosticKind::UnexpectedToken, message);
     }
 
-    fn error(&mut self, message: &str) {
-        self.error_at(self.previous.clone(), message);
+    /// Skip tokens after an error until one that starts a new expression,
+    /// or ends a loop, so the errors after it are reported as well.
+    fn synchronize(&mut self) {
+        // Errors of the skipped tokens, like unterminated strings, are
+        // unrelated to the first one and still reported.
+        self.panic_mode = false;
+
+        while !self.check(TokenKind::Eof) {
+            match self.current.kind {
+                TokenKind::Plus
+                | TokenKind::Minus
+                | TokenKind::LeftAngle
+                | TokenKind::RightAngle
+                | TokenKind::LeftBracket
+                | TokenKind::RightBracket
+                | TokenKind::RightParen
+                | TokenKind::LeftBrace
+                | TokenKind::Subroutine
+                | TokenKind::Call => break,
+                _ => self.advance(),
+            }
+        }
+
+        self.panic_mode = false;
     }
 
-    fn error_at_current(&mut self, message: &str) {
-        self.error_at(self.current.clone(), message);
+    fn error(&mut self, kind: DiagnosticKind, message: &str) {
+        self.error_at(self.previous.clone(), kind, message);
     }
 
-    fn error_at(&mut self, token: Token, message: &str) {
+    fn error_at_current(&mut self, kind: DiagnosticKind, message: &str) {
+        self.error_at(self.current.clone(), kind, message);
+    }
+
+    fn error_at(&mut self, token: Token, kind: DiagnosticKind, message: &str) {
         if self.panic_mode {
             return;
         }
         self.panic_mode = true;
 
-        eprint!("[line {}] Error", token.line);
+        let diagnostic = Diagnostic {
+            kind,
+            line: token.line,
+            column: token.column,
+            at_end: token.kind == TokenKind::Eof,
+            message: String::from(message),
+        };
+
+        if let Some(report) = &mut self.report {
+            let context = source_context(&self.scanner, &token);
 
-        match token.kind {
-            TokenKind::EOF => eprint!(" at end"),
-            TokenKind::Error => (),
-            _ => (),
+            // Failing to report is no reason to stop compiling.
+            let _ = write!(report, "{diagnostic}\n{context}");
         }
 
-        println!(": {message}");
+        self.diagnostics.push(diagnostic);
         self.had_error = true;
     }
 
@@ -266,61 +1016,725 @@ impl<'a> Parser<'a> {
 
     fn emit_constant(&mut self, value: Value) {
         let constant = self.make_constant(value);
-        self.emit_two_bytes(OpCode::Constant as u8, constant);
+        self.emit_constant_index(constant);
+    }
+
+    fn emit_constant_index(&mut self, constant: u16) {
+        match u8::try_from(constant) {
+            Ok(constant) => self.emit_two_bytes(OpCode::Constant as u8, constant),
+            Err(_) => {
+                let [a, b] = constant.to_be_bytes();
+
+                self.emit_byte(OpCode::Constant16);
+                self.emit_two_bytes(a, b);
+            }
+        }
     }
 
     fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        let [a, b] = JUMP_PLACEHOLDER.to_be_bytes();
+
         self.emit_byte(instruction as u8);
-        self.emit_byte(0xff);
-        self.emit_byte(0xff);
+        self.emit_byte(a);
+        self.emit_byte(b);
 
         self.current_chunk().code.len() - 2
     }
 
+    /// Turn the jump with its operand at the given offset into a wide jump
+    /// by moving the code after it. Only relative jumps follow the jump, so
+    /// the moved code stays valid.
+    fn widen_jump(&mut self, offset: usize) {
+        let chunk = self.current_chunk();
+        let line = chunk.lines[offset];
+
+        chunk.code[offset - 1] = OpCode::JumpIfZeroWide as u8;
+        chunk
+            .code
+            .splice(offset..offset, JUMP_PLACEHOLDER.to_be_bytes());
+        chunk.lines.splice(offset..offset, [line; 2]);
+    }
+
     fn patch_jump(&mut self, offset: usize) {
-        // -2 to adjust for the bytecode for the jump offset itself
-        let jump = self.current_chunk().code.len() - offset - 2;
+        let wide = self.current_chunk().code[offset - 1] == OpCode::JumpIfZeroWide as u8;
+        let width = if wide { 4 } else { 2 };
+
+        // Adjust for the bytecode for the jump offset itself
+        let jump = self.current_chunk().code.len() - offset - width;
+
+        // Leave the placeholder in place rather than a truncated offset.
+        let operand = match wide {
+            true => u32::try_from(jump)
+                .ok()
+                .map(|jump| jump.to_be_bytes().to_vec()),
+            false => u16::try_from(jump)
+                .ok()
+                .map(|jump| jump.to_be_bytes().to_vec()),
+        };
+        let operand = match operand {
+            Some(operand) => operand,
+            None => {
+                self.error(DiagnosticKind::JumpTooLarge, "Too much code to jump over.");
+                return;
+            }
+        };
 
-        if jump > u16::MAX as usize {
-            self.error("Too much code to jump over.");
-        }
+        self.current_chunk().code[offset..offset + width].copy_from_slice(&operand);
+    }
 
-        let [a, b] = (jump as u16).to_be_bytes();
+    fn emit_loop(&mut self, loop_start: usize, wide: bool) {
+        let instruction = if wide { OpCode::LoopWide } else { OpCode::Loop };
+        self.emit_byte(instruction);
+
+        let offset = self.current_chunk().code.len() - loop_start + instruction.length() - 1;
+        let operand = match wide {
+            true => u32::try_from(offset)
+                .ok()
+                .map(|offset| offset.to_be_bytes().to_vec()),
+            false => u16::try_from(offset)
+                .ok()
+                .map(|offset| offset.to_be_bytes().to_vec()),
+        };
+        let operand = match operand {
+            Some(operand) => operand,
+            None => {
+                self.error(DiagnosticKind::JumpTooLarge, "Loop body too large.");
+                vec![0xff; instruction.length() - 1]
+            }
+        };
 
-        self.current_chunk().code[offset] = a;
-        self.current_chunk().code[offset + 1] = b;
+        for byte in operand {
+            self.emit_byte(byte);
+        }
     }
 
-    fn emit_loop(&mut self, loop_start: usize) {
-        self.emit_byte(OpCode::Loop as u8);
-
-        let offset = self.current_chunk().code.len() - loop_start + 2;
-        if offset > u16::MAX as usize {
-            self.error("Loop body too large.");
+    /// Index of the constant. With optimizations, an equal constant made
+    /// before is reused.
+    fn make_constant(&mut self, value: Value) -> u16 {
+        if self.optimize {
+            if let Some(&constant) = self.constants.get(&value) {
+                return constant;
+            }
         }
 
-        let [a, b] = (offset as u16).to_be_bytes();
+        let constant = self.chunk.add_constant(value.clone());
+        let constant = match u16::try_from(constant) {
+            Ok(constant) => constant,
+            Err(_) => {
+                self.error(
+                    DiagnosticKind::TooManyConstants,
+                    "Too many constants in one chunk.",
+                );
+                return 0;
+            }
+        };
 
-        self.emit_byte(a);
-        self.emit_byte(b);
+        if self.optimize {
+            self.constants.insert(value, constant);
+        }
+
+        constant
     }
 
-    fn make_constant(&mut self, value: Value) -> u8 {
-        let constant = self.current_chunk().add_constant(value);
-        if constant > (u8::MAX as usize) {
-            self.error("Too many constants in one chunk.");
+    /// Append the body of every defined subroutine after the program and
+    /// point its constant to it.
+    fn emit_subroutines(&mut self) {
+        for subroutine in mem::take(&mut self.subroutines) {
+            match subroutine.body {
+                Some((code, lines)) => {
+                    let chunk = self.current_chunk();
+                    let offset = Value::Int(chunk.code.len() as u32);
+
+                    chunk.constants[subroutine.constant as usize] = offset;
+                    chunk.code.extend(code);
+                    chunk.lines.extend(lines);
+                }
+                None => {
+                    // Every unknown subroutine is reported, not just the first.
+                    self.panic_mode = false;
+
+                    let call = subroutine.call.unwrap_or_else(Token::empty);
+                    let message = format!("Unknown subroutine '!{}()'.", subroutine.name);
+                    self.error_at(call, DiagnosticKind::UnknownSubroutine, &message);
+                }
+            }
         }
-
-        constant as u8
     }
 
     fn end(&mut self) -> bool {
         self.emit_return();
+        self.emit_subroutines();
 
-        if DEBUG_PRINT_CODE {
+        if self.dump_bytecode {
             disassemble_chunk(self.current_chunk(), "<script>");
         }
 
         !self.had_error
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_use_wide_jumps_for_large_loop_bodies() {
+        let body = "><".repeat(u16::MAX as usize / 2);
+        let chunk = compile(&format!("+[-[+]{body}]+[-[{body}]]")).unwrap();
+
+        let count = |opcode| {
+            opcodes(&chunk)
+                .iter()
+                .filter(|code| **code == opcode)
+                .count()
+        };
+        assert_eq!(count(OpCode::JumpIfZero), 1);
+        assert_eq!(count(OpCode::Loop), 1);
+        assert_eq!(count(OpCode::JumpIfZeroWide), 3);
+        assert_eq!(count(OpCode::LoopWide), 3);
+
+        // Every jump lands on an instruction.
+        assert!(Chunk::from_bytes(&chunk.as_bytes().unwrap()).is_ok());
+        assert_eq!(chunk.lines.len(), chunk.code.len());
+    }
+
+    #[test]
+    fn should_collect_and_report_diagnostics() {
+        let mut report = vec![];
+        let mut chunk = Chunk::new();
+
+        let mut parser = Parser::new(Scanner::new("+\n+300"), &mut chunk).report_to(&mut report);
+        assert!(!parser.compile());
+
+        let expected = Diagnostic {
+            kind: DiagnosticKind::IntegerOutOfRange,
+            line: 2,
+            column: 2,
+            at_end: false,
+            message: String::from("Expect integer between 0-255."),
+        };
+        assert_eq!(parser.diagnostics(), &[expected]);
+
+        drop(parser);
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "[line 2:2] Error: Expect integer between 0-255.\n  2 | +300\n    |  ^^^\n"
+        );
+    }
+
+    #[test]
+    fn should_report_every_error_in_one_pass() {
+        let diagnostics = compile("+300 #5\n{5 .\n[-999 >3 <99999999999] 'abc").unwrap_err();
+
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            errors,
+            [
+                (1, "Expect integer between 0-255."),
+                (2, "Expect '}' after define tape."),
+                (3, "Expect integer between 0-255."),
+                (3, "Integer is too large."),
+                (3, "Unterminated string."),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_classify_diagnostics() {
+        let kinds = |source: &str| -> Vec<DiagnosticKind> {
+            compile(source)
+                .unwrap_err()
+                .iter()
+                .map(|diagnostic| diagnostic.kind)
+                .collect()
+        };
+
+        assert_eq!(
+            kinds("} +300 + 'a\\q' + $nope + $sort(0, 1, 2) + ["),
+            [
+                DiagnosticKind::UnexpectedToken,
+                DiagnosticKind::IntegerOutOfRange,
+                DiagnosticKind::InvalidEscape,
+                DiagnosticKind::UnknownIntrinsic,
+                DiagnosticKind::IntegerOutOfRange,
+                DiagnosticKind::UnexpectedToken,
+            ]
+        );
+        assert_eq!(kinds("'abc"), [DiagnosticKind::InvalidToken]);
+    }
+
+    #[test]
+    fn should_underline_the_offending_token() {
+        let report = |source: &str| {
+            let mut report = vec![];
+            let mut chunk = Chunk::new();
+
+            let mut parser = Parser::new(Scanner::new(source), &mut chunk).report_to(&mut report);
+            assert!(!parser.compile());
+
+            drop(parser);
+            String::from_utf8(report).unwrap()
+        };
+
+        assert_eq!(
+            report("\t'é' #300"),
+            "[line 1:7] Error: Expect integer between 0 and 255 (included).\n  1 | \t'é' #300\n    | \t     ^^^\n"
+        );
+        assert_eq!(
+            report("+\r\n'abc\r\n."),
+            "[line 2:1] Error: Unterminated string.\n  2 | 'abc\n    | ^^^^\n"
+        );
+        assert_eq!(
+            report("{5"),
+            "[line 1:3] Error at end: Expect '}' after define tape.\n  1 | {5\n    |   ^\n"
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_programs_without_panicking() {
+        let programs = [
+            "5",
+            "]",
+            "}",
+            "[",
+            "[+>",
+            "#",
+            "@",
+            "{",
+            "{5",
+            "#99999999999999999999999",
+            "+99999999999999999999999",
+            "<99999999999",
+            "{99999999999}",
+            "'unterminated",
+            "'é'ü'",
+            "$(1)",
+            "$clearif(99999999999, 0)",
+        ];
+
+        for program in programs {
+            assert!(compile(program).is_err(), "{program:?} should not compile");
+        }
+    }
+
+    #[test]
+    fn should_reject_restricted_intrinsics() {
+        let mut chunk = Chunk::new();
+        let mut parser = Parser::new(Scanner::new("$sort(0, 2)\n$env('HOME', 0, 1)+"), &mut chunk)
+            .intrinsics(Intrinsics::except(&["env"]));
+        assert!(!parser.compile());
+
+        let expected = Diagnostic {
+            kind: DiagnosticKind::IntrinsicNotAllowed,
+            line: 2,
+            column: 1,
+            at_end: false,
+            message: String::from("Intrinsic '$env' is not allowed."),
+        };
+        assert_eq!(parser.diagnostics(), &[expected]);
+
+        let mut chunk = Chunk::new();
+        let mut parser =
+            Parser::new(Scanner::new("$sort(0, 2)"), &mut chunk).intrinsics(Intrinsics::none());
+        assert!(!parser.compile());
+    }
+
+    #[test]
+    fn should_report_lines_of_windows_source() {
+        let diagnostics = compile("\u{feff}+\r\n+\r\n+300\r\n").unwrap_err();
+
+        assert_eq!(diagnostics[0].line, 3);
+    }
+
+    #[test]
+    fn should_report_columns_in_characters() {
+        let diagnostics = compile("+\n\t'é' ü#300").unwrap_err();
+
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 8));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "[line 2:8] Error: Expect integer between 0 and 255 (included)."
+        );
+    }
+
+    #[test]
+    fn should_compile_non_ascii_source() {
+        assert!(compile("ü+ 'héllo'$ ✓").is_ok());
+    }
+
+    #[test]
+    fn should_report_unterminated_string_at_end_of_file() {
+        let diagnostics = compile("+\n'abc\n.").unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                kind: DiagnosticKind::InvalidToken,
+                line: 2,
+                column: 1,
+                at_end: false,
+                message: String::from("Unterminated string."),
+            }]
+        );
+    }
+
+    #[test]
+    fn should_decode_escapes_in_strings() {
+        let chunk = compile(r#"'a\tb\n'$ "\"\\\0\r\'""#).unwrap();
+        let strings = chunk
+            .constants
+            .iter()
+            .filter_map(|value| match value {
+                Value::String(string) => Some(string.to_string()),
+                Value::Int(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(strings, ["a\tb\n", "\"\\\0\r'"]);
+
+        // The printed length counts decoded characters.
+        assert!(matches!(chunk.constants[2], Value::Int(4)));
+
+        let diagnostics = compile(r"'a\qb'").unwrap_err();
+        assert_eq!(diagnostics[0].message, "Unknown escape sequence '\\q'.");
+        assert!(compile(r"$env('\x', 0, 1)").is_err());
+    }
+
+    #[test]
+    fn should_reject_characters_past_cell_width() {
+        let diagnostics = compile("+\n'hé\\nā'").unwrap_err();
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::CharacterOutOfRange);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(
+            diagnostics[0].message,
+            "Expect characters between 0-255, found 'ā' (257)."
+        );
+
+        let mut chunk = Chunk::new();
+        assert!(Parser::new(Scanner::new("'hé\\nā'"), &mut chunk)
+            .cell_width(CellWidth::Sixteen)
+            .compile());
+    }
+
+    #[test]
+    fn should_reuse_equal_constants() {
+        let compile = |source| {
+            let mut chunk = Chunk::new();
+            assert!(Parser::new(Scanner::new(source), &mut chunk)
+                .optimize(true)
+                .compile());
+
+            chunk
+        };
+
+        let chunk = compile("@5 @5 @5");
+        assert_eq!(chunk.constants, [Value::Int(30000), Value::Int(5)]);
+
+        let chunk = compile("{2}'ab'<2 'ab' @2");
+        assert_eq!(
+            chunk.constants,
+            [Value::Int(2), Value::String(Rc::from("ab"))]
+        );
+
+        // Only distinct constants count towards the limit.
+        let program = "@7 '7' ".repeat(300);
+        assert_eq!(compile(&program).constants.len(), 3);
+    }
+
+    #[test]
+    fn should_append_subroutines_after_the_program() {
+        let chunk = compile("{1}!inc() :inc(+) !inc()").unwrap();
+
+        // Both calls use the same constant, the offset of the body which
+        // follows the return of the program.
+        let call = [OpCode::Constant as u8, 1, OpCode::Call as u8];
+        let mut code = vec![OpCode::Constant as u8, 0, OpCode::DefineTape as u8];
+        code.extend(call);
+        code.extend(call);
+        code.extend([OpCode::Return as u8]);
+        code.extend([OpCode::IncrementSingular as u8, OpCode::Return as u8]);
+
+        assert_eq!(chunk.code, code);
+        assert_eq!(chunk.constants, [Value::Int(1), Value::Int(10)]);
+        assert!(Chunk::from_bytes(&chunk.as_bytes().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn should_not_reuse_constants_of_subroutines() {
+        let chunk = compile("{1}!a() @0 :a(+)").unwrap();
+
+        assert_eq!(
+            chunk.constants,
+            [Value::Int(1), Value::Int(10), Value::Int(0)]
+        );
+    }
+
+    #[test]
+    fn should_report_unknown_and_redefined_subroutines() {
+        let diagnostics = compile("!a() :b(+)\n:b(-) !c() :d(!a()").unwrap_err();
+
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.kind, diagnostic.line, diagnostic.column))
+            .collect();
+
+        assert_eq!(
+            errors,
+            [
+                (DiagnosticKind::SubroutineRedefined, 2, 1),
+                (DiagnosticKind::UnexpectedToken, 2, 19),
+                (DiagnosticKind::UnknownSubroutine, 1, 1),
+                (DiagnosticKind::UnknownSubroutine, 2, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_decode_hex_and_binary_literals() {
+        let mut chunk = Chunk::new();
+        let mut parser =
+            Parser::new(Scanner::new("#0xFF @0b100 +0x0a"), &mut chunk).without_default_tape();
+        assert!(parser.compile());
+
+        drop(parser);
+        assert_eq!(
+            chunk.code,
+            [
+                OpCode::WriteCell as u8,
+                255,
+                OpCode::Constant as u8,
+                0,
+                OpCode::SetPointer as u8,
+                OpCode::Increment as u8,
+                10,
+                OpCode::Return as u8,
+            ]
+        );
+        assert_eq!(chunk.constants, [Value::Int(4)]);
+
+        assert!(compile("{0x10}@0xf").is_ok());
+        assert!(compile("#0x100").is_err());
+        assert!(compile("@0xffffffffffffffffff").is_err());
+    }
+
+    #[test]
+    fn should_use_wide_constants_past_the_first_256() {
+        let program: String = (0..300).map(|i| format!("@{i} ")).collect();
+        let chunk = compile(&program).unwrap();
+
+        assert_eq!(chunk.constants.len(), 301);
+        assert_eq!(
+            opcodes(&chunk)
+                .iter()
+                .filter(|opcode| **opcode == OpCode::Constant16)
+                .count(),
+            45
+        );
+        assert!(Chunk::from_bytes(&chunk.as_bytes().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn should_emit_print_number() {
+        let mut chunk = Chunk::new();
+        let mut parser = Parser::new(Scanner::new(":"), &mut chunk).without_default_tape();
+        assert!(parser.compile());
+
+        drop(parser);
+        assert_eq!(
+            chunk.code,
+            [OpCode::PrintNumber as u8, OpCode::Return as u8]
+        );
+    }
+
+    #[test]
+    fn should_emit_one_instruction_per_input() {
+        let input = |source| {
+            let mut chunk = Chunk::new();
+            let mut parser = Parser::new(Scanner::new(source), &mut chunk).without_default_tape();
+            assert!(parser.compile());
+
+            drop(parser);
+            chunk
+        };
+
+        let code = |source| input(source).code;
+        let ret = OpCode::Return as u8;
+        assert_eq!(code(","), [OpCode::Input as u8, ret]);
+        assert_eq!(code(",?"), [OpCode::PeekInput as u8, ret]);
+        assert_eq!(code(",#"), [OpCode::InputNumber as u8, ret]);
+        assert_eq!(code(",*"), [OpCode::MultiInput as u8, 0, ret]);
+        assert_eq!(code(",*^"), [OpCode::MultiInput as u8, 1, ret]);
+
+        let listing = crate::debug::disassemble_to_string(&input(",,*,*^"), "input");
+        assert_eq!(
+            listing,
+            "== input ==\n\
+             0000    1 OP_INPUT\n\
+             0001    | OP_MULTI_INPUT      0\n\
+             0003    | OP_MULTI_INPUT      1\n\
+             0005    | OP_RETURN\n"
+        );
+    }
+
+    fn opcodes(chunk: &Chunk) -> Vec<OpCode> {
+        let mut opcodes = vec![];
+
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let opcode = OpCode::try_from(chunk.code[offset]).unwrap();
+            offset += opcode.length();
+            opcodes.push(opcode);
+        }
+
+        opcodes
+    }
+
+    #[test]
+    fn should_bound_values_by_cell_width() {
+        let mut chunk = Chunk::new();
+        let mut parser =
+            Parser::new(Scanner::new("+300-70000"), &mut chunk).cell_width(CellWidth::Sixteen);
+        assert!(!parser.compile());
+        assert_eq!(
+            parser.diagnostics()[0].message,
+            "Expect integer between 0-65535."
+        );
+
+        let mut chunk = Chunk::new();
+        let mut parser =
+            Parser::new(Scanner::new("+300#9-2"), &mut chunk).cell_width(CellWidth::Sixteen);
+        assert!(parser.compile());
+        drop(parser);
+        assert_eq!(
+            &opcodes(&chunk)[2..],
+            &[
+                OpCode::Constant,
+                OpCode::IncrementBy,
+                OpCode::WriteCell,
+                OpCode::Decrement,
+                OpCode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_fold_runs_of_operators() {
+        let code = |source: &str, width| {
+            let mut chunk = Chunk::new();
+            let mut parser = Parser::new(Scanner::new(source), &mut chunk)
+                .without_default_tape()
+                .optimize(true)
+                .cell_width(width);
+            assert!(parser.compile());
+
+            drop(parser);
+            chunk.code
+        };
+
+        let ret = OpCode::Return as u8;
+        let increment = OpCode::Increment as u8;
+        assert_eq!(code("+++++", CellWidth::Eight), [increment, 5, ret]);
+        assert_eq!(
+            code("+2+3--", CellWidth::Eight),
+            [increment, 5, OpCode::Decrement as u8, 2, ret]
+        );
+        assert_eq!(
+            code(">>><", CellWidth::Eight),
+            [
+                OpCode::Constant as u8,
+                0,
+                OpCode::MoveRight as u8,
+                OpCode::ShiftLeft as u8,
+                ret
+            ]
+        );
+        assert_eq!(
+            code("..", CellWidth::Eight),
+            [OpCode::Print as u8, OpCode::Print as u8, ret]
+        );
+
+        // Runs past the cell width are split to overflow when executed.
+        let run = "+".repeat(300);
+        assert_eq!(
+            code(&run, CellWidth::Eight),
+            [increment, 255, increment, 45, ret]
+        );
+        assert_eq!(
+            code(&run, CellWidth::Sixteen),
+            [OpCode::Constant as u8, 0, OpCode::IncrementBy as u8, ret]
+        );
+
+        // Runs are only folded with optimizations.
+        let mut chunk = Chunk::new();
+        let mut parser = Parser::new(Scanner::new("++>>"), &mut chunk).without_default_tape();
+        assert!(parser.compile());
+
+        drop(parser);
+        assert_eq!(
+            chunk.code,
+            [
+                OpCode::IncrementSingular as u8,
+                OpCode::IncrementSingular as u8,
+                OpCode::ShiftRight as u8,
+                OpCode::ShiftRight as u8,
+                ret
+            ]
+        );
+    }
+
+    #[test]
+    fn should_map_source_one_to_one_without_optimizations() {
+        let source = "{4}+++[-]+1>1 @2 @2";
+
+        let mut naive = Chunk::new();
+        assert!(Parser::new(Scanner::new(source), &mut naive).compile());
+
+        let mut optimized = Chunk::new();
+        let parser = Parser::new(Scanner::new(source), &mut optimized);
+        assert!(parser.optimize(true).compile());
+
+        assert_eq!(
+            opcodes(&naive)[2..],
+            [
+                OpCode::IncrementSingular,
+                OpCode::IncrementSingular,
+                OpCode::IncrementSingular,
+                OpCode::JumpIfZero,
+                OpCode::DecrementSingular,
+                OpCode::Loop,
+                OpCode::Increment,
+                OpCode::Constant,
+                OpCode::MoveRight,
+                OpCode::Constant,
+                OpCode::SetPointer,
+                OpCode::Constant,
+                OpCode::SetPointer,
+                OpCode::Return,
+            ]
+        );
+        assert_eq!(
+            opcodes(&optimized)[2..],
+            [
+                OpCode::Increment,
+                OpCode::ClearCell,
+                OpCode::IncrementSingular,
+                OpCode::ShiftRight,
+                OpCode::Constant,
+                OpCode::SetPointer,
+                OpCode::Constant,
+                OpCode::SetPointer,
+                OpCode::Return
+            ]
+        );
+
+        // Repeated constants are only reused with optimizations.
+        assert_eq!(
+            naive.constants,
+            [Value::Int(4), Value::Int(1), Value::Int(2), Value::Int(2)]
+        );
+        assert_eq!(optimized.constants, [Value::Int(4), Value::Int(2)]);
+    }
+}
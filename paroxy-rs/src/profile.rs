@@ -0,0 +1,316 @@
+use std::{
+    cmp::Reverse,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    chunk::Chunk,
+    debug::{jump_target, opcode_name},
+    opcode::OpCode,
+};
+
+/// Execution counts and time spent per chunk offset.
+pub struct Profile {
+    counts: Vec<u64>,
+    durations: Vec<Duration>,
+    last: Option<(usize, Instant)>,
+}
+
+/// Aggregated execution of a single source loop.
+#[derive(Debug)]
+pub struct LoopProfile {
+    /// Source line the loop was opened on, unless the chunk was stripped.
+    pub line: Option<usize>,
+
+    /// Offset of the `JumpIfZero` opening the loop.
+    pub start: usize,
+
+    /// Offset just after the `Loop` closing the loop.
+    pub end: usize,
+
+    /// Number of times the loop body was repeated.
+    pub iterations: u64,
+
+    /// Instructions executed inside the loop, nested loops included.
+    pub instructions: u64,
+
+    /// Time spent inside the loop, nested loops included.
+    pub time: Duration,
+}
+
+/// Number of times every opcode executed, indexed by its byte value.
+#[derive(Debug, Clone)]
+pub struct OpcodeCounts {
+    counts: Vec<u64>,
+}
+
+impl Default for OpcodeCounts {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; OpCode::ALL.len()],
+        }
+    }
+}
+
+impl OpcodeCounts {
+    pub fn record(&mut self, opcode: OpCode) {
+        self.counts[opcode as usize] += 1;
+    }
+
+    /// Number of executed instructions.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The opcodes which executed with their counts, most frequent first
+    /// and in opcode order for equal counts.
+    pub fn sorted(&self) -> Vec<(OpCode, u64)> {
+        let mut counts = OpCode::ALL
+            .iter()
+            .map(|opcode| (*opcode, self.counts[*opcode as usize]))
+            .filter(|(_, count)| *count > 0)
+            .collect::<Vec<_>>();
+
+        counts.sort_by_key(|(_, count)| Reverse(*count));
+        counts
+    }
+
+    /// Render the counts as a histogram, most frequent first.
+    pub fn report(&self) -> String {
+        let total = self.total();
+
+        let mut report = String::from("== opcode profile ==\n");
+        let _ = writeln!(report, "{:20} {:>12} {:>7}", "opcode", "count", "share");
+
+        for (opcode, count) in self.sorted() {
+            let share = count as f64 * 100.0 / total as f64;
+            let _ = writeln!(
+                report,
+                "{:20} {:>12} {:>6.1}%",
+                opcode_name(opcode),
+                count,
+                share
+            );
+        }
+
+        report
+    }
+}
+
+impl Profile {
+    pub fn new(code_size: usize) -> Self {
+        Self {
+            counts: vec![0; code_size],
+            durations: vec![Duration::ZERO; code_size],
+            last: None,
+        }
+    }
+
+    /// Record the dispatch of the instruction at the given offset. The time
+    /// until the next dispatch is attributed to this instruction.
+    pub fn record(&mut self, offset: usize) {
+        let now = Instant::now();
+        if let Some((previous, started)) = self.last {
+            self.durations[previous] += now - started;
+        }
+
+        self.counts[offset] += 1;
+        self.last = Some((offset, now));
+    }
+
+    /// Aggregate the offsets into the loops of the chunk, hottest first.
+    pub fn loops(&self, chunk: &Chunk) -> Vec<LoopProfile> {
+        let mut loops = vec![];
+
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let instruction = match OpCode::try_from(chunk.code[offset]) {
+                Ok(instruction) => instruction,
+                Err(_) => break,
+            };
+
+            if let OpCode::JumpIfZero | OpCode::JumpIfZeroWide = instruction {
+                let end = jump_target(chunk, offset).unwrap_or(chunk.code.len());
+
+                // Both jumps of a loop have the same width.
+                let closing = match instruction {
+                    OpCode::JumpIfZero => OpCode::Loop,
+                    _ => OpCode::LoopWide,
+                };
+
+                loops.push(LoopProfile {
+                    line: chunk.line_for(offset),
+                    start: offset,
+                    end,
+                    // The closing `Loop` is executed once per repetition.
+                    iterations: self.counts[end - closing.length()],
+                    instructions: self.counts[offset..end].iter().sum(),
+                    time: self.durations[offset..end].iter().sum(),
+                });
+            }
+
+            offset += instruction.length();
+        }
+
+        loops.sort_by_key(|profile| Reverse(profile.time));
+        loops
+    }
+
+    /// Offsets of the instructions of the chunk which never executed.
+    pub fn uncovered(&self, chunk: &Chunk) -> Vec<usize> {
+        let mut uncovered = vec![];
+
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            if self.counts[offset] == 0 {
+                uncovered.push(offset);
+            }
+
+            offset += match OpCode::try_from(chunk.code[offset]) {
+                Ok(instruction) => instruction.length(),
+                Err(_) => break,
+            };
+        }
+
+        uncovered
+    }
+
+    /// Render the share of executed instructions and the source lines, or
+    /// offsets of stripped chunks, which never executed.
+    pub fn coverage_report(&self, chunk: &Chunk) -> String {
+        let total = chunk.instruction_count();
+        let uncovered = self.uncovered(chunk);
+        let covered = total - uncovered.len();
+
+        let mut report = String::from("== coverage ==\n");
+        let percentage = if total == 0 {
+            100.0
+        } else {
+            covered as f64 * 100.0 / total as f64
+        };
+        let _ = writeln!(
+            report,
+            "{covered}/{total} instructions executed ({percentage:.1}%)"
+        );
+
+        if uncovered.is_empty() {
+            return report;
+        }
+
+        if chunk.lines.is_empty() {
+            let offsets = uncovered
+                .iter()
+                .map(|offset| format!("{offset:04}"))
+                .collect::<Vec<_>>();
+            let _ = writeln!(report, "uncovered offsets: {}", offsets.join(", "));
+        } else {
+            let mut lines = uncovered
+                .iter()
+                .filter_map(|offset| chunk.line_for(*offset))
+                .collect::<Vec<_>>();
+            lines.dedup();
+
+            let lines = lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>();
+            let _ = writeln!(report, "uncovered lines: {}", lines.join(", "));
+        }
+
+        report
+    }
+
+    /// Render the loop profile as a table.
+    pub fn loop_report(&self, chunk: &Chunk) -> String {
+        let mut report = String::from("== loop profile ==\n");
+        let _ = writeln!(
+            report,
+            "{:>6} {:>12} {:>12} {:>14} {:>12}",
+            "line", "offsets", "iterations", "instructions", "time"
+        );
+
+        for profile in self.loops(chunk) {
+            let _ = writeln!(
+                report,
+                "{:>6} {:>12} {:>12} {:>14} {:>12}",
+                profile
+                    .line
+                    .map_or(String::from("-"), |line| line.to_string()),
+                format!("{:04}..{:04}", profile.start, profile.end),
+                profile.iterations,
+                profile.instructions,
+                format!("{:.3?}", profile.time),
+            );
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::sink;
+
+    use super::*;
+    use crate::{testing::compile, vm::VM};
+
+    #[test]
+    fn should_aggregate_nested_loops() {
+        let chunk = compile("+3[>+2[->+<]<-]");
+
+        let mut vm = VM::with_output(chunk, Box::new(sink()));
+        vm.enable_profiling();
+        vm.run().unwrap();
+
+        let loops = vm.profile().unwrap().loops(vm.chunk());
+        let mut iterations = loops.iter().map(|l| l.iterations).collect::<Vec<_>>();
+        iterations.sort();
+
+        assert_eq!(iterations, vec![3, 6]);
+
+        let outer = loops.iter().find(|l| l.iterations == 3).unwrap();
+        let inner = loops.iter().find(|l| l.iterations == 6).unwrap();
+        assert!(outer.instructions > inner.instructions);
+        assert!(outer.start < inner.start && inner.end < outer.end);
+    }
+
+    #[test]
+    fn should_count_executed_opcodes() {
+        let chunk = compile("{2}+3[>+<-]");
+
+        let mut vm = VM::with_output(chunk, Box::new(sink()));
+        vm.enable_opcode_counts();
+        vm.run().unwrap();
+
+        let counts = vm.opcode_counts().unwrap();
+        assert_eq!(
+            counts.sorted()[..4],
+            [
+                (OpCode::JumpIfZero, 4),
+                (OpCode::ShiftLeft, 3),
+                (OpCode::ShiftRight, 3),
+                (OpCode::IncrementSingular, 3),
+            ]
+        );
+
+        let report = counts.report();
+        assert!(report.starts_with("== opcode profile ==\n"));
+        assert!(report.contains("OP_JUMP_IF_ZERO                 4   17.4%\n"));
+    }
+
+    #[test]
+    fn should_report_uncovered_lines() {
+        let chunk = compile("[\n+\n]\n.");
+
+        let mut vm = VM::with_output(chunk, Box::new(sink()));
+        vm.enable_profiling();
+        vm.run().unwrap();
+
+        let report = vm.profile().unwrap().coverage_report(vm.chunk());
+
+        // The loop body and its closing jump never run.
+        assert!(report.contains("5/7 instructions executed (71.4%)"));
+        assert!(report.ends_with("uncovered lines: 2, 3\n"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    chunk::Chunk,
+    error::{Error, Result},
+    parser::Parser,
+    scanner::Scanner,
+    tape,
+    vm::VM,
+};
+
+/// Number of cells `:tape` shows on either side of the pointer.
+const TAPE_RADIUS: usize = 8;
+
+/// Meta-command of the REPL. Every other line, including other lines
+/// starting with `:`, is code.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// `:tape`
+    Tape,
+
+    /// `:save <path>`
+    Save(&'a str),
+
+    /// `:quit`
+    Quit,
+}
+
+impl<'a> Command<'a> {
+    /// The command of the line, `None` if the line is code.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        match line.trim() {
+            ":tape" => return Some(Command::Tape),
+            ":quit" => return Some(Command::Quit),
+            _ => (),
+        }
+
+        let path = line.trim_start().strip_prefix(":save")?;
+        match path.chars().next() {
+            None => Some(Command::Save("")),
+            Some(c) if c.is_whitespace() => Some(Command::Save(path.trim())),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Incrementally compiles and runs lines of a program against one
+/// long lived vm.
+pub struct Repl {
+    vm: VM,
+    transcript: Vec<String>,
+    tape_defined: bool,
+}
+
+impl Repl {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self {
+            vm: VM::with_output(Chunk::new(), output),
+            transcript: vec![],
+            tape_defined: false,
+        }
+    }
+
+    /// Compile only the given line and run it on the current tape.
+    /// Lines which fail to compile leave the state and transcript untouched.
+    pub fn eval(&mut self, line: &str) -> Result<()> {
+        let mut chunk = Chunk::new();
+
+        let mut parser = Parser::new(Scanner::new(line), &mut chunk).report_to(io::stderr());
+        if self.tape_defined {
+            parser = parser.without_default_tape();
+        }
+
+        let success = parser.compile();
+        drop(parser);
+
+        if !success {
+            return Err(Error::Compile);
+        }
+
+        self.tape_defined = true;
+        self.transcript.push(String::from(line));
+
+        self.vm.load(chunk);
+        self.vm.run()
+    }
+
+    /// Every successfully compiled line as a complete program.
+    pub fn transcript(&self) -> String {
+        let mut program = self.transcript.join("\n");
+        program.push('\n');
+        program
+    }
+
+    /// Write the transcript to the given file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.transcript())?;
+        Ok(())
+    }
+
+    /// The cells around the pointer.
+    pub fn tape(&self) -> String {
+        tape::dump_around(&self.vm.tape(), self.vm.pointer(), TAPE_RADIUS)
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::sink;
+
+    use super::*;
+
+    #[test]
+    fn should_keep_tape_between_lines() {
+        let mut repl = Repl::new(Box::new(sink()));
+
+        repl.eval("{10}+5>").unwrap();
+        repl.eval("+2").unwrap();
+        repl.eval("<+").unwrap();
+
+        assert_eq!(repl.vm().pointer(), 0);
+        assert_eq!(&repl.vm().tape()[0..2], &[6, 2]);
+        assert_eq!(repl.tape(), "0: [6] 2 0 0 0 0 0 0 0");
+    }
+
+    #[test]
+    fn should_skip_failed_lines_in_transcript() {
+        let mut repl = Repl::new(Box::new(sink()));
+
+        repl.eval("+5").unwrap();
+        assert!(matches!(repl.eval("#300"), Err(Error::Compile)));
+        repl.eval(">+").unwrap();
+
+        assert_eq!(repl.transcript(), "+5\n>+\n");
+        assert_eq!(repl.vm().tape().len(), 30000);
+    }
+
+    #[test]
+    fn should_only_parse_meta_commands() {
+        assert_eq!(Command::parse(":tape"), Some(Command::Tape));
+        assert_eq!(Command::parse(" :quit "), Some(Command::Quit));
+        assert_eq!(
+            Command::parse(":save  out.px"),
+            Some(Command::Save("out.px"))
+        );
+
+        for code in [":", "+48:done", ":saved", ":tapes", ":count(+)"] {
+            assert_eq!(Command::parse(code), None, "{code}");
+        }
+    }
+}
@@ -1,34 +1,101 @@
 use super::token::{Token, TokenKind};
 
+/// Byte order mark some editors put at the start of UTF-8 files.
+const BOM: char = '\u{feff}';
+
+/// Character starting a line comment unless configured otherwise.
+pub const DEFAULT_COMMENT: char = ';';
+
+/// Characters with a meaning in paroxy source.
+const RESERVED: &str = "{}[]<>.,:!$()@#*^+-?'\"";
+
 pub struct Scanner<'a> {
     source: &'a str,
-    chars: Vec<char>,
+
+    /// Characters of the source with their byte offsets.
+    chars: Vec<(usize, char)>,
     start: usize,
     current: usize,
     line: usize,
+
+    /// Index of the first character of the current line.
+    line_start: usize,
+
+    /// Line and column of the token being scanned.
+    start_line: usize,
+    start_column: usize,
+    bf_compat: bool,
+    comment: char,
 }
 
 impl<'a> Scanner<'a> {
+    /// Create a scanner for the source. A leading byte order mark is
+    /// skipped. Line breaks may be `\n` or `\r\n`.
     pub fn new(source: &'a str) -> Self {
+        let source = source.strip_prefix(BOM).unwrap_or(source);
+
         Self {
             source,
-            chars: source.chars().collect(),
+            chars: source.char_indices().collect(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            bf_compat: false,
+            comment: DEFAULT_COMMENT,
         }
     }
 
+    /// Only recognize the eight brainfuck commands and treat every
+    /// other character as a comment.
+    pub fn bf_compat(mut self, enabled: bool) -> Self {
+        self.bf_compat = enabled;
+        self
+    }
+
+    /// Start line comments with the given character, which should have
+    /// passed [`check_comment_char`].
+    pub fn comment_char(mut self, comment: char) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Text of the line with the given 1-based number, without its line
+    /// break. Empty past the last line.
+    pub fn line_text(&self, line: usize) -> &'a str {
+        let text = self.source.split('\n').nth(line - 1).unwrap_or("");
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    /// The source covered by the token, as scanned.
+    pub fn token_source(&self, token: &Token) -> &'a str {
+        self.source
+            .get(token.offset..token.offset + token.length)
+            .unwrap_or("")
+    }
+
     pub fn scan_token(&mut self) -> Token {
         self.skip_whitespace();
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.current - self.line_start + 1;
 
         if self.is_at_end() {
-            return self.make_token(TokenKind::EOF);
+            return self.make_token(TokenKind::Eof);
         }
 
         let c = self.advance();
 
+        if self.bf_compat {
+            return self.bf_token(c);
+        }
+
+        if c == self.comment {
+            return self.comment();
+        }
+
         match c {
             '{' => self.make_token(TokenKind::LeftBrace),
             '}' => self.make_token(TokenKind::RightBrace),
@@ -38,28 +105,59 @@ impl<'a> Scanner<'a> {
             '>' => self.make_token(TokenKind::RightAngle),
             '.' => self.make_token(TokenKind::Dot),
             ',' => self.make_token(TokenKind::Comma),
-            '$' => self.make_token(TokenKind::Dollar),
+            '$' => {
+                if self.starts_name() {
+                    return self.name(TokenKind::Intrinsic);
+                }
+
+                self.make_token(TokenKind::Dollar)
+            }
+            '(' => self.make_token(TokenKind::LeftParen),
+            ')' => self.make_token(TokenKind::RightParen),
             '@' => self.make_token(TokenKind::At),
             '#' => self.make_token(TokenKind::Hash),
             '*' => self.make_token(TokenKind::Star),
             '^' => self.make_token(TokenKind::Caret),
+            '?' => self.make_token(TokenKind::Question),
+            ':' if self.starts_subroutine() => self.name(TokenKind::Subroutine),
+            ':' => self.make_token(TokenKind::Colon),
+            '!' if self.starts_subroutine() => self.name(TokenKind::Call),
             '+' => self.make_token(TokenKind::Plus),
             '-' => self.make_token(TokenKind::Minus),
             n @ ('\'' | '"') => self.string(n),
-            n @ _ => {
+            n => {
                 if self.is_digit(n) {
                     return self.integer();
                 }
 
-                return self.make_token(TokenKind::Ignore);
+                self.make_token(TokenKind::Ignore)
             }
         }
     }
 
+    fn bf_token(&self, c: char) -> Token {
+        match c {
+            '[' => self.make_token(TokenKind::LeftBracket),
+            ']' => self.make_token(TokenKind::RightBracket),
+            '<' => self.make_token(TokenKind::LeftAngle),
+            '>' => self.make_token(TokenKind::RightAngle),
+            '.' => self.make_token(TokenKind::Dot),
+            ',' => self.make_token(TokenKind::Comma),
+            '+' => self.make_token(TokenKind::Plus),
+            '-' => self.make_token(TokenKind::Minus),
+            _ => self.make_token(TokenKind::Ignore),
+        }
+    }
+
     fn string(&mut self, terminator: char) -> Token {
         while !self.is_at_end() && self.peek() != terminator {
-            if self.peek() == '\n' {
-                self.line += 1;
+            // Skip the escaped character so an escaped quote does not end
+            // the string. Escapes are decoded by the parser.
+            if self.peek() == '\\' {
+                self.advance();
+                if self.is_at_end() {
+                    break;
+                }
             }
 
             self.advance();
@@ -73,8 +171,57 @@ impl<'a> Scanner<'a> {
         self.make_token(TokenKind::String)
     }
 
+    fn comment(&mut self) -> Token {
+        while !self.is_at_end() && self.peek() != '\n' {
+            self.advance();
+        }
+
+        self.make_token(TokenKind::Ignore)
+    }
+
+    /// Whether a name follows, as it does after the sigil of an intrinsic
+    /// or subroutine.
+    fn starts_name(&self) -> bool {
+        !self.is_at_end() && self.peek().is_ascii_alphabetic()
+    }
+
+    /// Whether a name directly followed by `(` follows, as it does after
+    /// the sigil of a subroutine definition or call. Without the `(`, the
+    /// sigil and name are left to prose like `+48:done` or `hello!world`.
+    fn starts_subroutine(&self) -> bool {
+        let rest = &self.chars[self.current..];
+        let length = rest
+            .iter()
+            .take_while(|(_, c)| c.is_ascii_alphanumeric())
+            .count();
+
+        self.starts_name() && matches!(rest.get(length), Some((_, '(')))
+    }
+
+    /// Scan the name following the sigil of an intrinsic or subroutine.
+    fn name(&mut self, kind: TokenKind) -> Token {
+        while !self.is_at_end() && self.peek().is_ascii_alphanumeric() {
+            self.advance();
+        }
+
+        self.make_token(kind)
+    }
+
     fn integer(&mut self) -> Token {
-        while !self.is_at_end() && self.is_digit(self.peek()) {
+        // A leading zero followed by 'x' or 'b' and a digit of that radix
+        // starts a hexadecimal or binary literal.
+        let prefix = self.chars.get(self.current..self.current + 2);
+        let radix = match (self.chars[self.start].1, prefix) {
+            ('0', Some([(_, 'x'), (_, digit)])) if digit.is_ascii_hexdigit() => 16,
+            ('0', Some([(_, 'b'), (_, '0' | '1')])) => 2,
+            _ => 10,
+        };
+
+        if radix != 10 {
+            self.advance();
+        }
+
+        while !self.is_at_end() && self.peek().is_digit(radix) {
             self.advance();
         }
 
@@ -82,7 +229,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_digit(&self, c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
     fn is_at_end(&self) -> bool {
@@ -90,21 +237,50 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> char {
+        let c = self.chars[self.current].1;
         self.current += 1;
-        self.chars[self.current - 1]
+
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.current;
+        }
+
+        c
     }
 
     fn peek(&self) -> char {
-        self.chars[self.current]
+        self.chars[self.current].1
+    }
+
+    /// Byte offset in the source of the character at the given index.
+    fn offset(&self, index: usize) -> usize {
+        self.chars
+            .get(index)
+            .map_or(self.source.len(), |(offset, _)| *offset)
     }
 
     fn make_token(&self, kind: TokenKind) -> Token {
-        let lexeme = &self.source[self.start..self.current];
-        Token::new(kind, String::from(lexeme), self.line)
+        let lexeme = &self.source[self.offset(self.start)..self.offset(self.current)];
+        self.token(kind, String::from(lexeme))
     }
 
+    /// Error token spanning the source scanned so far, positioned at its
+    /// start, such as the opening quote of an unterminated string.
     fn error_token(&self, message: &'static str) -> Token {
-        Token::new(TokenKind::Error, String::from(message), self.line)
+        self.token(TokenKind::Error, String::from(message))
+    }
+
+    fn token(&self, kind: TokenKind, lexeme: String) -> Token {
+        let offset = self.offset(self.start);
+
+        Token {
+            kind,
+            lexeme,
+            line: self.start_line,
+            column: self.start_column,
+            offset,
+            length: self.offset(self.current) - offset,
+        }
     }
 
     fn skip_whitespace(&mut self) {
@@ -114,46 +290,234 @@ impl<'a> Scanner<'a> {
             }
 
             match self.peek() {
-                ' ' | '\r' | '\t' => self.advance(),
-                '\n' => {
-                    self.line += 1;
-                    self.advance()
-                }
+                ' ' | '\r' | '\t' | '\n' => self.advance(),
                 _ => return,
             };
         }
     }
 }
 
+/// Make sure the character can start a line comment without changing the
+/// meaning of other source.
+pub fn check_comment_char(c: char) -> Result<char, String> {
+    if RESERVED.contains(c) {
+        return Err(format!("'{c}' is already used by paroxy syntax"));
+    }
+
+    if c.is_ascii_digit() || c.is_whitespace() || c == BOM {
+        return Err(format!("'{}' cannot start a comment", c.escape_default()));
+    }
+
+    Ok(c)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn should_scan_brainxysm() {
-        let mut scanner = Scanner::new("{30000}\"Hello world!\n\"$");
+    /// Scan until the end of the source or the first error, skipping
+    /// ignored characters. The last token is the end or the error.
+    fn tokens(mut scanner: Scanner) -> Vec<Token> {
         let mut tokens = vec![];
 
         loop {
             let token = scanner.scan_token();
-            tokens.push(token.kind);
-
-            match tokens.last().unwrap() {
-                TokenKind::Error | TokenKind::EOF => break,
-                _ => (),
+            match token.kind {
+                TokenKind::Ignore => continue,
+                TokenKind::Error | TokenKind::Eof => {
+                    tokens.push(token);
+                    return tokens;
+                }
+                _ => tokens.push(token),
             }
         }
+    }
+
+    fn kinds(scanner: Scanner) -> Vec<TokenKind> {
+        tokens(scanner)
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
 
+    #[test]
+    fn should_scan_brainxysm() {
         assert_eq!(
-            tokens,
+            kinds(Scanner::new("{30000}\"Hello world!\n\"$")),
             vec![
                 TokenKind::LeftBrace,
                 TokenKind::Integer,
                 TokenKind::RightBrace,
                 TokenKind::String,
                 TokenKind::Dollar,
-                TokenKind::EOF,
+                TokenKind::Eof,
             ]
         );
     }
+
+    #[test]
+    fn should_scan_intrinsic() {
+        assert_eq!(
+            kinds(Scanner::new("'a'$ $clearif(0, 1)")),
+            vec![
+                TokenKind::String,
+                TokenKind::Dollar,
+                TokenKind::Intrinsic,
+                TokenKind::LeftParen,
+                TokenKind::Integer,
+                TokenKind::Comma,
+                TokenKind::Integer,
+                TokenKind::RightParen,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_scan_subroutines() {
+        let tokens = tokens(Scanner::new(":digit2(:) !digit2() ! :"))
+            .into_iter()
+            .map(|token| (token.kind, token.lexeme))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Subroutine, String::from(":digit2")),
+                (TokenKind::LeftParen, String::from("(")),
+                (TokenKind::Colon, String::from(":")),
+                (TokenKind::RightParen, String::from(")")),
+                (TokenKind::Call, String::from("!digit2")),
+                (TokenKind::LeftParen, String::from("(")),
+                (TokenKind::RightParen, String::from(")")),
+                (TokenKind::Colon, String::from(":")),
+                (TokenKind::Eof, String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_skip_bom_and_count_crlf_lines() {
+        let tokens = tokens(Scanner::new("\u{feff}+\r\n'a\r\nb'\r\n>"))
+            .into_iter()
+            .map(|token| (token.kind, token.line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Plus, 1),
+                (TokenKind::String, 2),
+                (TokenKind::RightAngle, 4),
+                (TokenKind::Eof, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_track_token_positions() {
+        let tokens = tokens(Scanner::new("\u{feff}+\n\t'é' #12"))
+            .into_iter()
+            .map(|token| {
+                (
+                    token.kind,
+                    token.line,
+                    token.column,
+                    token.offset,
+                    token.length,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Plus, 1, 1, 0, 1),
+                (TokenKind::String, 2, 2, 3, 4),
+                (TokenKind::Hash, 2, 6, 8, 1),
+                (TokenKind::Integer, 2, 7, 9, 2),
+                (TokenKind::Eof, 2, 9, 11, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_scan_prefixed_integers() {
+        let lexemes = tokens(Scanner::new("0xFf 0b101 0b2 10x1 0x"))
+            .into_iter()
+            .filter(|token| token.kind == TokenKind::Integer)
+            .map(|token| token.lexeme)
+            .collect::<Vec<_>>();
+
+        assert_eq!(lexemes, ["0xFf", "0b101", "0", "2", "10", "1", "0"]);
+    }
+
+    #[test]
+    fn should_ignore_prose_in_bf_compat() {
+        assert_eq!(
+            kinds(Scanner::new("Set #0 to 8 '+'{5}").bf_compat(true)),
+            vec![TokenKind::Plus, TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn should_skip_comments_to_end_of_line() {
+        let scan = |scanner: Scanner| {
+            tokens(scanner)
+                .into_iter()
+                .map(|token| (token.kind, token.line))
+                .collect::<Vec<_>>()
+        };
+
+        let expected = vec![
+            (TokenKind::Plus, 1),
+            (TokenKind::Minus, 2),
+            (TokenKind::Eof, 2),
+        ];
+        assert_eq!(scan(Scanner::new("+ ; [.'\n-")), expected);
+        assert_eq!(scan(Scanner::new("+ % [.'\n-").comment_char('%')), expected);
+
+        // A comment at the end without a line break, and in strings.
+        assert_eq!(scan(Scanner::new("+\n- ; done")), expected);
+        assert_eq!(scan(Scanner::new(";")), vec![(TokenKind::Eof, 1)]);
+        assert_eq!(
+            scan(Scanner::new("\"a;b\" ; c\n'd\n;e'\n;f")),
+            vec![
+                (TokenKind::String, 1),
+                (TokenKind::String, 2),
+                (TokenKind::Eof, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_unterminated_string_at_opening_line() {
+        let mut scanner = Scanner::new("+\n'abc\ndef");
+        assert_eq!(scanner.scan_token().kind, TokenKind::Plus);
+
+        let token = scanner.scan_token();
+        assert_eq!(token.kind, TokenKind::Error);
+        assert_eq!(token.lexeme, "Unterminated string.");
+        assert_eq!(token.line, 2);
+        assert_eq!(scanner.scan_token().kind, TokenKind::Eof);
+    }
+
+    #[test]
+    fn should_not_end_strings_on_escaped_quotes() {
+        let mut scanner = Scanner::new(r#""a\"b\\"'\'"#);
+
+        let token = scanner.scan_token();
+        assert_eq!(token.kind, TokenKind::String);
+        assert_eq!(token.lexeme, r#""a\"b\\""#);
+        assert_eq!(scanner.scan_token().kind, TokenKind::Error);
+    }
+
+    #[test]
+    fn should_reject_comment_char_colliding_with_syntax() {
+        assert_eq!(check_comment_char(';'), Ok(';'));
+        assert_eq!(check_comment_char('%'), Ok('%'));
+        assert!(check_comment_char('#').is_err());
+        assert!(check_comment_char('7').is_err());
+        assert!(check_comment_char(' ').is_err());
+    }
 }
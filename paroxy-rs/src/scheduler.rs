@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use crate::{
+    error::Result,
+    vm::{HaltReason, StepResult, VM},
+};
+
+/// Runs several programs cooperatively, each on its own tape. Programs
+/// take turns in the order they were given, switching at every `$yield`,
+/// so their output interleaves the same way on every run.
+pub struct Scheduler {
+    ready: VecDeque<VM>,
+}
+
+impl Scheduler {
+    pub fn new(vms: Vec<VM>) -> Self {
+        Self { ready: vms.into() }
+    }
+
+    /// Run every program until all of them halted. Stops at the first
+    /// runtime error. Returns the exit code of the first program to halt
+    /// with a non-zero code, or zero.
+    pub fn run(&mut self) -> Result<u8> {
+        let mut code = 0;
+
+        while let Some(mut vm) = self.ready.pop_front() {
+            match vm.resume()? {
+                StepResult::Yielded => self.ready.push_back(vm),
+                _ => {
+                    if let (0, Some(HaltReason::Exit(exit))) = (code, vm.halt_reason()) {
+                        code = exit;
+                    }
+                }
+            }
+        }
+
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output::Capture, parser::compile};
+
+    #[test]
+    fn should_interleave_at_yields() {
+        let output = Capture::new();
+        let vms = ["'a'$$yield'b'$$yield'c'$", "'1'$$yield'2'$"]
+            .into_iter()
+            .map(|program| VM::with_output(compile(program).unwrap(), Box::new(output.clone())))
+            .collect();
+
+        assert_eq!(Scheduler::new(vms).run().unwrap(), 0);
+        assert_eq!(output.contents(), b"a1b2c");
+    }
+
+    #[test]
+    fn should_return_first_non_zero_exit_code() {
+        let vms = ["$yield$yield$halt(4)", "$halt(0)", "$yield$halt(2)"]
+            .into_iter()
+            .map(|program| VM::with_output(compile(program).unwrap(), Box::new(Capture::new())))
+            .collect();
+
+        assert_eq!(Scheduler::new(vms).run().unwrap(), 2);
+    }
+}
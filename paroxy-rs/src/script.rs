@@ -0,0 +1,92 @@
+/// Name of the binary the generated scripts run the program with.
+const RUNNER: &str = "paroxy-rs";
+
+/// Maximum line width of the embedded base64 data.
+const LINE_WIDTH: usize = 76;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Wrap compiled chunk bytes into a shell script which runs them with the
+/// runner binary on `PATH`. Extra arguments of the script are passed on
+/// to the `run` command.
+pub fn shell_script(chunk: &[u8]) -> String {
+    let encoded = base64(chunk);
+    let data = encoded
+        .as_bytes()
+        .chunks(LINE_WIDTH)
+        .map(|line| String::from_utf8_lossy(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"#!/bin/sh
+# Compiled paroxy program. Requires '{RUNNER}' on PATH.
+set -e
+
+if ! command -v {RUNNER} >/dev/null 2>&1; then
+    echo "error: '{RUNNER}' was not found on PATH, install it to run this program." >&2
+    exit 127
+fi
+
+chunk=$(mktemp)
+trap 'rm -f "$chunk"' EXIT
+
+base64 -d > "$chunk" <<'CHUNK'
+{data}
+CHUNK
+
+{RUNNER} run --file --compiled "$chunk" "$@"
+"#
+    )
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for group in bytes.chunks(3) {
+        let b = [
+            group[0],
+            group.get(1).copied().unwrap_or(0),
+            group.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn should_embed_chunk_in_script() {
+        let script = shell_script(&[0xff; 60]);
+        let data = script
+            .lines()
+            .skip_while(|line| !line.starts_with("base64"))
+            .skip(1)
+            .take_while(|line| *line != "CHUNK")
+            .collect::<Vec<_>>();
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert_eq!(data, vec![&"/".repeat(76)[..], &"/".repeat(4)[..]]);
+    }
+}
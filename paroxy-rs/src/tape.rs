@@ -0,0 +1,664 @@
+use std::{fmt::Display, io, ops::Range, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Value stored in a single tape cell, wide enough for every [`CellWidth`].
+pub type Cell = u32;
+
+/// Number of bits a cell value may use. Arithmetic overflows past the
+/// maximum of the width.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellWidth {
+    #[default]
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+
+impl CellWidth {
+    pub fn bits(&self) -> u32 {
+        match self {
+            CellWidth::Eight => 8,
+            CellWidth::Sixteen => 16,
+            CellWidth::ThirtyTwo => 32,
+        }
+    }
+
+    /// Largest value a cell may hold.
+    pub fn max(&self) -> Cell {
+        Cell::MAX >> (Cell::BITS - self.bits())
+    }
+
+    /// Keep the low bits of the value which fit in a cell.
+    pub fn wrap(&self, value: u32) -> Cell {
+        value & self.max()
+    }
+}
+
+impl Display for CellWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellWidth::Eight),
+            "16" => Ok(CellWidth::Sixteen),
+            "32" => Ok(CellWidth::ThirtyTwo),
+            _ => Err(format!("invalid cell width '{s}', expected 8, 16 or 32")),
+        }
+    }
+}
+
+/// Storage backing the cells of the vm.
+///
+/// Cells are stored with as many bytes as the [`CellWidth`] of the tape
+/// needs, so the values are only read and written one at a time.
+pub trait Tape {
+    fn get(&self, index: usize) -> Cell;
+
+    fn set(&mut self, index: usize, value: Cell);
+
+    fn len(&self) -> usize;
+
+    /// Change the number of cells and store them with the given width.
+    /// New cells are zero unless the backing storage already holds data
+    /// for them.
+    fn resize(&mut self, size: usize, width: CellWidth) -> io::Result<()>;
+
+    /// Make sure all writes reached the underlying storage.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Make sure the cell at the given index exists, growing the tape
+    /// if the storage supports it. Returns whether the cell is available.
+    fn reach(&mut self, index: usize) -> bool {
+        index < self.len()
+    }
+
+    /// Copy the cells in the range, which must be within the tape.
+    fn read(&self, range: Range<usize>) -> Vec<Cell> {
+        range.map(|index| self.get(index)).collect()
+    }
+
+    /// Overwrite the cells from `start` on, which must be within the tape.
+    fn write(&mut self, start: usize, cells: &[Cell]) {
+        for (offset, cell) in cells.iter().enumerate() {
+            self.set(start + offset, *cell);
+        }
+    }
+
+    /// Copy of all cells of the tape.
+    fn cells(&self) -> Vec<Cell> {
+        self.read(0..self.len())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Render the cells up to the last non zero cell or the pointer,
+/// whichever comes later, with the pointed cell in brackets.
+pub fn dump(cells: &[Cell], pointer: usize) -> String {
+    let last = cells.iter().rposition(|cell| *cell != 0).unwrap_or(0);
+    let end = last.max(pointer).min(cells.len().saturating_sub(1));
+
+    cells
+        .iter()
+        .take(end + 1)
+        .enumerate()
+        .map(|(index, cell)| {
+            if index == pointer {
+                format!("[{cell}]")
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Number of cells on each row of [`dump_table`].
+const TABLE_ROW: usize = 16;
+
+/// Render the cells in `range` as rows of decimal values prefixed with the
+/// index of their first cell and followed by their ASCII characters. The
+/// range defaults to the cells [`dump`] would render and is clamped to
+/// the tape.
+pub fn dump_table(cells: &[Cell], pointer: usize, range: Option<Range<usize>>) -> String {
+    let range = range.unwrap_or_else(|| {
+        let last = cells.iter().rposition(|cell| *cell != 0).unwrap_or(0);
+        0..last.max(pointer).saturating_add(1)
+    });
+    let end = range.end.min(cells.len());
+    let start = range.start.min(end);
+
+    let render = |index: usize| {
+        if index == pointer {
+            format!("[{}]", cells[index])
+        } else {
+            cells[index].to_string()
+        }
+    };
+    let width = (start..end)
+        .map(|index| render(index).len())
+        .max()
+        .unwrap_or(0);
+    let index_width = end.saturating_sub(1).to_string().len();
+
+    let mut table = String::new();
+    for row in (start..end).step_by(TABLE_ROW) {
+        let row_end = (row + TABLE_ROW).min(end);
+
+        let values = (row..row_end)
+            .map(|index| format!("{:>width$}", render(index)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = cells[row..row_end]
+            .iter()
+            .map(|cell| match char::from_u32(*cell) {
+                Some(c) if c == ' ' || c.is_ascii_graphic() => c,
+                _ => '.',
+            })
+            .collect::<String>();
+
+        table.push_str(&format!("{row:>index_width$}: {values} |{ascii}|\n"));
+    }
+
+    table
+}
+
+/// Render the cells at most `radius` cells left and right of the pointer,
+/// prefixed with the index of the first rendered cell and with the
+/// pointed cell in brackets.
+pub fn dump_around(cells: &[Cell], pointer: usize, radius: usize) -> String {
+    let start = pointer.saturating_sub(radius).min(cells.len());
+    let end = pointer.saturating_add(radius + 1).min(cells.len());
+
+    let rendered = cells[start..end]
+        .iter()
+        .enumerate()
+        .map(|(offset, cell)| {
+            if start + offset == pointer {
+                format!("[{cell}]")
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!("{start}: {rendered}")
+}
+
+/// In memory cells with as many bytes per cell as the width needs.
+#[derive(Debug)]
+enum Cells {
+    Eight(Vec<u8>),
+    Sixteen(Vec<u16>),
+    ThirtyTwo(Vec<u32>),
+}
+
+impl Default for Cells {
+    fn default() -> Self {
+        Cells::Eight(vec![])
+    }
+}
+
+impl Cells {
+    fn get(&self, index: usize) -> Cell {
+        match self {
+            Cells::Eight(cells) => cells[index] as Cell,
+            Cells::Sixteen(cells) => cells[index] as Cell,
+            Cells::ThirtyTwo(cells) => cells[index],
+        }
+    }
+
+    fn set(&mut self, index: usize, value: Cell) {
+        match self {
+            Cells::Eight(cells) => cells[index] = value as u8,
+            Cells::Sixteen(cells) => cells[index] = value as u16,
+            Cells::ThirtyTwo(cells) => cells[index] = value,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Cells::Eight(cells) => cells.len(),
+            Cells::Sixteen(cells) => cells.len(),
+            Cells::ThirtyTwo(cells) => cells.len(),
+        }
+    }
+
+    /// Change the number of cells, converting the existing cells if the
+    /// width changed. Values wider than the new width keep their low bits.
+    fn resize(&mut self, size: usize, width: CellWidth) {
+        if self.width() != width {
+            let values = (0..self.len()).map(|index| self.get(index));
+            *self = match width {
+                CellWidth::Eight => Cells::Eight(values.map(|value| value as u8).collect()),
+                CellWidth::Sixteen => Cells::Sixteen(values.map(|value| value as u16).collect()),
+                CellWidth::ThirtyTwo => Cells::ThirtyTwo(values.collect()),
+            };
+        }
+
+        match self {
+            Cells::Eight(cells) => cells.resize(size, 0),
+            Cells::Sixteen(cells) => cells.resize(size, 0),
+            Cells::ThirtyTwo(cells) => cells.resize(size, 0),
+        }
+    }
+
+    fn width(&self) -> CellWidth {
+        match self {
+            Cells::Eight(_) => CellWidth::Eight,
+            Cells::Sixteen(_) => CellWidth::Sixteen,
+            Cells::ThirtyTwo(_) => CellWidth::ThirtyTwo,
+        }
+    }
+}
+
+/// Tape kept in memory. This is the default backing.
+#[derive(Debug, Default)]
+pub struct VecTape {
+    cells: Cells,
+}
+
+impl VecTape {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Tape for VecTape {
+    fn get(&self, index: usize) -> Cell {
+        self.cells.get(index)
+    }
+
+    fn set(&mut self, index: usize, value: Cell) {
+        self.cells.set(index, value);
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn resize(&mut self, size: usize, width: CellWidth) -> io::Result<()> {
+        self.cells.resize(size, width);
+        Ok(())
+    }
+}
+
+/// Number of cells above which a [`GrowableTape`] stops growing unless
+/// another limit is given.
+pub const DEFAULT_MAX_TAPE_SIZE: usize = 16 * 1024 * 1024;
+
+/// In memory tape which grows with zero cells whenever a cell past its
+/// end is reached, up to a maximum size.
+#[derive(Debug)]
+pub struct GrowableTape {
+    cells: Cells,
+    max_size: usize,
+}
+
+impl GrowableTape {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            cells: Cells::default(),
+            max_size,
+        }
+    }
+}
+
+impl Tape for GrowableTape {
+    fn get(&self, index: usize) -> Cell {
+        self.cells.get(index)
+    }
+
+    fn set(&mut self, index: usize, value: Cell) {
+        self.cells.set(index, value);
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn resize(&mut self, size: usize, width: CellWidth) -> io::Result<()> {
+        self.cells.resize(size.min(self.max_size), width);
+        Ok(())
+    }
+
+    fn reach(&mut self, index: usize) -> bool {
+        if index >= self.max_size {
+            return false;
+        }
+
+        if index >= self.cells.len() {
+            let width = self.cells.width();
+            self.cells.resize(index + 1, width);
+        }
+
+        true
+    }
+}
+
+#[cfg(unix)]
+pub use mmap::MmapTape;
+
+#[cfg(unix)]
+mod mmap {
+    use std::{fs::File, io, os::unix::io::AsRawFd, path::Path, ptr, slice};
+
+    use super::{Cell, CellWidth, Tape};
+
+    /// Tape backed by a memory mapped file.
+    ///
+    /// Cells are stored in native byte order with as many bytes as the
+    /// cell width needs, so an 8 bit tape takes one byte per cell. The file
+    /// is grown to fit the tape but never truncated, so existing data past
+    /// the tape size is left untouched. Writes are shared with the file and
+    /// synced on [`Tape::flush`] and when the tape is dropped.
+    pub struct MmapTape {
+        file: File,
+        map: *mut u8,
+
+        /// Number of mapped cells.
+        size: usize,
+        width: CellWidth,
+    }
+
+    impl MmapTape {
+        /// Open (or create) the file at the given path as an empty tape.
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+
+            Ok(Self {
+                file,
+                map: ptr::null_mut(),
+                size: 0,
+                width: CellWidth::default(),
+            })
+        }
+
+        /// Bytes of the cell at the given index.
+        fn bytes(&self, index: usize) -> &[u8] {
+            assert!(index < self.size, "cell {index} is outside the mapped tape");
+            let width = cell_bytes(self.width);
+
+            // SAFETY: the mapping is valid for `size` cells while it is held.
+            unsafe { slice::from_raw_parts(self.map.add(index * width), width) }
+        }
+
+        fn bytes_mut(&mut self, index: usize) -> &mut [u8] {
+            assert!(index < self.size, "cell {index} is outside the mapped tape");
+            let width = cell_bytes(self.width);
+
+            // SAFETY: the mapping is valid for `size` cells and writable.
+            unsafe { slice::from_raw_parts_mut(self.map.add(index * width), width) }
+        }
+
+        fn mapped_bytes(&self) -> usize {
+            self.size * cell_bytes(self.width)
+        }
+
+        fn unmap(&mut self) -> io::Result<()> {
+            if self.map.is_null() {
+                return Ok(());
+            }
+
+            self.flush()?;
+
+            // SAFETY: `map` was returned by a successful `mmap` of `mapped_bytes` bytes.
+            if unsafe { libc::munmap(self.map as *mut libc::c_void, self.mapped_bytes()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            self.map = ptr::null_mut();
+            self.size = 0;
+            Ok(())
+        }
+    }
+
+    impl Tape for MmapTape {
+        fn get(&self, index: usize) -> Cell {
+            match *self.bytes(index) {
+                [byte] => byte as Cell,
+                [a, b] => u16::from_ne_bytes([a, b]) as Cell,
+                [a, b, c, d] => u32::from_ne_bytes([a, b, c, d]),
+                _ => unreachable!("cells are 1, 2 or 4 bytes"),
+            }
+        }
+
+        fn set(&mut self, index: usize, value: Cell) {
+            let bytes = self.bytes_mut(index);
+            match bytes.len() {
+                1 => bytes[0] = value as u8,
+                2 => bytes.copy_from_slice(&(value as u16).to_ne_bytes()),
+                _ => bytes.copy_from_slice(&value.to_ne_bytes()),
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.size
+        }
+
+        /// Fails with [`io::ErrorKind::InvalidData`] if the file does not
+        /// hold a whole number of cells of the width.
+        fn resize(&mut self, size: usize, width: CellWidth) -> io::Result<()> {
+            self.unmap()?;
+
+            let length = self.file.metadata()?.len();
+            if length % cell_bytes(width) as u64 != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("tape file of {length} bytes does not hold whole {width} bit cells"),
+                ));
+            }
+
+            self.width = width;
+            if size == 0 {
+                return Ok(());
+            }
+
+            let bytes = size * cell_bytes(width);
+            if length < bytes as u64 {
+                self.file.set_len(bytes as u64)?;
+            }
+
+            // SAFETY: the file is at least `bytes` bytes long and opened
+            // for reading and writing.
+            let map = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    bytes,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    self.file.as_raw_fd(),
+                    0,
+                )
+            };
+
+            if map == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            self.map = map as *mut u8;
+            self.size = size;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if self.map.is_null() {
+                return Ok(());
+            }
+
+            // SAFETY: `map` was returned by a successful `mmap` of `mapped_bytes` bytes.
+            let size = self.mapped_bytes();
+            if unsafe { libc::msync(self.map as *mut libc::c_void, size, libc::MS_SYNC) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Number of bytes taken by a cell of the given width.
+    fn cell_bytes(width: CellWidth) -> usize {
+        width.bits() as usize / 8
+    }
+
+    impl Drop for MmapTape {
+        fn drop(&mut self) {
+            let _ = self.unmap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_resize_vec_tape_with_zeros() {
+        let mut tape = VecTape::new();
+        tape.resize(4, CellWidth::Eight).unwrap();
+        tape.set(3, 7);
+
+        assert_eq!(tape.cells(), &[0, 0, 0, 7]);
+        assert!(!tape.reach(4));
+    }
+
+    #[test]
+    fn should_keep_cells_when_changing_width() {
+        let mut tape = VecTape::new();
+        tape.resize(2, CellWidth::ThirtyTwo).unwrap();
+        tape.write(0, &[0x1f441, 7]);
+
+        tape.resize(3, CellWidth::Sixteen).unwrap();
+        assert_eq!(tape.cells(), &[0xf441, 7, 0]);
+
+        tape.resize(3, CellWidth::Eight).unwrap();
+        assert_eq!(tape.read(0..2), &[0x41, 7]);
+    }
+
+    #[test]
+    fn should_dump_cells_up_to_pointer_or_last_value() {
+        assert_eq!(dump(&[0, 4, 0, 7, 0, 0], 1), "0 [4] 0 7");
+        assert_eq!(dump(&[0, 4, 0, 0, 0, 0], 3), "0 4 0 [0]");
+        assert_eq!(dump(&[], 0), "");
+    }
+
+    #[test]
+    fn should_dump_table_of_cells() {
+        let mut cells = vec![0; 20];
+        cells[0] = 72;
+        cells[1] = 105;
+        cells[17] = 7;
+
+        assert_eq!(
+            dump_table(&cells, 1, Some(0..3)),
+            "0:    72 [105]     0 |Hi.|\n"
+        );
+        assert_eq!(
+            dump_table(&cells, 16, Some(15..40)),
+            "15:   0 [0]   7   0   0 |.....|\n"
+        );
+        assert_eq!(
+            dump_table(&cells, 0, None),
+            concat!(
+                " 0: [72]  105    0    0    0    0    0    0    0    0    0    0    0    0    0    0 |Hi..............|\n",
+                "16:    0    7 |..|\n",
+            )
+        );
+        assert_eq!(dump_table(&cells, 0, Some(30..40)), "");
+    }
+
+    #[test]
+    fn should_dump_cells_around_pointer() {
+        let cells = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        assert_eq!(dump_around(&cells, 4, 2), "2: 3 4 [5] 6 7");
+        assert_eq!(dump_around(&cells, 0, 2), "0: [1] 2 3");
+        assert_eq!(dump_around(&cells, 7, 2), "5: 6 7 [8]");
+        assert_eq!(dump_around(&[], 0, 2), "0: ");
+    }
+
+    #[test]
+    fn should_grow_until_max_size() {
+        let mut tape = GrowableTape::new(8);
+        tape.resize(2, CellWidth::Sixteen).unwrap();
+
+        assert!(tape.reach(5));
+        assert_eq!(tape.len(), 6);
+
+        assert!(!tape.reach(8));
+        assert_eq!(tape.len(), 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_write_through_to_mapped_file() {
+        let path = std::env::temp_dir().join(format!("paroxy-mmap-{}.tape", std::process::id()));
+        std::fs::write(&path, [1, 2, 3, 4, 5, 6]).unwrap();
+
+        {
+            let mut tape = MmapTape::open(&path).unwrap();
+            tape.resize(4, CellWidth::Eight).unwrap();
+            assert_eq!(tape.cells(), &[1, 2, 3, 4]);
+
+            tape.set(0, 42);
+            tape.resize(8, CellWidth::Eight).unwrap();
+            tape.set(7, 9);
+        }
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents, vec![42, 2, 3, 4, 5, 6, 0, 9]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_map_wide_cells_of_whole_files_only() {
+        let path =
+            std::env::temp_dir().join(format!("paroxy-mmap-wide-{}.tape", std::process::id()));
+        let cells: Vec<u8> = [1u16, 0x1234, 3]
+            .iter()
+            .flat_map(|cell| cell.to_ne_bytes())
+            .collect();
+        std::fs::write(&path, cells).unwrap();
+
+        let mut tape = MmapTape::open(&path).unwrap();
+        tape.resize(3, CellWidth::Sixteen).unwrap();
+        assert_eq!(tape.cells(), &[1, 0x1234, 3]);
+
+        let error = tape.resize(1, CellWidth::ThirtyTwo).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        drop(tape);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_bound_cells_by_width() {
+        assert_eq!(CellWidth::Eight.max(), 255);
+        assert_eq!(CellWidth::Sixteen.max(), 65535);
+        assert_eq!(CellWidth::ThirtyTwo.max(), u32::MAX);
+        assert_eq!(CellWidth::Eight.wrap(0x1f441), 0x41);
+        assert_eq!(CellWidth::Sixteen.wrap(0x1f441), 0xf441);
+
+        assert_eq!("16".parse(), Ok(CellWidth::Sixteen));
+        assert!("64".parse::<CellWidth>().is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    chunk::Chunk,
+    opcode::OpCode,
+    output::Capture,
+    parser::Parser,
+    scanner::Scanner,
+    tape::Cell,
+    vm::{StepResult, VM},
+};
+
+/// State of the vm after executing one instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// The executed opcode.
+    pub opcode: OpCode,
+
+    /// Pointer after the instruction.
+    pub pointer: usize,
+
+    /// Whole tape after the instruction.
+    pub tape: Vec<Cell>,
+
+    /// Bytes printed by the instruction.
+    pub output: Vec<u8>,
+}
+
+impl State {
+    pub fn new(opcode: OpCode, pointer: usize, tape: &[Cell]) -> Self {
+        Self {
+            opcode,
+            pointer,
+            tape: tape.to_vec(),
+            output: vec![],
+        }
+    }
+
+    /// Expect the instruction to print the given bytes.
+    pub fn output(mut self, output: &[u8]) -> Self {
+        self.output = output.to_vec();
+        self
+    }
+}
+
+/// Compile the source, panicking if it does not compile.
+pub fn compile(source: &str) -> Chunk {
+    let mut chunk = Chunk::new();
+    assert!(
+        Parser::new(Scanner::new(source), &mut chunk).compile(),
+        "could not compile {source:?}"
+    );
+
+    chunk
+}
+
+/// Compile the source with optimizations as `-O` does, panicking if it
+/// does not compile.
+pub fn compile_optimized(source: &str) -> Chunk {
+    let mut chunk = Chunk::new();
+    assert!(
+        Parser::new(Scanner::new(source), &mut chunk)
+            .optimize(true)
+            .compile(),
+        "could not compile {source:?}"
+    );
+
+    chunk
+}
+
+/// Compile brainfuck source, treating every other character as a comment.
+/// Panics if it does not compile.
+pub fn compile_bf(source: &str) -> Chunk {
+    let mut chunk = Chunk::new();
+    assert!(
+        Parser::new(Scanner::new(source).bf_compat(true), &mut chunk).compile(),
+        "could not compile {source:?}"
+    );
+
+    chunk
+}
+
+/// Run the chunk to completion, collecting the state after every executed
+/// instruction. Panics on runtime errors.
+pub fn states(chunk: Chunk) -> Vec<State> {
+    let captured = Capture::new();
+    let mut vm = VM::with_output(chunk, Box::new(captured.clone()));
+    let mut states = vec![];
+
+    loop {
+        let opcode = vm
+            .current_instruction()
+            .unwrap_or_else(|| panic!("unknown opcode at offset {}", vm.ip()));
+
+        let result = match vm.step() {
+            Ok(result) => result,
+            Err(error) => panic!("step {} failed: {error}", states.len()),
+        };
+
+        states.push(State {
+            opcode,
+            pointer: vm.pointer(),
+            tape: vm.tape(),
+            output: captured.take(),
+        });
+
+        if let StepResult::Halted = result {
+            return states;
+        }
+    }
+}
+
+/// Run the source one instruction at a time, asserting the state after
+/// every step and that the program halts after the last expected step.
+pub fn assert_steps(source: &str, expected: &[State]) {
+    let actual = states(compile(source));
+
+    for (step, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+        assert_eq!(actual, expected, "state after step {step} of {source:?}");
+    }
+
+    assert_eq!(
+        actual.len(),
+        expected.len(),
+        "number of steps of {source:?}, executed: {:?}",
+        actual.iter().map(|state| state.opcode).collect::<Vec<_>>()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pin_every_step() {
+        assert_steps(
+            "{3}+2>'A'.",
+            &[
+                State::new(OpCode::Constant, 0, &[]),
+                State::new(OpCode::DefineTape, 0, &[0, 0, 0]),
+                State::new(OpCode::Increment, 0, &[2, 0, 0]),
+                State::new(OpCode::ShiftRight, 1, &[2, 0, 0]),
+                State::new(OpCode::Constant, 1, &[2, 0, 0]),
+                State::new(OpCode::WriteString, 1, &[2, 65, 0]),
+                State::new(OpCode::Print, 1, &[2, 65, 0]).output(b"A"),
+                State::new(OpCode::Return, 1, &[2, 65, 0]),
+            ],
+        );
+    }
+
+    #[test]
+    fn should_pin_loops() {
+        assert_steps(
+            "{2}+[->]",
+            &[
+                State::new(OpCode::Constant, 0, &[]),
+                State::new(OpCode::DefineTape, 0, &[0, 0]),
+                State::new(OpCode::IncrementSingular, 0, &[1, 0]),
+                State::new(OpCode::JumpIfZero, 0, &[1, 0]),
+                State::new(OpCode::DecrementSingular, 0, &[0, 0]),
+                State::new(OpCode::ShiftRight, 1, &[0, 0]),
+                State::new(OpCode::Loop, 1, &[0, 0]),
+                State::new(OpCode::JumpIfZero, 1, &[0, 0]),
+                State::new(OpCode::Return, 1, &[0, 0]),
+            ],
+        );
+    }
+
+    #[test]
+    fn should_compile_like_the_command_line() {
+        assert!(!compile("+[-]").code.contains(&(OpCode::ClearCell as u8)));
+        assert!(compile_optimized("+[-]")
+            .code
+            .contains(&(OpCode::ClearCell as u8)));
+        assert_eq!(compile_bf("#1 plus: +").code, compile("+").code);
+    }
+
+    #[test]
+    #[should_panic(expected = "state after step 2")]
+    fn should_report_the_diverging_step() {
+        assert_steps(
+            "{1}+",
+            &[
+                State::new(OpCode::Constant, 0, &[]),
+                State::new(OpCode::DefineTape, 0, &[0]),
+                State::new(OpCode::IncrementSingular, 0, &[2]),
+                State::new(OpCode::Return, 0, &[1]),
+            ],
+        );
+    }
+}
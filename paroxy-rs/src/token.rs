@@ -1,8 +1,22 @@
+/// A token of the source. Positions are those of the first character of
+/// the token.
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
+
+    /// Text of the token, or the message of an error token.
     pub lexeme: String,
     pub line: usize,
+
+    /// Column on the line, starting at 1. Columns count characters, so a
+    /// tab or a multi byte character takes up a single column.
+    pub column: usize,
+
+    /// Byte offset in the source, without its byte order mark.
+    pub offset: usize,
+
+    /// Number of bytes of the source the token spans.
+    pub length: usize,
 }
 
 #[repr(u8)]
@@ -22,29 +36,35 @@ pub enum TokenKind {
     Dot,
     Star,
     Caret,
+    Question,
+    Colon,
     Plus,
     Minus,
+    LeftParen,
+    RightParen,
 
     // Literals.
     Integer,
     String,
+    Intrinsic,
+    Subroutine,
+    Call,
 
     // Misc.
     Ignore,
     Error,
-    EOF,
+    Eof,
 }
 
 impl Token {
-    pub fn new(kind: TokenKind, lexeme: String, line: usize) -> Self {
-        Self { kind, lexeme, line }
-    }
-
     pub fn empty() -> Self {
         Self {
             kind: TokenKind::Error,
             lexeme: String::from(""),
             line: 0,
+            column: 0,
+            offset: 0,
+            length: 0,
         }
     }
 }
@@ -0,0 +1,463 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::{Rc, Weak},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    output::{Capture, Tee},
+    tape::{self, Cell, CellWidth, Tape},
+    vm::{StepResult, VM},
+};
+
+pub const HELP: &str = "\
+Commands:
+  step [N], s [N]  replay the next N steps (default 1)
+  back [N], b [N]  go back N steps (default 1)
+  goto N           go to the state after N steps
+  where, w         show the next instruction and the pointer
+  get N            print the value of cell N
+  tape             print the tape
+  output, o        print the output written so far
+  help, h          show this help
+  quit, q          stop replaying";
+
+/// Effect of one executed instruction, as written to a trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    /// Number of instructions executed before this one.
+    pub step: usize,
+
+    /// Offset of the instruction in the chunk.
+    pub offset: usize,
+
+    /// Name of the executed opcode.
+    #[serde(rename = "op")]
+    pub opcode: String,
+
+    /// Pointer after the instruction.
+    pub pointer: usize,
+
+    /// Length of the tape after the instruction.
+    pub size: usize,
+
+    /// Cells which changed, with their new values.
+    pub writes: Vec<(usize, Cell)>,
+
+    /// Bytes printed by the instruction.
+    pub output: Vec<u8>,
+}
+
+/// Run the program, writing one json line per executed instruction to the
+/// trace. Executes at most `limit` instructions if given. The program
+/// output still reaches the writer of the vm.
+pub fn record(vm: &mut VM, trace: &mut dyn Write, limit: Option<usize>) -> Result<StepResult> {
+    let captured = Capture::new();
+    let output = vm.take_output();
+    vm.set_output(Box::new(Tee::new(output, captured.clone())));
+
+    // The tape stops logging once the log is dropped after recording.
+    let log = Rc::new(RefCell::new(WriteLog::default()));
+    let tape = vm.take_tape();
+    vm.set_tape(Box::new(RecordingTape {
+        tape,
+        log: Rc::downgrade(&log),
+    }));
+
+    let result = record_steps(vm, trace, &captured, &log, limit);
+    vm.flush()?;
+    trace.flush()?;
+
+    result
+}
+
+fn record_steps(
+    vm: &mut VM,
+    trace: &mut dyn Write,
+    captured: &Capture,
+    log: &RefCell<WriteLog>,
+    limit: Option<usize>,
+) -> Result<StepResult> {
+    let mut count = 0;
+
+    loop {
+        if limit.is_some_and(|limit| count >= limit) {
+            return Ok(StepResult::Continue);
+        }
+
+        let offset = vm.ip();
+        let opcode = match vm.current_instruction() {
+            Some(opcode) => format!("{opcode:?}"),
+            None => String::from("<unknown>"),
+        };
+
+        let result = vm.step()?;
+
+        let step = Step {
+            step: count,
+            offset,
+            opcode,
+            pointer: vm.pointer(),
+            size: vm.tape_len(),
+            writes: log.borrow_mut().take(vm.tape_len(), |index| vm.cell(index)),
+            output: captured.take(),
+        };
+        serde_json::to_writer(&mut *trace, &step).map_err(io::Error::from)?;
+        writeln!(trace)?;
+
+        count += 1;
+        if let StepResult::Halted = result {
+            return Ok(result);
+        }
+    }
+}
+
+/// Read the steps of a trace written by [`record`].
+pub fn parse(trace: &str) -> Result<Vec<Step>> {
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|error| Error::Trace {
+                line: index + 1,
+                message: error.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Cells the vm wrote during the current step, with their values before.
+#[derive(Default)]
+struct WriteLog {
+    writes: Vec<(usize, Cell)>,
+
+    /// All cells before the tape was resized, which may change any of them.
+    before: Option<Vec<Cell>>,
+}
+
+impl WriteLog {
+    /// The cells of a tape of `len` cells which changed since the last
+    /// call, with their new values, in order of their index.
+    fn take(&mut self, len: usize, cell: impl Fn(usize) -> Option<Cell>) -> Vec<(usize, Cell)> {
+        let changed = |index: usize, before: Cell| {
+            let cell = cell(index)?;
+            (cell != before).then_some((index, cell))
+        };
+
+        let writes = match self.before.take() {
+            Some(before) => (0..len)
+                .filter_map(|index| changed(index, before.get(index).copied().unwrap_or(0)))
+                .collect(),
+            None => {
+                // Keep the value before the first write of every cell.
+                self.writes.sort_by_key(|(index, _)| *index);
+                self.writes.dedup_by_key(|(index, _)| *index);
+                self.writes
+                    .iter()
+                    .filter_map(|(index, before)| changed(*index, *before))
+                    .collect()
+            }
+        };
+
+        self.writes.clear();
+        writes
+    }
+}
+
+/// Tape logging the writes of the vm, so recording a step only compares
+/// the cells it wrote instead of the whole tape.
+struct RecordingTape {
+    tape: Box<dyn Tape>,
+    log: Weak<RefCell<WriteLog>>,
+}
+
+impl RecordingTape {
+    fn save_cells(&self) {
+        if let Some(log) = self.log.upgrade() {
+            let mut log = log.borrow_mut();
+            if log.before.is_none() {
+                log.before = Some(self.tape.cells());
+            }
+        }
+    }
+}
+
+impl Tape for RecordingTape {
+    fn resize(&mut self, size: usize, width: CellWidth) -> io::Result<()> {
+        self.save_cells();
+        self.tape.resize(size, width)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tape.flush()
+    }
+
+    fn reach(&mut self, index: usize) -> bool {
+        self.tape.reach(index)
+    }
+
+    fn get(&self, index: usize) -> Cell {
+        self.tape.get(index)
+    }
+
+    fn set(&mut self, index: usize, value: Cell) {
+        if let Some(log) = self.log.upgrade() {
+            log.borrow_mut().writes.push((index, self.tape.get(index)));
+        }
+
+        self.tape.set(index, value);
+    }
+
+    fn len(&self) -> usize {
+        self.tape.len()
+    }
+}
+
+/// Command understood by the [`Replay`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Back(usize),
+    Goto(usize),
+    Where,
+    Get(usize),
+    Tape,
+    Output,
+    Help,
+    Quit,
+}
+
+/// Reconstructs the machine state at any step of a trace.
+pub struct Replay {
+    steps: Vec<Step>,
+
+    /// Number of steps applied to the state.
+    position: usize,
+    cells: Vec<Cell>,
+    pointer: usize,
+    output: Vec<u8>,
+}
+
+impl Replay {
+    pub fn new(steps: Vec<Step>) -> Self {
+        Self {
+            steps,
+            position: 0,
+            cells: vec![],
+            pointer: 0,
+            output: vec![],
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Move to the state after the given number of steps, clamped to the
+    /// length of the trace.
+    pub fn seek(&mut self, position: usize) {
+        let position = position.min(self.steps.len());
+
+        if position < self.position {
+            self.position = 0;
+            self.cells.clear();
+            self.pointer = 0;
+            self.output.clear();
+        }
+
+        for step in &self.steps[self.position..position] {
+            self.cells.resize(step.size, 0);
+            for (index, cell) in &step.writes {
+                if let Some(target) = self.cells.get_mut(*index) {
+                    *target = *cell;
+                }
+            }
+
+            self.pointer = step.pointer;
+            self.output.extend_from_slice(&step.output);
+        }
+
+        self.position = position;
+    }
+
+    /// Execute the command and return the text to show to the user.
+    pub fn execute(&mut self, command: Command) -> String {
+        match command {
+            Command::Step(count) => {
+                self.seek(self.position.saturating_add(count));
+                self.location()
+            }
+            Command::Back(count) => {
+                self.seek(self.position.saturating_sub(count));
+                self.location()
+            }
+            Command::Goto(position) => {
+                self.seek(position);
+                self.location()
+            }
+            Command::Where => self.location(),
+            Command::Get(index) => match self.cells.get(index) {
+                Some(value) => format!("[{index}] = {value}"),
+                None => format!("Cell {index} is outside the tape."),
+            },
+            Command::Tape => tape::dump(&self.cells, self.pointer),
+            Command::Output => String::from_utf8_lossy(&self.output).into_owned(),
+            Command::Help => String::from(HELP),
+            Command::Quit => String::new(),
+        }
+    }
+
+    fn location(&self) -> String {
+        let total = self.steps.len();
+
+        let next = match self.steps.get(self.position) {
+            Some(step) => format!("{:04} {}", step.offset, step.opcode),
+            None => String::from("end of trace"),
+        };
+
+        match self.cells.get(self.pointer) {
+            Some(cell) => format!(
+                "#{}/{total} {next} [{}] = {cell}",
+                self.position, self.pointer
+            ),
+            None => format!("#{}/{total} {next} [{}]", self.position, self.pointer),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or("step");
+        let argument = words.next();
+
+        let number = |what: &str| -> std::result::Result<usize, String> {
+            let word = argument.ok_or_else(|| format!("Expect {what} after '{name}'."))?;
+
+            word.parse::<usize>()
+                .map_err(|_| format!("Expect {what} to be a number, found '{word}'."))
+        };
+
+        let command = match name {
+            "step" | "s" => Command::Step(argument.map_or(Ok(1), |_| number("count"))?),
+            "back" | "b" => Command::Back(argument.map_or(Ok(1), |_| number("count"))?),
+            "goto" => Command::Goto(number("step")?),
+            "where" | "w" => Command::Where,
+            "get" => Command::Get(number("cell index")?),
+            "tape" => Command::Tape,
+            "output" | "o" => Command::Output,
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => {
+                return Err(format!(
+                    "Unknown command '{name}'. Type 'help' for commands."
+                ))
+            }
+        };
+
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::sink;
+
+    use super::*;
+    use crate::parser::compile;
+
+    fn trace(program: &str) -> String {
+        let mut vm = VM::with_output(compile(program).unwrap(), Box::new(sink()));
+        let mut trace = vec![];
+        record(&mut vm, &mut trace, None).unwrap();
+
+        String::from_utf8(trace).unwrap()
+    }
+
+    #[test]
+    fn should_record_one_line_per_step() {
+        let trace = trace("{2}+>+2.");
+        let lines = trace.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 7);
+        assert!(lines[4].contains("\"pointer\":1,\"size\":2,\"writes\":[[1,2]]"));
+        assert!(lines[5].ends_with("\"writes\":[],\"output\":[2]}"));
+    }
+
+    #[test]
+    fn should_read_recorded_steps() {
+        let steps = parse(&trace("{2}+>+2.")).unwrap();
+
+        assert_eq!(steps.len(), 7);
+        assert_eq!(steps[5].output, vec![2]);
+        assert_eq!(steps[4].writes, vec![(1, 2)]);
+
+        let step = &steps[4];
+        let json = serde_json::to_string(step).unwrap();
+        assert_eq!(&serde_json::from_str::<Step>(&json).unwrap(), step);
+
+        assert!(matches!(
+            parse("\n{\"offset\":1}"),
+            Err(Error::Trace { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn should_record_writes_of_every_instruction() {
+        for program in [
+            "{4}+3>+5<$sort(0, 2)",
+            "{2}+>+{4}>>+2[-]",
+            "{3}+2[>+<-]>[-]",
+        ] {
+            let mut vm = VM::with_output(compile(program).unwrap(), Box::new(sink()));
+            let mut trace = vec![];
+            record(&mut vm, &mut trace, None).unwrap();
+
+            let mut replay = Replay::new(parse(&String::from_utf8(trace).unwrap()).unwrap());
+            replay.seek(usize::MAX);
+            assert_eq!(replay.cells(), vm.tape(), "{program}");
+        }
+    }
+
+    #[test]
+    fn should_replay_forwards_and_back() {
+        let mut replay = Replay::new(parse(&trace("{4}+>+2'hi'$")).unwrap());
+
+        replay.execute(Command::Step(5));
+        assert_eq!((replay.cells(), replay.pointer()), (&[1, 2, 0, 0][..], 1));
+
+        replay.execute(Command::Goto(100));
+        assert_eq!(replay.output(), b"hi");
+
+        replay.execute(Command::Back(100));
+        assert_eq!(replay.position(), 0);
+        assert!(replay.cells().is_empty());
+    }
+
+    #[test]
+    fn should_parse_commands() {
+        assert_eq!("".parse(), Ok(Command::Step(1)));
+        assert_eq!("b 3".parse(), Ok(Command::Back(3)));
+        assert_eq!("goto 12".parse(), Ok(Command::Goto(12)));
+        assert!("goto".parse::<Command>().is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{scanner::Scanner, token::TokenKind};
+
+/// Maximum line width of the generated source.
+const LINE_WIDTH: usize = 80;
+
+/// Translate a brainfuck program into equivalent paroxy source.
+///
+/// Runs of the same increment, decrement or move command are collapsed
+/// into their sized counterparts (`+++` becomes `+3`). Everything that is
+/// not a brainfuck command is dropped.
+pub fn translate(source: &str) -> String {
+    let mut scanner = Scanner::new(source).bf_compat(true);
+    let mut operations: Vec<String> = vec![];
+
+    let mut run: Option<(TokenKind, usize)> = None;
+    loop {
+        let token = scanner.scan_token();
+
+        match (&mut run, &token.kind) {
+            (_, TokenKind::Ignore) => continue,
+            (Some((kind, count)), next) if kind == next => {
+                *count += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        if let Some((kind, count)) = run.take() {
+            push_run(&mut operations, kind, count);
+        }
+
+        match token.kind {
+            TokenKind::Plus | TokenKind::Minus | TokenKind::LeftAngle | TokenKind::RightAngle => {
+                run = Some((token.kind, 1));
+            }
+            TokenKind::Eof => break,
+            _ => operations.push(token.lexeme),
+        }
+    }
+
+    wrap_lines(&operations)
+}
+
+fn push_run(operations: &mut Vec<String>, kind: TokenKind, count: usize) {
+    let symbol = match kind {
+        TokenKind::Plus => '+',
+        TokenKind::Minus => '-',
+        TokenKind::LeftAngle => '<',
+        TokenKind::RightAngle => '>',
+        _ => unreachable!("only sized commands are collapsed"),
+    };
+
+    // Cell arithmetic only takes a single byte operand.
+    let limit = match kind {
+        TokenKind::Plus | TokenKind::Minus => u8::MAX as usize,
+        _ => usize::MAX,
+    };
+
+    let mut remaining = count;
+    while remaining > 0 {
+        let size = remaining.min(limit);
+        remaining -= size;
+
+        if size == 1 {
+            operations.push(symbol.to_string());
+        } else {
+            operations.push(format!("{symbol}{size}"));
+        }
+    }
+}
+
+fn wrap_lines(operations: &[String]) -> String {
+    let mut output = String::new();
+    let mut width = 0;
+
+    for operation in operations {
+        if width + operation.len() > LINE_WIDTH {
+            output.push('\n');
+            width = 0;
+        }
+
+        output.push_str(operation);
+        width += operation.len();
+    }
+
+    if !output.is_empty() {
+        output.push('\n');
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_collapse_runs() {
+        assert_eq!(translate("+++>>--<,."), "+3>2-2<,.\n");
+    }
+
+    #[test]
+    fn should_split_runs_larger_than_a_byte() {
+        let source = "+".repeat(300);
+
+        assert_eq!(translate(&source), "+255+45\n");
+    }
+
+    #[test]
+    fn should_drop_comments() {
+        assert_eq!(translate("Set #0 to 2 ++ [loop ->+<]"), "+2[->+<]\n");
+    }
+}
@@ -1,54 +1,654 @@
-use std::io::stdin;
+use std::{
+    env,
+    fmt::Display,
+    io::{self, stdout, Read, Write},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
-use crate::chunk::{Chunk, Value};
-use crate::debug::{disassemble_instruction, DEBUG_TRACE_EXECUTION};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{self, Chunk, ChunkError, Value};
+use crate::debug::{disassemble_instruction, instruction_to_string, DEBUG_TRACE_EXECUTION};
+use crate::error::{Error, Location, Result, RuntimeError};
+use crate::input::Input;
 use crate::opcode::OpCode;
+use crate::profile::{OpcodeCounts, Profile};
+use crate::tape::{Cell, Tape, VecTape};
+
+/// Instructions executed between two checks of the timeout.
+const TIMEOUT_CHECK_INTERVAL: usize = 1024;
+
+/// Subroutine calls which may be nested before the program fails, which
+/// stops runaway recursion.
+pub const MAX_CALL_DEPTH: usize = 10_000;
 
 pub struct VM {
     chunk: Chunk,
-    tape: Vec<u8>,
-    tape_size: usize,
+    tape: Box<dyn Tape>,
+    ptr: usize,
+    stack: Vec<Value>,
+    ip: usize,
+
+    /// Offsets to continue at when the subroutines being executed return,
+    /// innermost last.
+    calls: Vec<usize>,
+    input: Input,
+    output: Box<dyn Write>,
+    profile: Option<Profile>,
+    opcode_counts: Option<OpcodeCounts>,
+    stop_on_output: Option<Cell>,
+    halt_reason: Option<HaltReason>,
+    sandbox: bool,
+    redefine_tape: TapeRedefinition,
+    eof: EofMode,
+
+    /// Arithmetic wraps around the cell width instead of failing.
+    wrapping: bool,
+
+    /// `,` reads a single byte and printing writes every cell as one byte,
+    /// instead of lines and UTF-8 encoded characters.
+    byte_io: bool,
+
+    /// Receives a line for every instruction before it executes.
+    trace: Option<Box<dyn Write>>,
+
+    /// A [`OpCode::DefineTape`] already executed.
+    tape_defined: bool,
+
+    /// Offset of the instruction being executed.
+    instruction: usize,
+}
+
+/// Copy of the execution state of a vm which can be restored later.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// [`Chunk::fingerprint`] of the chunk the snapshot was taken of.
+    chunk: u64,
+    cells: Vec<Cell>,
     ptr: usize,
     stack: Vec<Value>,
     ip: usize,
+    calls: Vec<usize>,
+    halt_reason: Option<HaltReason>,
+    tape_defined: bool,
 }
 
-macro_rules! into_instruction {
-    ($byte:expr) => {
-        $byte.try_into().expect("Could not convert u8 into opcode.")
-    };
+/// Bytes every serialized snapshot starts with.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"PXS\0";
+
+/// Version of the serialized snapshot layout, written little endian after
+/// the magic.
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// Failure to load or restore a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The data does not start with [`SNAPSHOT_MAGIC`].
+    BadMagic,
+
+    /// The data was serialized with another layout version.
+    VersionMismatch { found: u16 },
+
+    /// The data could not be decoded into a snapshot.
+    Malformed(bincode::Error),
+
+    /// The pointer lies outside the tape of the snapshot.
+    PointerOutOfBounds,
+
+    /// The snapshot was taken of another chunk than the one loaded.
+    ChunkMismatch,
+}
+
+/// Result of [`OpCode::FindByte`] when no cell in the range matches.
+const NOT_FOUND: Cell = 255;
+
+/// Outcome of executing a single instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepResult {
+    /// More instructions are left to execute.
+    Continue,
+
+    /// The program reached its end.
+    Halted,
+
+    /// The program let other interleaved programs run. More instructions
+    /// are left to execute.
+    Yielded,
+}
+
+/// What executing [`OpCode::DefineTape`] does when the tape is already
+/// defined.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TapeRedefinition {
+    /// Zero every cell and move the pointer back to the first cell.
+    Reset,
+
+    /// Grow or shrink the tape, keeping the cells both sizes share. Fails
+    /// if the pointer would end up outside the tape.
+    #[default]
+    Preserve,
+
+    /// Fail with a runtime error.
+    Error,
+}
+
+/// What reading input writes to the current cell once the input ended.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EofMode {
+    /// Write zero.
+    #[default]
+    Zero,
+
+    /// Leave the cell unchanged.
+    Unchanged,
+
+    /// Write the largest cell value, 255 with 8 bit cells.
+    NegativeOne,
+}
+
+/// Why the program stopped running.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HaltReason {
+    /// The program reached its end.
+    Return,
+
+    /// The program printed the sentinel byte.
+    Sentinel(Cell),
+
+    /// The program halted with an explicit exit code.
+    Exit(u8),
+}
+
+impl Snapshot {
+    /// Serialize the snapshot behind its magic and layout version.
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend(SNAPSHOT_VERSION.to_le_bytes());
+        chunk::options()
+            .serialize_into(&mut bytes, self)
+            .map_err(Error::Serialize)?;
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, SnapshotError> {
+        let reader = bytes
+            .strip_prefix(&SNAPSHOT_MAGIC)
+            .ok_or(SnapshotError::BadMagic)?;
+
+        let reader = match reader {
+            [a, b, rest @ ..] => {
+                let found = u16::from_le_bytes([*a, *b]);
+                if found != SNAPSHOT_VERSION {
+                    return Err(SnapshotError::VersionMismatch { found });
+                }
+
+                rest
+            }
+            _ => {
+                let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+                return Err(SnapshotError::Malformed(bincode::ErrorKind::Io(eof).into()));
+            }
+        };
+
+        // Limit reads to the data so garbage lengths cannot allocate more.
+        let snapshot: Snapshot = chunk::options()
+            .with_limit(reader.len() as u64)
+            .deserialize(reader)
+            .map_err(SnapshotError::Malformed)?;
+
+        if snapshot.ptr > 0 && snapshot.ptr >= snapshot.cells.len() {
+            return Err(SnapshotError::PointerOutOfBounds);
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a paroxy snapshot"),
+            SnapshotError::VersionMismatch { found } => write!(
+                f,
+                "snapshot format version {found} is not supported, expected {SNAPSHOT_VERSION}"
+            ),
+            SnapshotError::Malformed(error) => write!(f, "{error}"),
+            SnapshotError::PointerOutOfBounds => write!(f, "pointer lies outside the tape"),
+            SnapshotError::ChunkMismatch => {
+                write!(f, "it was taken of another program")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Malformed(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl VM {
     pub fn new(chunk: Chunk) -> Self {
+        Self::with_output(chunk, Box::new(stdout()))
+    }
+
+    /// Create a vm which writes the program output to the given writer.
+    pub fn with_output(chunk: Chunk, output: Box<dyn Write>) -> Self {
         Self {
             chunk,
-            tape: vec![],
-            tape_size: 0,
+            tape: Box::new(VecTape::new()),
             stack: vec![],
             ptr: 0,
             ip: 0,
+            calls: vec![],
+            input: Input::default(),
+            output,
+            profile: None,
+            opcode_counts: None,
+            stop_on_output: None,
+            halt_reason: None,
+            sandbox: false,
+            redefine_tape: TapeRedefinition::default(),
+            eof: EofMode::default(),
+            wrapping: false,
+            byte_io: false,
+            trace: None,
+            tape_defined: false,
+            instruction: 0,
+        }
+    }
+
+    /// Create a vm which reads the program input from the given reader and
+    /// writes the program output to the given writer.
+    pub fn with_io(chunk: Chunk, input: Box<dyn Read>, output: Box<dyn Write>) -> Self {
+        let mut vm = Self::with_output(chunk, output);
+        vm.set_input(input);
+        vm
+    }
+
+    /// Load a vm directly from serialized bytecode, skipping compilation.
+    ///
+    /// Precompiled programs can be baked into an application with
+    /// `include_bytes!("program.pxb")` and executed without their source.
+    ///
+    /// ```
+    /// # use std::{cell::RefCell, io::Write, rc::Rc};
+    /// # use paroxy::{chunk::Chunk, parser::Parser, scanner::Scanner, vm::VM};
+    /// # #[derive(Clone, Default)]
+    /// # struct Output(Rc<RefCell<Vec<u8>>>);
+    /// # impl Write for Output {
+    /// #     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    /// #         self.0.borrow_mut().write(buf)
+    /// #     }
+    /// #     fn flush(&mut self) -> std::io::Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # let mut chunk = Chunk::new();
+    /// # Parser::new(Scanner::new("'Hi!'$"), &mut chunk).compile();
+    /// # let program = chunk.as_bytes()?;
+    /// // let program: &[u8] = include_bytes!("hello.pxb");
+    /// let mut vm = VM::from_bytes(&program)?;
+    ///
+    /// let output = Output::default();
+    /// vm.set_output(Box::new(output.clone()));
+    /// vm.run()?;
+    ///
+    /// assert_eq!(output.0.borrow().as_slice(), b"Hi!");
+    /// # Ok::<(), paroxy::error::Error>(())
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, ChunkError> {
+        Ok(Self::new(Chunk::from_bytes(bytes)?))
+    }
+
+    /// Replace the chunk being executed while keeping the tape and pointer,
+    /// so the new chunk continues where the previous one stopped.
+    pub fn load(&mut self, chunk: Chunk) {
+        self.chunk = chunk;
+        self.stack.clear();
+        self.ip = 0;
+        self.calls.clear();
+        self.halt_reason = None;
+
+        if self.profile.is_some() {
+            self.enable_profiling();
+        }
+    }
+
+    /// Replace the writer the program output is written to.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Take the writer the program output is written to, leaving one
+    /// which discards the output.
+    pub fn take_output(&mut self) -> Box<dyn Write> {
+        std::mem::replace(&mut self.output, Box::new(io::sink()))
+    }
+
+    /// Read the program input from the given reader instead of stdin.
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = Input::new(input);
+    }
+
+    /// Replace the storage backing the tape. The tape is sized once the
+    /// program defines it.
+    pub fn set_tape(&mut self, tape: Box<dyn Tape>) {
+        self.tape = tape;
+    }
+
+    /// Take the tape of the vm, leaving an empty one.
+    pub fn take_tape(&mut self) -> Box<dyn Tape> {
+        std::mem::replace(&mut self.tape, Box::new(VecTape::new()))
+    }
+
+    /// Record execution counts and timings for every executed instruction.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.chunk.code.len()));
+    }
+
+    /// Count how many times every opcode executes.
+    pub fn enable_opcode_counts(&mut self) {
+        self.opcode_counts = Some(OpcodeCounts::default());
+    }
+
+    /// Halt as soon as the program prints the given byte.
+    pub fn set_stop_on_output(&mut self, sentinel: Option<Cell>) {
+        self.stop_on_output = sentinel;
+    }
+
+    /// Forbid instructions which access the environment of the process.
+    pub fn set_sandbox(&mut self, enabled: bool) {
+        self.sandbox = enabled;
+    }
+
+    /// Choose what happens when the program defines the tape again.
+    pub fn set_redefine_tape(&mut self, policy: TapeRedefinition) {
+        self.redefine_tape = policy;
+    }
+
+    /// Choose what reading input does once the input ended.
+    pub fn set_eof_mode(&mut self, mode: EofMode) {
+        self.eof = mode;
+    }
+
+    /// Let adding to or subtracting from a cell wrap around the cell width
+    /// instead of failing with [`RuntimeError::CellOverflow`].
+    pub fn set_wrapping(&mut self, wrapping: bool) {
+        self.wrapping = wrapping;
+    }
+
+    /// Read input and write output one byte at a time as brainfuck does:
+    /// `,` reads a single byte instead of keeping the first character of
+    /// a line, and printing writes the low byte of every cell instead of
+    /// its UTF-8 encoding.
+    pub fn set_byte_io(&mut self, byte_io: bool) {
+        self.byte_io = byte_io;
+    }
+
+    /// Write the offset, disassembly, pointer and current cell of every
+    /// instruction to the given writer before executing it.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+
+    /// Why the program stopped, if it halted.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason
+    }
+
+    /// Whether the program halted. Stepping a halted program does nothing
+    /// until another chunk is loaded.
+    pub fn is_halted(&self) -> bool {
+        self.halt_reason.is_some()
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn opcode_counts(&self) -> Option<&OpcodeCounts> {
+        self.opcode_counts.as_ref()
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+
+    /// Copy of all cells of the tape.
+    pub fn tape(&self) -> Vec<Cell> {
+        self.tape.cells()
+    }
+
+    /// Number of cells of the tape.
+    pub fn tape_len(&self) -> usize {
+        self.tape.len()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.ptr
+    }
+
+    /// Offset of the next instruction to execute.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// The next instruction to execute.
+    pub fn current_instruction(&self) -> Option<OpCode> {
+        let byte = *self.chunk.code.get(self.ip)?;
+        OpCode::try_from(byte).ok()
+    }
+
+    /// Value of the cell at the given index, if it exists.
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        (index < self.tape.len()).then(|| self.tape.get(index))
+    }
+
+    /// Copy the tape, pointer and position in the chunk.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            chunk: self.chunk.fingerprint(),
+            cells: self.tape.cells(),
+            ptr: self.ptr,
+            stack: self.stack.clone(),
+            ip: self.ip,
+            calls: self.calls.clone(),
+            halt_reason: self.halt_reason,
+            tape_defined: self.tape_defined,
+        }
+    }
+
+    /// Return to the state of the snapshot. Fails if the snapshot was
+    /// taken of another chunk.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.chunk != self.chunk.fingerprint() {
+            return Err(SnapshotError::ChunkMismatch.into());
+        }
+
+        self.tape
+            .resize(snapshot.cells.len(), self.chunk.cell_width)?;
+        self.tape.write(0, &snapshot.cells);
+
+        self.ptr = snapshot.ptr;
+        self.stack = snapshot.stack.clone();
+        self.ip = snapshot.ip;
+        self.calls = snapshot.calls.clone();
+        self.halt_reason = snapshot.halt_reason;
+        self.tape_defined = snapshot.tape_defined;
+        Ok(())
+    }
+
+    /// Overwrite the cell at the given index.
+    pub fn set_cell(&mut self, index: usize, value: u32) -> Result<()> {
+        if index >= self.tape.len() {
+            return Err(Error::Runtime {
+                error: RuntimeError::CellOutOfBounds,
+                location: None,
+            });
+        }
+
+        if value > self.chunk.cell_width.max() {
+            return Err(Error::Runtime {
+                error: RuntimeError::InvalidArgument("Cell value exceeds the cell width."),
+                location: None,
+            });
+        }
+
+        self.tape.set(index, value);
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        self.run_with_limits(None, None)
+    }
+
+    /// Execute at most the given number of instructions. Returns
+    /// [`StepResult::Continue`] when the program was paused before halting.
+    pub fn run_steps(&mut self, limit: usize) -> Result<StepResult> {
+        self.run_until(Some(limit))
+    }
+
+    /// Run the program to completion, failing with
+    /// [`RuntimeError::StepLimitExceeded`] instead of executing more than
+    /// the given number of instructions. The program may be continued
+    /// after the failure.
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Result<()> {
+        self.run_with_limits(Some(max_steps), None)
+    }
+
+    /// Run the program to completion, failing with [`RuntimeError::Timeout`]
+    /// once it ran for longer than the given duration. The time is checked
+    /// every [`TIMEOUT_CHECK_INTERVAL`] instructions. The program may be
+    /// continued after the failure.
+    pub fn run_with_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.run_with_limits(None, Some(timeout))
+    }
+
+    /// Run the program to completion within both an optional step limit,
+    /// see [`VM::run_with_limit`], and an optional timeout, see
+    /// [`VM::run_with_timeout`].
+    pub fn run_with_limits(
+        &mut self,
+        max_steps: Option<u64>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let limit = max_steps.map(|steps| usize::try_from(steps).unwrap_or(usize::MAX));
+
+        let result = self.execute(limit, false, timeout);
+        self.flush()?;
+
+        match (result?, max_steps) {
+            (StepResult::Continue, Some(max_steps)) => {
+                Err(self.limit_error(RuntimeError::StepLimitExceeded(max_steps)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Execute until the program yields or halts.
+    pub fn resume(&mut self) -> Result<StepResult> {
+        let result = self.execute(None, true, None);
+        self.flush()?;
+
+        result
+    }
+
+    /// Write out buffered program output and tape contents. Only needed
+    /// after driving the vm with [`VM::step`].
+    pub fn flush(&mut self) -> Result<()> {
+        self.output.flush()?;
+        self.tape.flush()?;
+        if let Some(trace) = &mut self.trace {
+            trace.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn run_until(&mut self, limit: Option<usize>) -> Result<StepResult> {
+        let result = self.execute(limit, false, None);
+        self.flush()?;
+
+        result
+    }
+
+    fn execute(
+        &mut self,
+        limit: Option<usize>,
+        stop_on_yield: bool,
+        timeout: Option<Duration>,
+    ) -> Result<StepResult> {
+        let started = Instant::now();
+        let mut steps = 0;
+
+        loop {
+            if limit.is_some_and(|limit| steps >= limit) {
+                return Ok(StepResult::Continue);
+            }
+
+            if let Some(timeout) = timeout {
+                if steps % TIMEOUT_CHECK_INTERVAL == 0 && started.elapsed() >= timeout {
+                    return Err(self.limit_error(RuntimeError::Timeout(timeout)));
+                }
+            }
+
+            match self.step()? {
+                StepResult::Halted => return Ok(StepResult::Halted),
+                StepResult::Yielded if stop_on_yield => return Ok(StepResult::Yielded),
+                _ => (),
+            }
+
+            steps += 1;
         }
     }
 
-    pub fn run(&mut self) {
+    /// Decode and execute exactly one instruction. Returns
+    /// [`StepResult::Halted`] without executing anything once the program
+    /// halted.
+    pub fn step(&mut self) -> Result<StepResult> {
+        if self.is_halted() {
+            return Ok(StepResult::Halted);
+        }
+
+        self.instruction = self.ip;
+
         macro_rules! read_byte {
             () => {{
-                self.ip += 1;
-                self.chunk.code[self.ip - 1]
+                match self.chunk.code.get(self.ip) {
+                    Some(byte) => {
+                        self.ip += 1;
+                        *byte
+                    }
+                    None => return Err(self.runtime_error(RuntimeError::UnexpectedEnd)),
+                }
             }};
         }
 
         macro_rules! read_instruction {
-            () => {
-                into_instruction!(read_byte!())
-            };
+            () => {{
+                let byte = read_byte!();
+                match OpCode::try_from(byte) {
+                    Ok(instruction) => instruction,
+                    Err(_) => return Err(self.runtime_error(RuntimeError::InvalidOpcode(byte))),
+                }
+            }};
         }
 
         macro_rules! read_constant {
-            () => {
-                self.chunk.constants[read_byte!() as usize].clone()
-            };
+            ($index:expr) => {{
+                let index = $index;
+                match self.chunk.constants.get(index as usize) {
+                    Some(value) => value.clone(),
+                    None => return Err(self.runtime_error(RuntimeError::InvalidConstant(index))),
+                }
+            }};
         }
 
         macro_rules! read_short {
@@ -60,221 +660,1687 @@ impl VM {
             }};
         }
 
+        macro_rules! read_word {
+            () => {{
+                let high = read_short!();
+                let low = read_short!();
+
+                ((high as u32) << 16) | low as u32
+            }};
+        }
+
         macro_rules! current_cell {
-            () => {
-                self.tape[self.ptr]
-            };
+            () => {{
+                self.check_pointer()?;
+                self.tape.get(self.ptr)
+            }};
         }
 
-        loop {
-            if DEBUG_TRACE_EXECUTION {
-                let safe_cell = if self.tape.len() > self.ptr {
-                    format!("{}->{}", self.ptr, self.tape[self.ptr])
-                } else {
-                    String::new()
-                };
+        macro_rules! set_current_cell {
+            ($value:expr) => {{
+                let value = $value;
+                self.check_pointer()?;
+                self.tape.set(self.ptr, value)
+            }};
+        }
 
-                print!("          ");
-                print!("[{safe_cell}] ");
-                for value in self.stack.iter() {
-                    print!("[ {value} ]");
-                }
-                println!();
+        if DEBUG_TRACE_EXECUTION {
+            let safe_cell = if self.tape.len() > self.ptr {
+                format!("{}->{}", self.ptr, self.tape.get(self.ptr))
+            } else {
+                String::new()
+            };
 
-                disassemble_instruction(&self.chunk, self.ip);
+            print!("          ");
+            print!("[{safe_cell}] ");
+            for value in self.stack.iter() {
+                print!("[ {value} ]");
             }
+            println!();
 
-            let instruction: OpCode = read_instruction!();
+            disassemble_instruction(&self.chunk, self.ip);
+        }
 
-            match instruction {
-                OpCode::DefineTape => {
-                    if let Value::Int(value) = self.stack_pop() {
-                        self.tape.resize(value as usize, 0);
-                        self.tape_size = value as usize;
-                    } else {
-                        self.runtime_error("Expect an integer.");
-                    }
-                }
-                OpCode::PointerValue => {
-                    let value = current_cell!();
-                    self.stack.push(Value::Int(value as u32));
-                }
-                OpCode::SetPointer => {
-                    let value = self.stack_pop();
-                    if let Value::Int(value) = value {
-                        if (value as usize) < self.tape.len() {
-                            self.ptr = value as usize;
-                        } else {
-                            self.runtime_error("Tape overflow.");
-                        }
-                    } else {
-                        self.runtime_error("Expect an integer.");
-                    }
-                }
-                OpCode::Constant => {
-                    self.stack.push(read_constant!());
-                }
-                OpCode::MoveLeft => {
-                    let value = self.stack_pop();
-                    if let Value::Int(value) = value {
-                        if self.ptr >= value as usize {
-                            self.ptr -= value as usize;
-                        } else {
-                            self.runtime_error("Pointer cannot move below zero.");
-                        }
-                    } else {
-                        self.runtime_error("Expect an integer.");
-                    }
-                }
-                OpCode::MoveRight => {
-                    let value = self.stack_pop();
-                    if let Value::Int(value) = value {
-                        if (self.ptr + value as usize) <= self.tape_size {
-                            self.ptr += value as usize;
-                        } else {
-                            self.runtime_error("Pointer exceeds tape size.");
-                        }
-                    } else {
-                        self.runtime_error("Expect an integer.");
-                    }
-                }
-                OpCode::ShiftLeft => {
-                    self.ptr -= 1;
+        if let Some(trace) = &mut self.trace {
+            let cell = match self.tape.len() > self.ptr {
+                true => self.tape.get(self.ptr).to_string(),
+                false => String::from("-"),
+            };
+            let instruction = instruction_to_string(&self.chunk, self.ip);
+
+            writeln!(trace, "{instruction:40} ptr {} cell {cell}", self.ptr)?;
+        }
+
+        if let Some(profile) = &mut self.profile {
+            profile.record(self.ip);
+        }
+
+        let instruction: OpCode = read_instruction!();
+
+        if let Some(counts) = &mut self.opcode_counts {
+            counts.record(instruction);
+        }
+
+        match instruction {
+            // The opcodes most programs spend their time in come first.
+            OpCode::ShiftLeft => {
+                if self.ptr == 0 {
+                    return Err(self.runtime_error(RuntimeError::PointerUnderflow));
                 }
-                OpCode::ShiftRight => {
+
+                self.ptr -= 1;
+            }
+            OpCode::ShiftRight => {
+                if self.tape.reach(self.ptr + 1) {
                     self.ptr += 1;
+                } else {
+                    return Err(self.pointer_out_of_bounds(self.ptr + 1));
                 }
-                OpCode::Increment => {
-                    let value = read_byte!();
-                    let available = u8::MAX - current_cell!();
-                    if available > value {
-                        current_cell!() += value as u8;
-                    } else {
-                        self.runtime_error(
-                            format!(
-                                "Cannot be greater than {} [{}]",
-                                u8::MAX,
-                                value + current_cell!()
-                            )
-                            .as_str(),
-                        );
-                    }
-                }
-                OpCode::Decrement => {
-                    let value = read_byte!();
-                    let available = current_cell!() - u8::MIN;
-                    if available > value {
-                        current_cell!() -= value as u8;
-                    } else {
-                        self.runtime_error(
-                            format!(
-                                "Cannot be less than {} [{}]",
-                                u8::MIN,
-                                value - current_cell!()
-                            )
-                            .as_str(),
-                        );
-                    }
+            }
+            OpCode::Increment => {
+                let value = read_byte!();
+                self.add_to_cell(value as i64)?;
+            }
+            OpCode::Decrement => {
+                let value = read_byte!();
+                self.add_to_cell(-(value as i64))?;
+            }
+            OpCode::IncrementSingular => self.add_to_cell(1)?,
+            OpCode::DecrementSingular => self.add_to_cell(-1)?,
+            OpCode::ClearCell => set_current_cell!(0),
+            OpCode::ScanRight => {
+                let len = self.tape.len();
+                if self.ptr > len {
+                    return Err(self.pointer_out_of_bounds(self.ptr));
                 }
-                OpCode::IncrementSingular => {
-                    current_cell!() += 1;
+                match (self.ptr..len).find(|index| self.tape.get(*index) == 0) {
+                    Some(index) => self.ptr = index,
+                    // Move past the end like the loop would, which grows
+                    // a growable tape by a zero cell.
+                    None if self.tape.reach(len) => self.ptr = len,
+                    None => return Err(self.pointer_out_of_bounds(len)),
                 }
-                OpCode::DecrementSingular => {
-                    current_cell!() -= 1;
+            }
+            OpCode::ScanLeft => {
+                if self.ptr >= self.tape.len() {
+                    return Err(self.pointer_out_of_bounds(self.ptr));
                 }
-                OpCode::WriteString => {
-                    let value = self.stack_pop();
-                    if let Value::String(value) = value {
-                        for (i, c) in value.chars().enumerate() {
-                            self.tape[self.ptr + i] = c as u8;
-                        }
-                    } else {
-                        self.runtime_error("Expect a string value.");
-                    }
+                match (0..=self.ptr)
+                    .rev()
+                    .find(|index| self.tape.get(*index) == 0)
+                {
+                    Some(index) => self.ptr = index,
+                    None => return Err(self.runtime_error(RuntimeError::PointerUnderflow)),
                 }
-                OpCode::WriteCell => {
-                    current_cell!() = read_byte!();
+            }
+            OpCode::JumpIfZero | OpCode::JumpIfZeroWide => {
+                let offset = match instruction {
+                    OpCode::JumpIfZero => read_short!() as usize,
+                    _ => read_word!() as usize,
+                };
+                if self.ip + offset > self.chunk.code.len() {
+                    return Err(self.runtime_error(RuntimeError::InvalidJump));
                 }
-                OpCode::Print => {
-                    print!("{}", current_cell!() as char);
 
-                    // Make sure stack trace is on the next line.
-                    if DEBUG_TRACE_EXECUTION {
-                        println!();
-                    }
+                if current_cell!() == 0 {
+                    self.ip += offset;
+                }
+            }
+            OpCode::Loop | OpCode::LoopWide => {
+                let offset = match instruction {
+                    OpCode::Loop => read_short!() as usize,
+                    _ => read_word!() as usize,
+                };
+                match self.ip.checked_sub(offset) {
+                    Some(ip) => self.ip = ip,
+                    None => return Err(self.runtime_error(RuntimeError::InvalidJump)),
                 }
-                OpCode::PrintRange => {
-                    let value = self.stack_pop();
-                    if let Value::Int(value) = value {
-                        let range = &self.tape[self.ptr..self.ptr + value as usize];
-                        let output = range.iter().map(|c| *c as char).collect::<String>();
-                        print!("{output}");
+            }
+            OpCode::DefineTape => {
+                let size = self.pop_int()? as usize;
 
-                        // Make sure stack trace is on the next line.
-                        if DEBUG_TRACE_EXECUTION {
-                            println!();
+                if self.tape_defined {
+                    match self.redefine_tape {
+                        TapeRedefinition::Error => {
+                            return Err(self.runtime_error(RuntimeError::TapeRedefined))
+                        }
+                        TapeRedefinition::Preserve if self.ptr >= size => {
+                            let index = self.ptr;
+                            let error = RuntimeError::PointerOutOfBounds {
+                                index,
+                                tape_size: size,
+                            };
+                            return Err(self.runtime_error(error));
+                        }
+                        TapeRedefinition::Preserve => (),
+                        TapeRedefinition::Reset => {
+                            for index in 0..self.tape.len() {
+                                self.tape.set(index, 0);
+                            }
+                            self.ptr = 0;
                         }
-                    } else {
-                        self.runtime_error("Expect a number.");
-                    }
-                }
-                OpCode::Input => {
-                    let mut line = String::new();
-                    stdin().read_line(&mut line).unwrap();
-                    match line.chars().nth(0) {
-                        Some(char) => current_cell!() = char as u8,
-                        None => (),
                     }
                 }
-                OpCode::MultiInput => {
-                    let flags = read_byte!();
-
-                    let mut line = String::new();
-                    stdin().read_line(&mut line).unwrap();
 
-                    if line.len() < (self.tape_size - self.ptr - 1) {
-                        let move_pointer_flag = flags & 0x00000001 == 0x00000001;
+                self.tape.resize(size, self.chunk.cell_width)?;
+                self.tape_defined = true;
 
-                        for (i, c) in line.chars().enumerate() {
-                            self.tape[self.ptr + i] = c as u8;
-                        }
-
-                        if move_pointer_flag {
-                            self.ptr += line.len();
-                        }
-                    } else {
-                        self.runtime_error("The input is too large for following cells");
-                    }
+                if !self.tape.reach(self.ptr) {
+                    return Err(self.pointer_out_of_bounds(self.ptr));
                 }
-                OpCode::JumpIfZero => {
-                    let offset = read_short!();
-                    if current_cell!() == 0 {
-                        self.ip += offset as usize;
-                    }
+            }
+            OpCode::PointerValue => {
+                let value = current_cell!();
+                self.stack.push(Value::Int(value));
+            }
+            OpCode::SetPointer => {
+                let value = self.pop_int()?;
+                if self.tape.reach(value as usize) {
+                    self.ptr = value as usize;
+                } else {
+                    return Err(self.pointer_out_of_bounds(value as usize));
                 }
-                OpCode::Loop => {
-                    let offset = read_short!();
-                    self.ip -= offset as usize;
+            }
+            OpCode::Constant => {
+                self.stack.push(read_constant!(read_byte!() as u16));
+            }
+            OpCode::Constant16 => {
+                self.stack.push(read_constant!(read_short!()));
+            }
+            OpCode::MoveLeft => {
+                let value = self.pop_int()?;
+                if self.ptr >= value as usize {
+                    self.ptr -= value as usize;
+                } else {
+                    return Err(self.runtime_error(RuntimeError::PointerUnderflow));
+                }
+            }
+            OpCode::MoveRight => {
+                let value = self.pop_int()?;
+                if self.tape.reach(self.ptr + value as usize) {
+                    self.ptr += value as usize;
+                } else {
+                    return Err(self.pointer_out_of_bounds(self.ptr + value as usize));
+                }
+            }
+            OpCode::IncrementBy => {
+                let value = self.pop_int()?;
+                self.add_to_cell(value as i64)?;
+            }
+            OpCode::DecrementBy => {
+                let value = self.pop_int()?;
+                self.add_to_cell(-(value as i64))?;
+            }
+            OpCode::WriteString => {
+                let value = self.pop_string()?;
+                let length = value.chars().count();
+                if length > 0 && !self.tape.reach(self.ptr + length - 1) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                // The parser rejects such strings, but chunks may be assembled.
+                let max = self.chunk.cell_width.max();
+                if let Some(c) = value.chars().find(|c| *c as u32 > max) {
+                    let value = c as i64;
+                    return Err(self.runtime_error(RuntimeError::CellOverflow { value, max }));
+                }
+
+                for (i, c) in value.chars().enumerate() {
+                    self.tape.set(self.ptr + i, c as Cell);
+                }
+            }
+            OpCode::WriteCell => {
+                set_current_cell!(read_byte!() as Cell);
+            }
+            OpCode::WriteValue => {
+                let value = self.pop_int()?;
+                if value > self.chunk.cell_width.max() {
+                    let value = value as i64;
+                    let max = self.chunk.cell_width.max();
+                    return Err(self.runtime_error(RuntimeError::CellOverflow { value, max }));
+                }
+
+                set_current_cell!(value);
+            }
+            OpCode::Print => {
+                let cell = current_cell!();
+
+                if self.byte_io {
+                    self.output.write_all(&[cell as u8])?;
+                } else {
+                    let mut buffer = [0; 4];
+                    let output = as_char(cell).encode_utf8(&mut buffer);
+                    self.output.write_all(output.as_bytes())?;
+                }
+
+                // Make sure stack trace is on the next line.
+                if DEBUG_TRACE_EXECUTION {
+                    println!();
+                }
+
+                if self.stop_on_output == Some(cell) {
+                    self.halt_reason = Some(HaltReason::Sentinel(cell));
+                    return Ok(StepResult::Halted);
+                }
+            }
+            OpCode::PrintRange => {
+                let value = self.pop_int()?;
+                if value > 0 && !self.tape.reach(self.ptr + value as usize - 1) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                let mut range = self.tape.read(self.ptr..self.ptr + value as usize);
+
+                // Only print up to and including the sentinel.
+                let sentinel = self
+                    .stop_on_output
+                    .and_then(|sentinel| range.iter().position(|c| *c == sentinel));
+                if let Some(position) = sentinel {
+                    range.truncate(position + 1);
+                }
+
+                if self.byte_io {
+                    let output = range.iter().map(|c| *c as u8).collect::<Vec<_>>();
+                    self.output.write_all(&output)?;
+                } else {
+                    let output = range.iter().map(|c| as_char(*c)).collect::<String>();
+                    self.output.write_all(output.as_bytes())?;
+                }
+
+                // Make sure stack trace is on the next line.
+                if DEBUG_TRACE_EXECUTION {
+                    println!();
+                }
+
+                if let Some(position) = sentinel {
+                    let cell = self.tape.get(self.ptr + position);
+                    self.halt_reason = Some(HaltReason::Sentinel(cell));
+                    return Ok(StepResult::Halted);
+                }
+            }
+            OpCode::PrintNumber => {
+                let cell = current_cell!();
+                write!(self.output, "{cell}")?;
+
+                // Make sure stack trace is on the next line.
+                if DEBUG_TRACE_EXECUTION {
+                    println!();
+                }
+            }
+            OpCode::Input if self.byte_io => match self.input.read_byte()? {
+                Some(byte) => set_current_cell!(byte as Cell),
+                None => self.write_eof()?,
+            },
+            OpCode::Input => {
+                let line = self.input.read_line()?;
+                match line.chars().next() {
+                    Some(char) => set_current_cell!(self.chunk.cell_width.wrap(char as u32)),
+                    None => self.write_eof()?,
+                }
+            }
+            OpCode::MultiInput => {
+                let flags = read_byte!();
+
+                let line = self.input.read_line()?;
+
+                // Every character takes up one cell, like written strings.
+                let length = line.chars().count();
+
+                if line.is_empty() {
+                    self.write_eof()?;
+                } else if self.tape.reach(self.ptr + length + 1) {
+                    let move_pointer_flag = flags & 0x00000001 == 0x00000001;
+
+                    let width = self.chunk.cell_width;
+                    for (i, c) in line.chars().enumerate() {
+                        self.tape.set(self.ptr + i, width.wrap(c as u32));
+                    }
+
+                    if move_pointer_flag {
+                        self.ptr += length;
+                    }
+                } else {
+                    return Err(self.runtime_error(RuntimeError::InputTooLarge));
+                }
+            }
+            OpCode::Pop => {
+                self.stack_pop()?;
+            }
+            OpCode::Call => {
+                let offset = self.pop_int()? as usize;
+                if offset >= self.chunk.code.len() {
+                    return Err(self.runtime_error(RuntimeError::InvalidJump));
+                }
+                if self.calls.len() >= MAX_CALL_DEPTH {
+                    let error = RuntimeError::CallStackOverflow(MAX_CALL_DEPTH);
+                    return Err(self.runtime_error(error));
+                }
+
+                self.calls.push(self.ip);
+                self.ip = offset;
+            }
+            OpCode::Return => match self.calls.pop() {
+                Some(ip) => self.ip = ip,
+                None => {
+                    self.halt_reason = Some(HaltReason::Return);
+                    return Ok(StepResult::Halted);
+                }
+            },
+            OpCode::ClearIf => {
+                let target = self.pop_cell_index()?;
+                let flag = self.pop_cell_index()?;
+
+                if self.tape.get(flag) != 0 {
+                    self.tape.set(target, 0);
+                }
+            }
+            OpCode::DupRight => {
+                if !self.tape.reach(self.ptr + 1) {
+                    return Err(self.pointer_out_of_bounds(self.ptr + 1));
+                }
+
+                let value = self.tape.get(self.ptr);
+                self.tape.set(self.ptr + 1, value);
+            }
+            OpCode::Checksum => {
+                let length = self.pop_int()? as usize;
+                let start = self.pop_cell_index()?;
+
+                if length > 0 && !self.tape.reach(start + length - 1) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                if !self.tape.reach(self.ptr + 3) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                let sum = self
+                    .tape
+                    .read(start..start + length)
+                    .iter()
+                    .fold(0u32, |sum, cell| sum.wrapping_add(*cell));
+
+                self.tape
+                    .write(self.ptr, &sum.to_be_bytes().map(|byte| byte as Cell));
+            }
+            OpCode::Yield => return Ok(StepResult::Yielded),
+            OpCode::ReverseRange => {
+                let length = self.pop_int()? as usize;
+                let start = self.pop_cell_index()?;
+
+                if length > 0 && !self.tape.reach(start + length - 1) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                let mut range = self.tape.read(start..start + length);
+                range.reverse();
+                self.tape.write(start, &range);
+            }
+            OpCode::SortRange => {
+                let descending = read_byte!() & 0x00000001 == 0x00000001;
+                let length = self.pop_int()? as usize;
+                let start = self.pop_cell_index()?;
+
+                if length > 0 && !self.tape.reach(start + length - 1) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                let mut range = self.tape.read(start..start + length);
+                if descending {
+                    range.sort_unstable_by(|a, b| b.cmp(a));
+                } else {
+                    range.sort_unstable();
+                }
+                self.tape.write(start, &range);
+            }
+            OpCode::FindByte => {
+                let result = self.pop_cell_index()?;
+                let value = match self.pop_int()? {
+                    value if value <= self.chunk.cell_width.max() => value,
+                    _ => {
+                        let error =
+                            RuntimeError::InvalidArgument("Expect a value within the cell width.");
+                        return Err(self.runtime_error(error));
+                    }
+                };
+                let length = match self.pop_int()? {
+                    length if length < NOT_FOUND => length as usize,
+                    _ => {
+                        let error = RuntimeError::InvalidArgument("Expect a length between 0-254.");
+                        return Err(self.runtime_error(error));
+                    }
+                };
+                let start = self.pop_cell_index()?;
+
+                if length > 0 && !self.tape.reach(start + length - 1) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                let index = self
+                    .tape
+                    .read(start..start + length)
+                    .iter()
+                    .position(|cell| *cell == value)
+                    .map_or(NOT_FOUND, |index| index as Cell);
+
+                self.tape.set(result, index);
+            }
+            OpCode::Halt => {
+                let code = match self.pop_int()? {
+                    code if code <= u8::MAX as u32 => code as u8,
+                    _ => {
+                        let error =
+                            RuntimeError::InvalidArgument("Expect an exit code between 0-255.");
+                        return Err(self.runtime_error(error));
+                    }
+                };
+
+                self.halt_reason = Some(HaltReason::Exit(code));
+                return Ok(StepResult::Halted);
+            }
+            OpCode::PeekInput => match self.input.peek()? {
+                Some(byte) => set_current_cell!(byte as Cell),
+                None => self.write_eof()?,
+            },
+            OpCode::InputNumber => {
+                let word = self.input.read_word()?;
+                if word.is_empty() {
+                    self.write_eof()?;
+                } else {
+                    let value = match word.parse::<i64>() {
+                        Ok(value) => value,
+                        Err(_) => return Err(self.runtime_error(RuntimeError::InvalidNumber(word))),
+                    };
+
+                    let max = self.chunk.cell_width.max();
+                    if value < 0 || value > max as i64 {
+                        return Err(self.runtime_error(RuntimeError::CellOverflow { value, max }));
+                    }
+
+                    set_current_cell!(value as Cell);
                 }
-                OpCode::Pop => {
-                    self.stack_pop();
+            }
+            OpCode::ReadEnv => {
+                let length_index = self.pop_cell_index()?;
+                let start = self.pop_cell_index()?;
+                let name = self.pop_string()?;
+
+                if self.sandbox {
+                    return Err(self.runtime_error(RuntimeError::Sandboxed));
                 }
-                OpCode::Return => {
-                    break;
+
+                // Missing variables read as an empty string.
+                let value = env::var(name.as_ref()).unwrap_or_default();
+                let length = match u8::try_from(value.len()) {
+                    Ok(length) => length as Cell,
+                    Err(_) => {
+                        let error = RuntimeError::InvalidArgument(
+                            "Environment variable exceeds 255 bytes.",
+                        );
+                        return Err(self.runtime_error(error));
+                    }
+                };
+
+                if !self.tape.reach(start + value.len()) {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
                 }
+
+                let mut cells = value.bytes().map(|byte| byte as Cell).collect::<Vec<_>>();
+                cells.push(0);
+                self.tape.write(start, &cells);
+
+                self.tape.set(length_index, length);
             }
+            OpCode::Transpose => {
+                let dest = self.pop_cell_index()?;
+                let mut dimensions = [0; 2];
+                for dimension in dimensions.iter_mut().rev() {
+                    *dimension = self.pop_int()? as usize;
+                }
+                let [width, height] = dimensions;
+                let start = self.pop_cell_index()?;
+
+                let size = match width.checked_mul(height) {
+                    Some(size) => size,
+                    None => return Err(self.runtime_error(RuntimeError::RangeOutOfBounds)),
+                };
+
+                if size > 0
+                    && !(self.tape.reach(start + size - 1) && self.tape.reach(dest + size - 1))
+                {
+                    return Err(self.runtime_error(RuntimeError::RangeOutOfBounds));
+                }
+
+                // Copy the block first as the destination may overlap it.
+                let block = self.tape.read(start..start + size);
+                let mut cells = vec![0; size];
+                for row in 0..height {
+                    for column in 0..width {
+                        cells[column * height + row] = block[row * width + column];
+                    }
+                }
+                self.tape.write(dest, &cells);
+            }
+        }
+
+        Ok(StepResult::Continue)
+    }
+
+    /// Write the value of the end of input to the current cell.
+    fn write_eof(&mut self) -> Result<()> {
+        self.check_pointer()?;
+        match self.eof {
+            EofMode::Zero => self.tape.set(self.ptr, 0),
+            EofMode::Unchanged => (),
+            EofMode::NegativeOne => self.tape.set(self.ptr, self.chunk.cell_width.max()),
+        }
+
+        Ok(())
+    }
+
+    /// Fail unless the pointer is on a cell, which it is not while no tape
+    /// is defined, for example in a hand written chunk.
+    fn check_pointer(&mut self) -> Result<()> {
+        if self.ptr >= self.tape.len() {
+            return Err(self.pointer_out_of_bounds(self.ptr));
         }
+
+        Ok(())
     }
 
-    fn stack_pop(&mut self) -> Value {
-        self.stack
-            .pop()
-            .take()
-            .expect("Expect stack last item to be filled.")
+    /// Add the amount to the current cell, wrapping around or failing if
+    /// the result does not fit in the cell width.
+    fn add_to_cell(&mut self, amount: i64) -> Result<()> {
+        self.check_pointer()?;
+        let max = self.chunk.cell_width.max();
+        let mut value = self.tape.get(self.ptr) as i64 + amount;
+        if self.wrapping {
+            // Cell widths are powers of two, so keeping the low bits of the
+            // two's complement wraps around like the remainder, only faster.
+            value &= max as i64;
+        } else if value < 0 || value > max as i64 {
+            return Err(self.runtime_error(RuntimeError::CellOverflow { value, max }));
+        }
+
+        self.tape.set(self.ptr, value as Cell);
+        Ok(())
     }
 
-    fn runtime_error(&mut self, message: &str) {
-        eprintln!("{message}");
+    fn stack_pop(&mut self) -> Result<Value> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.runtime_error(RuntimeError::StackUnderflow)),
+        }
+    }
+
+    fn pop_int(&mut self) -> Result<u32> {
+        match self.stack_pop()? {
+            Value::Int(value) => Ok(value),
+            value => Err(self.type_mismatch("an integer", &value)),
+        }
+    }
+
+    fn pop_string(&mut self) -> Result<Rc<str>> {
+        match self.stack_pop()? {
+            Value::String(value) => Ok(value),
+            value => Err(self.type_mismatch("a string", &value)),
+        }
+    }
+
+    /// Pop an integer from the stack which indexes an existing cell.
+    fn pop_cell_index(&mut self) -> Result<usize> {
+        match self.pop_int()? {
+            index if self.tape.reach(index as usize) => Ok(index as usize),
+            _ => Err(self.runtime_error(RuntimeError::CellOutOfBounds)),
+        }
+    }
+
+    /// Error for a value of the wrong type taken by the current instruction,
+    /// which only happens with malformed bytecode.
+    fn type_mismatch(&mut self, expected: &'static str, found: &Value) -> Error {
+        let opcode = self
+            .chunk
+            .code
+            .get(self.instruction)
+            .and_then(|byte| OpCode::try_from(*byte).ok());
+
+        self.runtime_error(RuntimeError::TypeMismatch {
+            opcode,
+            expected,
+            found: found.kind(),
+        })
+    }
+
+    /// Error for the pointer reaching the given cell past the end of the tape.
+    fn pointer_out_of_bounds(&mut self, index: usize) -> Error {
+        let tape_size = self.tape.len();
+        self.runtime_error(RuntimeError::PointerOutOfBounds { index, tape_size })
+    }
+
+    /// Error at the current instruction, located by its source line or,
+    /// for stripped chunks, its offset.
+    fn runtime_error(&mut self, error: RuntimeError) -> Error {
         self.stack.clear();
+
+        Error::Runtime {
+            error,
+            location: Some(self.location(self.instruction)),
+        }
+    }
+
+    /// Error for a limit hit before executing the next instruction. The
+    /// stack is kept so the program may continue.
+    fn limit_error(&self, error: RuntimeError) -> Error {
+        Error::Runtime {
+            error,
+            location: Some(self.location(self.ip)),
+        }
+    }
+
+    fn location(&self, offset: usize) -> Location {
+        match self.chunk.line_for(offset) {
+            Some(line) => Location::Line(line),
+            None => Location::Offset(offset),
+        }
+    }
+}
+
+/// Character printed for a cell. Values which are not a character print
+/// as the replacement character.
+fn as_char(cell: Cell) -> char {
+    char::from_u32(cell).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        io::{sink, Cursor},
+        rc::Rc,
+    };
+
+    use super::*;
+    use crate::{
+        output::Capture,
+        parser::Parser,
+        scanner::Scanner,
+        tape::{CellWidth, GrowableTape},
+        testing::{compile, compile_optimized},
+    };
+
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_reject_constant_of_wrong_type() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::String(Rc::from("big")));
+        for byte in [
+            OpCode::Constant as u8,
+            constant as u8,
+            OpCode::DefineTape as u8,
+        ] {
+            chunk.write_chunk(byte, 1);
+        }
+
+        let error = VM::with_output(chunk, Box::new(sink())).run().unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: [line 1] Type mismatch: DefineTape expects an integer, found a string."
+        );
+    }
+
+    #[test]
+    fn should_reject_popping_empty_stack() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(OpCode::Pop as u8, 1);
+
+        let result = VM::with_output(chunk, Box::new(sink())).run();
+
+        assert!(matches!(result, Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_apply_tape_redefinition_policy() {
+        let run = |policy| {
+            let mut vm = VM::with_output(compile("{4}+>+2>+3{2}"), Box::new(sink()));
+            vm.set_redefine_tape(policy);
+            vm.run().map(|_| (vm.tape().to_vec(), vm.pointer()))
+        };
+
+        assert_eq!(run(TapeRedefinition::Reset).unwrap(), (vec![0, 0], 0));
+        assert!(matches!(
+            run(TapeRedefinition::Error),
+            Err(Error::Runtime { .. })
+        ));
+        assert!(matches!(
+            run(TapeRedefinition::Preserve),
+            Err(Error::Runtime { .. })
+        ));
+
+        let mut vm = VM::with_output(compile("{4}+>+2{2}{3}"), Box::new(sink()));
+        vm.run().unwrap();
+        assert_eq!((vm.tape(), vm.pointer()), (vec![1, 2, 0], 1));
+    }
+
+    #[test]
+    fn should_grow_tape_on_demand() {
+        let mut vm = VM::with_output(compile("{2}>>>+3"), Box::new(sink()));
+        vm.set_tape(Box::new(GrowableTape::new(16)));
+
+        assert!(vm.run().is_ok());
+    }
+
+    #[test]
+    fn should_clear_target_if_flag_is_set() {
+        let mut vm = VM::with_output(
+            compile("#1>#5>#7@0$clearif(0, 1)$clearif(3, 2)"),
+            Box::new(sink()),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(&vm.tape.cells()[0..4], &[1, 0, 7, 0]);
+    }
+
+    #[test]
+    fn should_reject_clear_if_outside_tape() {
+        let mut vm = VM::with_output(compile("{4}$clearif(0, 4)"), Box::new(sink()));
+
+        assert!(matches!(vm.run(), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_duplicate_current_cell_to_the_right() {
+        let mut vm = VM::with_output(compile("{3}#9$dup>+"), Box::new(sink()));
+        vm.run().unwrap();
+
+        assert_eq!(vm.tape.cells(), &[9, 10, 0]);
+    }
+
+    #[test]
+    fn should_reject_duplicating_past_tape() {
+        let mut vm = VM::with_output(compile("{2}>$dup"), Box::new(sink()));
+
+        assert!(matches!(vm.run(), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_write_checksum_of_range() {
+        let mut vm = VM::with_output(compile("{8}#255>#255>#3>$checksum(0, 3)"), Box::new(sink()));
+        vm.run().unwrap();
+
+        assert_eq!(&vm.tape.cells()[3..7], &[0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn should_reject_checksum_outside_tape() {
+        let range = VM::with_output(compile("{8}>4$checksum(4, 5)"), Box::new(sink())).run();
+        let target = VM::with_output(compile("{8}>5$checksum(0, 2)"), Box::new(sink())).run();
+
+        assert!(matches!(range, Err(Error::Runtime { .. })));
+        assert!(matches!(target, Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_reverse_range() {
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::with_output(
+            compile("{8}'abcdef'$reverse(1, 4).6"),
+            Box::new(Output(output.clone())),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(*output.borrow(), b"aedcbf");
+    }
+
+    #[test]
+    fn should_reject_reverse_outside_tape() {
+        let result = VM::with_output(compile("{8}$reverse(4, 5)"), Box::new(sink())).run();
+
+        assert!(matches!(result, Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_sort_range() {
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::with_output(
+            compile("{8}'zdbeac'$sort(1, 4).6$sort(0, 6, 1).6"),
+            Box::new(Output(output.clone())),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(*output.borrow(), b"zabdeczedcba");
+    }
+
+    #[test]
+    fn should_reject_sort_outside_tape() {
+        let result = VM::with_output(compile("{8}$sort(4, 5)"), Box::new(sink())).run();
+
+        assert!(matches!(result, Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_find_byte_in_range() {
+        let mut vm = VM::with_output(
+            compile("{8}'abcb'$find(1, 3, 98, 6)$find(0, 4, 122, 7)"),
+            Box::new(sink()),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(&vm.tape.cells()[6..8], &[0, NOT_FOUND]);
+    }
+
+    #[test]
+    fn should_reject_find_outside_tape() {
+        let range = VM::with_output(compile("{8}$find(4, 5, 0, 0)"), Box::new(sink())).run();
+        let value = VM::with_output(compile("{8}$find(0, 2, 256, 0)"), Box::new(sink())).run();
+        let result = VM::with_output(compile("{8}$find(0, 2, 0, 8)"), Box::new(sink())).run();
+
+        assert!(matches!(range, Err(Error::Runtime { .. })));
+        assert!(matches!(value, Err(Error::Runtime { .. })));
+        assert!(matches!(result, Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_transpose_block() {
+        let mut vm = VM::with_output(
+            compile("{12}#1>#2>#3>#4>#5>#6$transpose(0, 3, 2, 6)$transpose(0, 2, 2, 0)"),
+            Box::new(sink()),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(vm.tape.cells(), &[1, 3, 2, 4, 5, 6, 1, 4, 2, 5, 3, 6]);
+    }
+
+    #[test]
+    fn should_reject_transpose_outside_tape() {
+        let source = VM::with_output(compile("{8}$transpose(4, 3, 2, 0)"), Box::new(sink())).run();
+        let dest = VM::with_output(compile("{8}$transpose(0, 3, 2, 4)"), Box::new(sink())).run();
+
+        assert!(matches!(source, Err(Error::Runtime { .. })));
+        assert!(matches!(dest, Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_read_environment_variable() {
+        env::set_var("PAROXY_TEST_ENV", "abc");
+
+        let mut vm = VM::with_output(
+            compile("{8}#9>#9>#9>#9$env('PAROXY_TEST_ENV', 0, 7)$env('PAROXY_TEST_MISSING', 5, 6)"),
+            Box::new(sink()),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(
+            vm.tape.cells(),
+            &['a' as Cell, 'b' as Cell, 'c' as Cell, 0, 0, 0, 0, 3]
+        );
+    }
+
+    #[test]
+    fn should_write_eof_mode_at_end_of_input() {
+        let run = |source, mode| {
+            let mut vm = VM::with_output(compile(source), Box::new(sink()));
+            vm.set_input(Box::new(Cursor::new("a")));
+            vm.set_eof_mode(mode);
+            vm.run().unwrap();
+
+            vm.tape()[..2].to_vec()
+        };
+
+        assert_eq!(run("{2},>#7,", EofMode::Zero), &[97, 0]);
+        assert_eq!(run("{2},>#7,", EofMode::Unchanged), &[97, 7]);
+        assert_eq!(run("{2},>#7,", EofMode::NegativeOne), &[97, 255]);
+        assert_eq!(run("{3},*>#7,*", EofMode::Zero), &[97, 0]);
+        assert_eq!(run("{3},*>#7,*", EofMode::Unchanged), &[97, 7]);
+        assert_eq!(run("{3},*>#7,*", EofMode::NegativeOne), &[97, 255]);
+    }
+
+    #[test]
+    fn should_read_and_print_single_bytes_with_byte_io() {
+        let run = |byte_io| {
+            let output = Capture::new();
+            let mut vm = VM::with_io(
+                compile("{4},>,>,>#200.<.<.<."),
+                Box::new(Cursor::new("abc\nd")),
+                Box::new(output.clone()),
+            );
+            vm.set_byte_io(byte_io);
+            vm.run().unwrap();
+
+            (vm.tape()[..3].to_vec(), output.contents())
+        };
+
+        // Every ',' reads one byte of the same line.
+        assert_eq!(run(true), (vec![97, 98, 99], b"\xc8cba".to_vec()));
+        assert_eq!(
+            run(false),
+            (vec![97, 100, 0], "\u{c8}\0da".as_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn should_capture_output_of_input_and_output() {
+        let output = Capture::new();
+        let mut vm = VM::with_io(
+            compile("{8},*^'!'.<2.2"),
+            Box::new(Cursor::new("hi\n")),
+            Box::new(output.clone()),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"!i\n");
+    }
+
+    #[test]
+    fn should_peek_input_without_consuming() {
+        let run = |eof: EofMode| {
+            let output = Rc::new(RefCell::new(vec![]));
+            let mut vm =
+                VM::with_output(compile("{4},?.,*.>#7,?."), Box::new(Output(output.clone())));
+            vm.set_input(Box::new(Cursor::new("xy")));
+            vm.set_eof_mode(eof);
+            vm.run().unwrap();
+
+            output.take()
+        };
+
+        // Peeking at the end of input follows the end of input mode.
+        assert_eq!(run(EofMode::Zero), b"xx\x00");
+        assert_eq!(run(EofMode::Unchanged), b"xx\x07");
+    }
+
+    #[test]
+    fn should_write_and_print_strings_by_character() {
+        let output = Capture::new();
+        let mut vm = VM::with_output(compile("{8}'héllo'$^#33."), Box::new(output.clone()));
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), "héllo!".as_bytes());
+        assert_eq!(vm.pointer(), 5);
+        assert_eq!(&vm.tape()[..6], &[104, 233, 108, 108, 111, 33]);
+
+        let string = |width| {
+            let mut chunk = Chunk::new();
+            assert!(Parser::new(Scanner::new("{2}'✓'$"), &mut chunk)
+                .cell_width(width)
+                .compile());
+            chunk
+        };
+
+        let output = Capture::new();
+        VM::with_output(string(CellWidth::Sixteen), Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert_eq!(output.contents(), "✓".as_bytes());
+
+        // The parser rejects characters past the cell width, which only
+        // assembled chunks may hold.
+        let mut chunk = string(CellWidth::Sixteen);
+        chunk.cell_width = CellWidth::Eight;
+        assert!(matches!(
+            VM::with_output(chunk, Box::new(sink())).run(),
+            Err(Error::Runtime {
+                error: RuntimeError::CellOverflow {
+                    value: 0x2713,
+                    max: 255
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_read_input_lines_by_character() {
+        let output = Capture::new();
+        let mut vm = VM::with_io(
+            compile("{10},*^#33."),
+            Box::new(Cursor::new("héllo\n")),
+            Box::new(output.clone()),
+        );
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"!");
+        assert_eq!(vm.pointer(), 6);
+        assert_eq!(&vm.tape()[..7], &[104, 233, 108, 108, 111, 10, 33]);
+    }
+
+    #[test]
+    fn should_print_cells_as_numbers() {
+        let output = Capture::new();
+        let mut vm = VM::with_output(compile("{2}#65:.>+255:"), Box::new(output.clone()));
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"65A255");
+    }
+
+    #[test]
+    fn should_read_numbers_from_input() {
+        let output = Capture::new();
+        let mut vm = VM::with_output(compile("{3},#:>,#:>,#:"), Box::new(output.clone()));
+        vm.set_input(Box::new(Cursor::new(" 42\n7 ")));
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"4270");
+
+        let read = |input: &'static str, cell_width| {
+            let mut chunk = compile(",#");
+            chunk.cell_width = cell_width;
+
+            let mut vm = VM::with_output(chunk, Box::new(sink()));
+            vm.set_input(Box::new(Cursor::new(input)));
+            vm.run().map(|_| vm.cell(0))
+        };
+
+        assert_eq!(read("300", CellWidth::Sixteen).unwrap(), Some(300));
+        assert!(matches!(
+            read("300", CellWidth::Eight),
+            Err(Error::Runtime {
+                error: RuntimeError::CellOverflow {
+                    value: 300,
+                    max: 255
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            read("-1", CellWidth::Eight),
+            Err(Error::Runtime {
+                error: RuntimeError::CellOverflow { value: -1, .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            read("4x", CellWidth::Eight),
+            Err(Error::Runtime {
+                error: RuntimeError::InvalidNumber(word),
+                ..
+            }) if word == "4x"
+        ));
+    }
+
+    #[test]
+    fn should_reject_environment_in_sandbox() {
+        let mut vm = VM::with_output(compile("{8}$env('HOME', 0, 7)"), Box::new(sink()));
+        vm.set_sandbox(true);
+
+        assert!(matches!(vm.run(), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn should_report_line_of_strings() {
+        let error = VM::with_output(compile("{1}\n'ab'"), Box::new(sink()))
+            .run()
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: [line 2] Range exceeds tape size."
+        );
+    }
+
+    #[test]
+    fn should_locate_errors_by_offset_when_stripped() {
+        let mut stripped = compile("{2}+\n>>");
+        stripped.strip();
+
+        let full = VM::with_output(compile("{2}+\n>>"), Box::new(sink())).run();
+        let stripped = VM::with_output(stripped, Box::new(sink())).run();
+
+        let location = |result: Result<()>| match result {
+            Err(Error::Runtime {
+                error: RuntimeError::PointerOutOfBounds { .. },
+                location: Some(location),
+            }) => location,
+            _ => panic!("expected the pointer to leave the tape"),
+        };
+        assert_eq!(location(full), Location::Line(2));
+        // The second shift leaves the tape.
+        assert_eq!(location(stripped), Location::Offset(5));
+
+        let error = VM::with_output(compile("{2}+\n>>"), Box::new(sink()))
+            .run()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: [line 2] Pointer exceeds tape size: cell 2 of a 2 cell tape."
+        );
+    }
+
+    #[test]
+    fn should_report_line_of_failing_instruction() {
+        let source = "{3}\n+5\n[>+<-]\n>-7\n+";
+        let chunk = compile(source);
+        let error = VM::with_output(chunk, Box::new(sink())).run().unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::Runtime {
+                error: RuntimeError::CellOverflow { value: -2, .. },
+                location: Some(Location::Line(4)),
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: [line 4] Cannot be less than 0 [-2]"
+        );
+    }
+
+    #[test]
+    fn should_report_pointer_target_outside_tape() {
+        let error = VM::with_output(compile("+\n@50000"), Box::new(sink()))
+            .run()
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::Runtime {
+                error: RuntimeError::PointerOutOfBounds {
+                    index: 50000,
+                    tape_size: 30000
+                },
+                location: Some(Location::Line(2)),
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Runtime error: [line 2] Pointer exceeds tape size: cell 50000 of a 30000 cell tape."
+        );
+    }
+
+    fn runtime_error(code: &[u8]) -> RuntimeError {
+        let mut chunk = Chunk::new();
+        for byte in code {
+            chunk.write_chunk(*byte, 1);
+        }
+
+        match VM::with_output(chunk, Box::new(sink())).run() {
+            Err(Error::Runtime { error, .. }) => error,
+            result => panic!("expected a runtime error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn should_fail_on_malformed_bytecode_without_panicking() {
+        let jump = OpCode::JumpIfZero as u8;
+        let repeat = OpCode::Loop as u8;
+        let move_left = OpCode::MoveLeft as u8;
+        let constant = OpCode::Constant as u8;
+
+        assert_eq!(
+            runtime_error(&[jump, 0xff, 0xff]),
+            RuntimeError::InvalidJump
+        );
+        assert_eq!(runtime_error(&[repeat, 0, 9]), RuntimeError::InvalidJump);
+        assert_eq!(runtime_error(&[move_left]), RuntimeError::StackUnderflow);
+        assert_eq!(
+            runtime_error(&[constant, 3]),
+            RuntimeError::InvalidConstant(3)
+        );
+        assert_eq!(runtime_error(&[constant]), RuntimeError::UnexpectedEnd);
+        assert_eq!(
+            runtime_error(&[OpCode::Constant16 as u8, 1, 0]),
+            RuntimeError::InvalidConstant(256)
+        );
+        assert_eq!(runtime_error(&[]), RuntimeError::UnexpectedEnd);
+        assert_eq!(runtime_error(&[0xfe]), RuntimeError::InvalidOpcode(0xfe));
+    }
+
+    #[test]
+    fn should_not_touch_cells_before_the_tape_is_defined() {
+        assert_eq!(
+            runtime_error(&[OpCode::IncrementSingular as u8, OpCode::Return as u8]),
+            RuntimeError::PointerOutOfBounds {
+                index: 0,
+                tape_size: 0
+            }
+        );
+
+        // Every instruction either runs or fails without a tape.
+        for opcode in OpCode::ALL {
+            let mut chunk = Chunk::new();
+            chunk.write_chunk(*opcode as u8, 1);
+            for _ in 1..opcode.length() {
+                chunk.write_chunk(0, 1);
+            }
+            chunk.write_chunk(OpCode::Return as u8, 1);
+
+            let mut vm = VM::with_io(chunk, Box::new(std::io::empty()), Box::new(sink()));
+            let _ = vm.run();
+        }
+    }
+
+    #[test]
+    fn should_run_wide_loops() {
+        let body = "><".repeat(u16::MAX as usize / 2);
+        let program = format!("+[-[>]{body}]+[-[{body}]]+");
+        let mut vm = VM::with_output(compile(&program), Box::new(sink()));
+
+        vm.run().unwrap();
+        assert_eq!((vm.pointer(), vm.tape()[0]), (0, 1));
+    }
+
+    #[test]
+    fn should_fail_past_step_limit() {
+        let mut vm = VM::with_output(compile("+\n[]"), Box::new(sink()));
+        match vm.run_with_limit(100) {
+            Err(Error::Runtime { error, location }) => {
+                assert_eq!(error, RuntimeError::StepLimitExceeded(100));
+                assert_eq!(location, Some(Location::Line(2)));
+            }
+            result => panic!("expected the step limit, got {result:?}"),
+        }
+
+        // Define the tape, increment and return.
+        let mut vm = VM::with_output(compile("+3"), Box::new(sink()));
+        assert!(vm.run_with_limit(4).is_ok());
+
+        let mut vm = VM::with_output(compile("+3"), Box::new(sink()));
+        assert!(vm.run_with_limit(3).is_err());
+        assert!(vm.run_with_limit(1).is_ok());
+    }
+
+    #[test]
+    fn should_fail_past_timeout() {
+        let mut vm = VM::with_output(compile("+[]"), Box::new(sink()));
+        match vm.run_with_timeout(Duration::from_millis(10)) {
+            Err(Error::Runtime { error, .. }) => {
+                assert_eq!(error, RuntimeError::Timeout(Duration::from_millis(10)));
+            }
+            result => panic!("expected the timeout, got {result:?}"),
+        }
+
+        // Whichever limit is hit first fails the program.
+        let mut vm = VM::with_output(compile("+[]"), Box::new(sink()));
+        assert!(matches!(
+            vm.run_with_limits(Some(1000), Some(Duration::from_secs(60))),
+            Err(Error::Runtime {
+                error: RuntimeError::StepLimitExceeded(1000),
+                ..
+            })
+        ));
+
+        let mut vm = VM::with_output(compile("+3"), Box::new(sink()));
+        assert!(vm.run_with_timeout(Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn should_trace_every_instruction() {
+        let trace = Capture::new();
+        let mut vm = VM::with_output(compile("{1}\n+"), Box::new(sink()));
+        vm.set_trace(Some(Box::new(trace.clone())));
+        vm.run().unwrap();
+
+        assert_eq!(
+            String::from_utf8(trace.contents()).unwrap(),
+            "0000    1 OP_CONSTANT         0 1        ptr 0 cell -\n\
+             0002    | OP_DEFINE_TAPE                 ptr 0 cell -\n\
+             0003    2 OP_INCREMENT_SINGLE            ptr 0 cell 0\n\
+             0004    | OP_RETURN                      ptr 0 cell 1\n"
+        );
+    }
+
+    #[test]
+    fn should_read_wide_constants() {
+        let program: String = (0..300).map(|i| format!("@{i} ")).collect();
+        let mut vm = VM::with_output(compile(&program), Box::new(sink()));
+
+        vm.run().unwrap();
+        assert_eq!(vm.pointer(), 299);
+    }
+
+    #[test]
+    fn should_run_with_wide_cells() {
+        let run = |source, width| {
+            let mut chunk = Chunk::new();
+            let mut parser = Parser::new(Scanner::new(source), &mut chunk).cell_width(width);
+            assert!(parser.compile());
+            drop(parser);
+
+            let mut vm = VM::with_output(chunk, Box::new(sink()));
+            vm.run().map(|_| vm.tape()[..2].to_vec())
+        };
+
+        assert_eq!(
+            run("#65535-300>+1000", CellWidth::Sixteen).unwrap(),
+            &[65235, 1000]
+        );
+        assert_eq!(
+            run("#4000000000+", CellWidth::ThirtyTwo).unwrap(),
+            &[4000000001, 0]
+        );
+        assert_eq!(run("'ā'", CellWidth::Sixteen).unwrap(), &[0x101, 0]);
+
+        let overflow = RuntimeError::CellOverflow {
+            value: 65536,
+            max: 65535,
+        };
+        assert!(matches!(
+            run("#65535+", CellWidth::Sixteen),
+            Err(Error::Runtime { error, .. }) if error == overflow
+        ));
+    }
+
+    #[test]
+    fn should_fail_on_cell_overflow() {
+        let run = |source| match VM::with_output(compile(source), Box::new(sink())).run() {
+            Err(Error::Runtime { error, .. }) => error,
+            result => panic!("expected a runtime error, got {result:?}"),
+        };
+
+        let overflow = |value| RuntimeError::CellOverflow { value, max: 255 };
+
+        assert_eq!(run("-"), overflow(-1));
+        assert_eq!(run("#255+"), overflow(256));
+        assert_eq!(run("#250+10"), overflow(260));
+        assert_eq!(run("<"), RuntimeError::PointerUnderflow);
+    }
+
+    #[test]
+    fn should_wrap_cells_around_width() {
+        let run = |source| {
+            let mut vm = VM::with_output(compile(source), Box::new(sink()));
+            vm.set_wrapping(true);
+            vm.run().unwrap();
+            vm.tape.get(0)
+        };
+
+        assert_eq!(run("-"), 255);
+        assert_eq!(run("#255+"), 0);
+        assert_eq!(run("#250+10"), 4);
+        assert_eq!(run("-2-255"), 255);
+
+        for (width, max) in [
+            (CellWidth::Sixteen, 0xffff),
+            (CellWidth::ThirtyTwo, u32::MAX),
+        ] {
+            let mut chunk = compile("-2");
+            chunk.cell_width = width;
+            let mut vm = VM::with_output(chunk, Box::new(sink()));
+            vm.set_wrapping(true);
+            vm.run().unwrap();
+            assert_eq!(vm.tape.get(0), max - 1);
+        }
+    }
+
+    #[test]
+    fn should_run_clear_loops_alike_with_and_without_optimizations() {
+        let program = "{3}+5[>+3[-]>+<<-]>:>:+[-]:";
+
+        let run = |optimize: bool| {
+            let chunk = if optimize {
+                compile_optimized(program)
+            } else {
+                compile(program)
+            };
+
+            let output = Capture::new();
+            let mut vm = VM::with_output(chunk, Box::new(output.clone()));
+            vm.run().unwrap();
+            (output.contents(), vm.tape.cells().to_vec())
+        };
+
+        assert_eq!(run(true), run(false));
+        assert_eq!(run(true), (b"050".to_vec(), vec![0, 0, 0]));
+    }
+
+    #[test]
+    fn should_clear_with_incrementing_loops_only_when_wrapping() {
+        let compile = |wrapping: bool| {
+            let mut chunk = Chunk::new();
+            let mut parser = Parser::new(Scanner::new("{1}+5[+]:"), &mut chunk)
+                .optimize(true)
+                .wrapping(wrapping);
+            assert!(parser.compile());
+            drop(parser);
+            chunk
+        };
+
+        let clears = |chunk: &Chunk| chunk.code.contains(&(OpCode::ClearCell as u8));
+        assert!(!clears(&compile(false)));
+        assert!(clears(&compile(true)));
+
+        let output = Capture::new();
+        let mut vm = VM::with_output(compile(true), Box::new(output.clone()));
+        vm.set_wrapping(true);
+        vm.run().unwrap();
+        assert_eq!(output.contents(), b"0");
+    }
+
+    #[test]
+    fn should_run_scan_loops_alike_with_and_without_optimizations() {
+        // Scans stop on the first zero cell in either direction, and
+        // don't move at all when already on one.
+        let program = "{7}>+>+>+>>+<<<<[>]>[>]:<[<]:[<]<[<]:";
+
+        let run = |optimize: bool| {
+            let chunk = if optimize {
+                compile_optimized(program)
+            } else {
+                compile(program)
+            };
+
+            let output = Capture::new();
+            let mut vm = VM::with_output(chunk, Box::new(output.clone()));
+            let result = vm.run().map_err(|error| error.to_string());
+            (result, output.contents(), vm.ptr)
+        };
+
+        assert_eq!(run(true), run(false));
+        assert_eq!(run(true), (Ok(()), b"000".to_vec(), 0));
+    }
+
+    #[test]
+    fn should_fail_scans_past_the_tape_like_loops() {
+        let run = |program: &str, optimize: bool| {
+            let chunk = if optimize {
+                compile_optimized(program)
+            } else {
+                compile(program)
+            };
+
+            let mut vm = VM::with_output(chunk, Box::new(sink()));
+            match vm.run() {
+                Err(Error::Runtime { error, .. }) => error,
+                result => panic!("expected runtime error, got {result:?}"),
+            }
+        };
+
+        for program in ["{2}+>+<[>]", "{2}+>+[<]"] {
+            assert_eq!(run(program, true), run(program, false));
+        }
+        assert_eq!(run("{2}+>+[<]", true), RuntimeError::PointerUnderflow);
+    }
+
+    #[test]
+    fn should_halt_with_exit_code() {
+        let mut vm = VM::with_output(compile("+$halt(3)+"), Box::new(sink()));
+        vm.run().unwrap();
+
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Exit(3)));
+        assert_eq!(vm.tape.get(0), 1);
+    }
+
+    #[test]
+    fn should_stop_on_sentinel_output() {
+        let output = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::with_output(
+            compile("'ab'$'c'.+3.'d'."),
+            Box::new(Output(output.clone())),
+        );
+        vm.set_stop_on_output(Some('b' as Cell));
+
+        vm.run().unwrap();
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Sentinel('b' as Cell)));
+        assert_eq!(*output.borrow(), b"ab");
+
+        vm.load(compile("'ab'$'c'.+3."));
+        vm.set_stop_on_output(Some('f' as Cell));
+
+        vm.run().unwrap();
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Sentinel('f' as Cell)));
+        assert_eq!(*output.borrow(), b"ababcf");
+    }
+
+    #[test]
+    fn should_pause_after_given_steps() {
+        let mut vm = VM::with_output(compile("{3}+>+2>+3"), Box::new(sink()));
+
+        // Defining the tape takes two instructions.
+        assert!(matches!(vm.run_steps(5), Ok(StepResult::Continue)));
+        assert_eq!(vm.tape.cells(), &[1, 2, 0]);
+
+        assert!(matches!(vm.run_steps(10), Ok(StepResult::Halted)));
+        assert_eq!(vm.tape.cells(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn should_call_subroutines_defined_anywhere() {
+        // Calls nest, loop and reach subroutines defined after them.
+        let program = "{4} !digits()
+            :digit(+48. #0)
+            :digits(+3[>!digit()<-] !newline())
+            :newline(#10. #0)";
+
+        let output = Capture::new();
+        let mut vm = VM::with_output(compile(program), Box::new(output.clone()));
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"000\n");
+        assert!(vm.calls.is_empty());
+    }
+
+    #[test]
+    fn should_leave_prose_around_sigils_alone() {
+        let output = Capture::new();
+        let program = "{2}+49. Hello!world :done\n!count() done!\n:count(>+48.<)";
+        VM::with_output(compile(program), Box::new(output.clone()))
+            .run()
+            .unwrap();
+
+        assert_eq!(output.contents(), b"1490");
+    }
+
+    #[test]
+    fn should_fail_on_runaway_recursion() {
+        let mut vm = VM::with_output(compile(":loop(!loop()) !loop()"), Box::new(sink()));
+
+        assert!(matches!(
+            vm.run(),
+            Err(Error::Runtime {
+                error: RuntimeError::CallStackOverflow(MAX_CALL_DEPTH),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_restore_snapshot_inside_subroutine() {
+        let program = "{2}:two(++) !two() !two()";
+        let mut vm = VM::with_output(compile(program), Box::new(sink()));
+        assert!(matches!(vm.run_steps(5), Ok(StepResult::Continue)));
+        assert_eq!(vm.calls.len(), 1);
+
+        let bytes = vm.snapshot().as_bytes().unwrap();
+        let mut resumed = VM::with_output(compile(program), Box::new(sink()));
+        resumed
+            .restore(&Snapshot::from_bytes(&bytes).unwrap())
+            .unwrap();
+
+        resumed.run().unwrap();
+        assert_eq!(resumed.tape.cells(), &[4, 0]);
+    }
+
+    #[test]
+    fn should_resume_from_serialized_snapshot() {
+        let program = "{3}+>+2>+3";
+        let mut vm = VM::with_output(compile(program), Box::new(sink()));
+        assert!(matches!(vm.run_steps(5), Ok(StepResult::Continue)));
+
+        let bytes = vm.snapshot().as_bytes().unwrap();
+        let mut resumed = VM::with_output(compile(program), Box::new(sink()));
+        resumed
+            .restore(&Snapshot::from_bytes(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(resumed.tape.cells(), &[1, 2, 0]);
+        assert_eq!(resumed.pointer(), 1);
+
+        assert!(matches!(resumed.run_steps(10), Ok(StepResult::Halted)));
+        assert_eq!(resumed.tape.cells(), &[1, 2, 3]);
+
+        let mut other = VM::with_output(compile("{3}+>+2>+4"), Box::new(sink()));
+        assert!(matches!(
+            other.restore(&Snapshot::from_bytes(&bytes).unwrap()),
+            Err(Error::Snapshot(SnapshotError::ChunkMismatch))
+        ));
+    }
+
+    #[test]
+    fn should_reject_invalid_snapshot_bytes() {
+        let bytes = VM::with_output(compile("{2}>"), Box::new(sink()))
+            .snapshot()
+            .as_bytes()
+            .unwrap();
+
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[1..]),
+            Err(SnapshotError::BadMagic)
+        ));
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[..SNAPSHOT_MAGIC.len() + 1]),
+            Err(SnapshotError::Malformed(_))
+        ));
+
+        let mut other_version = bytes.clone();
+        other_version[SNAPSHOT_MAGIC.len()] = 9;
+        assert!(matches!(
+            Snapshot::from_bytes(&other_version),
+            Err(SnapshotError::VersionMismatch { found: 9 })
+        ));
+    }
+
+    #[test]
+    fn should_step_across_loops() {
+        let mut vm = VM::with_output(compile("{2}+[->]"), Box::new(sink()));
+        let mut visited = vec![];
+
+        while !vm.is_halted() {
+            visited.push((vm.ip(), vm.current_instruction().unwrap()));
+            vm.step().unwrap();
+        }
+
+        assert_eq!(
+            visited,
+            [
+                (0, OpCode::Constant),
+                (2, OpCode::DefineTape),
+                (3, OpCode::IncrementSingular),
+                (4, OpCode::JumpIfZero),
+                (7, OpCode::DecrementSingular),
+                (8, OpCode::ShiftRight),
+                (9, OpCode::Loop),
+                (4, OpCode::JumpIfZero),
+                (12, OpCode::Return),
+            ]
+        );
+        assert_eq!(vm.step().unwrap(), StepResult::Halted);
+        assert_eq!(vm.ip(), 13);
+    }
+
+    #[test]
+    fn should_reject_moving_past_fixed_tape() {
+        let mut vm = VM::with_output(compile("{2}>>>+3"), Box::new(sink()));
+
+        assert!(matches!(vm.run(), Err(Error::Runtime { .. })));
     }
 }